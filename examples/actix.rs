@@ -1,12 +1,12 @@
-use std::{sync::Arc, time::Duration};
+use std::time::Duration;
 
 use actix_web::{
     get,
     web::{self, Data},
-    App, HttpResponse, HttpServer, Responder,
+    App, HttpServer,
 };
-use async_channel::{Receiver, Sender};
-use s2a4c::router::Router;
+use s2a4c::channel::{Receiver, Sender};
+use s2a4c::{actix::EndpointResponder, router::Router};
 use uuid::Uuid;
 
 async fn sleep_ms(ms: u64) {
@@ -30,32 +30,29 @@ async fn worker(receiver: Receiver<(Uuid, String)>, sender: Sender<(Uuid, String
 async fn hello(
     router: Data<Router<String, String>>,
     request: web::Path<(u64, String)>,
-) -> impl Responder {
+) -> EndpointResponder<String> {
     let (timeout, name) = request.into_inner();
     let timeout = Duration::from_millis(timeout);
-    let response = router
+    // timeouts respond with 504 Gateway Timeout
+    router
         .endpoint(Some(timeout))
         .handle_request(format!(
             "\nHello {name}\nTimeOut Set to {}ms\n",
             timeout.as_millis()
         ))
-        .await;
-    match response {
-        Ok(response) => HttpResponse::Ok().body(response),
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
+        .await
+        .into()
 }
 
-#[tokio::main]
+#[actix_web::main]
 async fn main() -> Result<(), std::io::Error> {
     // instantiate a router
     let router = Router::default();
-    // tokio spawn workers
-    router.tokio_spawn_workers(4, worker);
-    // tokio spawn router loops
-    router.tokio_spawn();
+    // spawn the router loops and workers on actix's system, until the guard
+    // is dropped
+    let _guard = router.spawn_on_actix(4, worker);
     // create actix web data from router
-    let data = Data::from(Arc::new(router));
+    let data = router.into_app_data();
     // create actix web app
     HttpServer::new(move || App::new().app_data(Data::clone(&data)).service(hello))
         .bind(("0.0.0.0", 8080))
@@ -0,0 +1,322 @@
+//! # s2a4c-derive
+//!
+//! Derive macros of the [s2a4c](https://docs.rs/s2a4c) crate, re-exported by
+//! it behind the `derive` feature.
+//!
+//! ## Overview
+//!
+//! A single router often multiplexes many request types through one request
+//! enum and one response enum. Instead of matching and wrapping the variants
+//! by hand in every worker and caller, [RequestEnum] generates the glue from
+//! the request enum, see its documentation.
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Error, Fields, Ident, Type};
+
+/// Derives the dispatch of an enum of requests through a
+/// `Router<Request, Response>` whose responses are a matching enum.
+///
+/// Every variant of the request enum holds a single request type, and the
+/// response enum has a variant of the same name for each of them. The
+/// response enum is named by the `#[s2a4c(response = ...)]` attribute of the
+/// request enum, the response type of every variant by the same attribute
+/// of the variant:
+///
+/// ```rust,ignore
+/// #[derive(Clone, RequestEnum)]
+/// #[s2a4c(response = ApiResponse)]
+/// enum ApiRequest {
+///     #[s2a4c(response = User)]
+///     GetUser(GetUserReq),
+///     #[s2a4c(response = bool)]
+///     DeleteUser(DeleteUserReq),
+/// }
+///
+/// #[derive(Clone)]
+/// enum ApiResponse {
+///     GetUser(User),
+///     DeleteUser(bool),
+/// }
+/// ```
+///
+/// For an `ApiRequest` enum, the macro generates:
+///
+/// - `ApiRequestRoute`, an enum of the variants without their requests, and
+///   `ApiRequest::route` returning the route of a request, e.g. as the key of
+///   sticky routing or of key throttling.
+/// - `ApiRequestHandler`, a trait with one method handling the requests of
+///   each variant, e.g. `get_user`, and `ApiRequestWorker`, the worker
+///   handling the requests with a handler and wrapping its responses in the
+///   matching variant.
+/// - `ApiRequestRouter`, a trait implemented by `Router<ApiRequest,
+///   ApiResponse>` with a typed endpoint for each variant, e.g.
+///   `router.get_user_endpoint(None)` taking `GetUserReq` requests and
+///   responding with `User`s, and `spawn_handler_workers` spawning
+///   `ApiRequestWorker`s.
+#[proc_macro_derive(RequestEnum, attributes(s2a4c))]
+pub fn derive_request_enum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// A variant of the request enum.
+struct Variant {
+    /// name of the variant, shared with the response enum
+    ident: Ident,
+    /// type of the requests of the variant
+    request: Type,
+    /// type of the responses of the variant
+    response: Type,
+}
+
+/// Expands the derive of [RequestEnum] for `input`.
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "RequestEnum can only be derived for enums",
+        ));
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "RequestEnum can not be derived for generic enums",
+        ));
+    }
+    let response_enum = response_type(&input.attrs, &input.ident)?;
+    let variants = data
+        .variants
+        .iter()
+        .map(|variant| {
+            let request = match &variant.fields {
+                Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                    fields.unnamed[0].ty.clone()
+                }
+                _ => {
+                    return Err(Error::new_spanned(
+                        variant,
+                        "variants must hold a single request, e.g. `GetUser(GetUserReq)`",
+                    ))
+                }
+            };
+            Ok(Variant {
+                ident: variant.ident.clone(),
+                request,
+                response: response_type(&variant.attrs, &variant.ident)?,
+            })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let vis = &input.vis;
+    let request_enum = &input.ident;
+    let route = format_ident!("{}Route", request_enum);
+    let handler = format_ident!("{}Handler", request_enum);
+    let worker = format_ident!("{}Worker", request_enum);
+    let router = format_ident!("{}Router", request_enum);
+
+    let idents: Vec<_> = variants.iter().map(|variant| &variant.ident).collect();
+    let names: Vec<_> = idents.iter().map(|ident| ident.to_string()).collect();
+    let requests: Vec<_> = variants.iter().map(|variant| &variant.request).collect();
+    let responses: Vec<_> = variants.iter().map(|variant| &variant.response).collect();
+    let methods: Vec<_> = names
+        .iter()
+        .map(|name| Ident::new(&snake_case(name), Span::call_site()))
+        .collect();
+    let endpoints: Vec<_> = methods
+        .iter()
+        .map(|method| format_ident!("{}_endpoint", method))
+        .collect();
+    let count = variants.len();
+    let mismatches = names.iter().map(|name| {
+        format!("a `{name}` request was answered with another variant of the response enum")
+    });
+
+    let route_doc = format!("The variants of [{request_enum}], without their requests.");
+    let handler_doc =
+        format!("Handles the requests of every variant of [{request_enum}], see [{worker}].");
+    let worker_doc = format!(
+        "A worker handling [{request_enum}]s with a [{handler}], wrapping its responses in the \
+         matching variant of the response enum."
+    );
+    let router_doc = format!(
+        "Typed endpoints and workers of a router of [{request_enum}]s, one endpoint per variant."
+    );
+    let handler_method_docs = names
+        .iter()
+        .map(|name| format!("Handles a `{name}` request and produces its response."));
+    let endpoint_docs = names.iter().map(|name| {
+        format!(
+            "Creates an endpoint submitting `{name}` requests and returning their responses.\n\n\
+             # Panics\n\n\
+             The endpoint panics if a worker answers a `{name}` request with another variant of \
+             the response enum, which [{worker}]s never do."
+        )
+    });
+
+    Ok(quote! {
+        #[doc = #route_doc]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #vis enum #route {
+            #( #idents, )*
+        }
+
+        impl #route {
+            /// All routes, in the order of the variants.
+            #vis const ALL: [Self; #count] = [ #( Self::#idents, )* ];
+
+            /// Returns the name of the variant.
+            #vis fn name(self) -> &'static str {
+                match self {
+                    #( Self::#idents => #names, )*
+                }
+            }
+        }
+
+        impl #request_enum {
+            /// Returns the route of the request.
+            #vis fn route(&self) -> #route {
+                match self {
+                    #( Self::#idents(_) => #route::#idents, )*
+                }
+            }
+        }
+
+        #[doc = #handler_doc]
+        #vis trait #handler: ::core::marker::Send + ::core::marker::Sync + 'static {
+            #(
+                #[doc = #handler_method_docs]
+                fn #methods(
+                    &self,
+                    request: #requests,
+                ) -> impl ::core::future::Future<Output = #responses> + ::core::marker::Send;
+            )*
+        }
+
+        #[doc = #worker_doc]
+        #vis struct #worker<H>(#vis ::std::sync::Arc<H>);
+
+        impl<H> ::s2a4c::worker::Worker<#request_enum, #response_enum> for #worker<H>
+        where
+            H: #handler,
+        {
+            async fn handle(&self, request: #request_enum) -> #response_enum {
+                match request {
+                    #(
+                        #request_enum::#idents(request) => {
+                            #response_enum::#idents(self.0.#methods(request).await)
+                        }
+                    )*
+                }
+            }
+        }
+
+        #[doc = #router_doc]
+        #vis trait #router {
+            #(
+                #[doc = #endpoint_docs]
+                fn #endpoints(
+                    &self,
+                    timeout: impl ::core::convert::Into<::s2a4c::endpoint::Timeout>,
+                ) -> ::s2a4c::adapt::MappedEndpoint<#requests, #responses>;
+            )*
+
+            /// Spawns `num_workers` workers handling the requests with
+            /// `handler`, see [Router::spawn_worker_instances](::s2a4c::router::Router::spawn_worker_instances).
+            fn spawn_handler_workers<H>(
+                &self,
+                num_workers: usize,
+                handler: H,
+            ) -> ::std::vec::Vec<::s2a4c::__private::JoinHandle<()>>
+            where
+                H: #handler;
+        }
+
+        impl #router for ::s2a4c::router::Router<#request_enum, #response_enum> {
+            #(
+                fn #endpoints(
+                    &self,
+                    timeout: impl ::core::convert::Into<::s2a4c::endpoint::Timeout>,
+                ) -> ::s2a4c::adapt::MappedEndpoint<#requests, #responses> {
+                    self.endpoint(timeout)
+                        .map_request(#request_enum::#idents)
+                        .map_response(|response| match response {
+                            #response_enum::#idents(response) => response,
+                            #[allow(unreachable_patterns)]
+                            _ => ::core::panic!(#mismatches),
+                        })
+                }
+            )*
+
+            fn spawn_handler_workers<H>(
+                &self,
+                num_workers: usize,
+                handler: H,
+            ) -> ::std::vec::Vec<::s2a4c::__private::JoinHandle<()>>
+            where
+                H: #handler,
+            {
+                let handler = ::std::sync::Arc::new(handler);
+                self.spawn_worker_instances(num_workers, || #worker(handler.clone()))
+            }
+        }
+    })
+}
+
+/// Returns the type named by the `#[s2a4c(response = ...)]` attribute among
+/// `attrs` of the item `ident`.
+fn response_type(attrs: &[Attribute], ident: &Ident) -> syn::Result<Type> {
+    let mut response = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("s2a4c")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("response") {
+                response = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `response = ...`"))
+            }
+        })?;
+    }
+    response.ok_or_else(|| {
+        Error::new_spanned(
+            ident,
+            format!("`{ident}` is missing its `#[s2a4c(response = ...)]` attribute"),
+        )
+    })
+}
+
+/// Converts the name of a variant from `UpperCamelCase` to `snake_case`,
+/// e.g. `GetUser` to `get_user` and `HTTPRequest` to `http_request`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_is_lower)
+            {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use super::snake_case;
+
+    #[test]
+    fn test_snake_case() {
+        assert_eq!(snake_case("GetUser"), "get_user");
+        assert_eq!(snake_case("HTTPRequest"), "http_request");
+        assert_eq!(snake_case("Ping"), "ping");
+        assert_eq!(snake_case("V2Lookup"), "v2_lookup");
+    }
+}
@@ -0,0 +1,216 @@
+//! # Actix Module
+//!
+//! This module provides helpers for serving a [Router] from an
+//! [actix-web](https://docs.rs/actix-web) application, enabled with the
+//! `actix` feature.
+//!
+//! ## Overview
+//!
+//! - [Router::into_app_data] wraps a [Router] into actix application data,
+//!   handlers extract it with `Data<Router<Request, Response>>`.
+//! - [EndpointError] implements [ResponseError], mapping
+//!   [EndpointError::Timeout] to `504 Gateway Timeout`,
+//!   [EndpointError::Overloaded], [EndpointError::TooManyInFlight],
+//!   [EndpointError::NoWorkers], [EndpointError::Paused] and
+//!   [EndpointError::RequestSend] to `503 Service Unavailable`,
+//!   [EndpointError::Throttled] and
+//!   [EndpointError::ConcurrencyLimit] to `429 Too Many Requests`, [EndpointError::Invalid] to
+//!   `400 Bad Request`, [EndpointError::ResponseReceive] and
+//!   [EndpointError::WorkerPanicked] to `500 Internal Server Error` and [EndpointError::Rejected] to
+//!   `422 Unprocessable Entity`, so handlers can return the error with `?`.
+//! - [EndpointResponder] responds with the response of an endpoint, or with
+//!   the status code of its error.
+//! - [Router::spawn_on_actix] spawns the router loops and workers on actix's
+//!   system, returning an [ActixRouterGuard] shutting the router down once
+//!   dropped.
+use std::{convert::Infallible, fmt, future::Future};
+
+use crate::channel::{Receiver, Sender};
+use actix_web::{
+    body::BoxBody, http::StatusCode, rt, web::Data, HttpRequest, HttpResponse, Responder,
+    ResponseError,
+};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{endpoint::EndpointError, router::Router};
+
+impl<E> ResponseError for EndpointError<E>
+where
+    E: fmt::Debug + fmt::Display,
+{
+    fn status_code(&self) -> StatusCode {
+        match self {
+            EndpointError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            EndpointError::Overloaded
+            | EndpointError::TooManyInFlight
+            | EndpointError::NoWorkers
+            | EndpointError::Paused
+            | EndpointError::RequestSend => StatusCode::SERVICE_UNAVAILABLE,
+            EndpointError::Throttled | EndpointError::ConcurrencyLimit => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            EndpointError::Invalid(_) => StatusCode::BAD_REQUEST,
+            EndpointError::ResponseReceive(_) | EndpointError::WorkerPanicked => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            EndpointError::Rejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+/// Responds with the response of an [Endpoint](crate::endpoint::Endpoint),
+/// or with the status code of its [EndpointError].
+///
+/// # Type Parameters
+/// - `Response`: the response type of the endpoint
+/// - `E`: the rejection type of the endpoint
+#[derive(Debug)]
+pub struct EndpointResponder<Response, E = Infallible>(pub Result<Response, EndpointError<E>>);
+
+impl<Response, E> From<Result<Response, EndpointError<E>>> for EndpointResponder<Response, E> {
+    fn from(result: Result<Response, EndpointError<E>>) -> Self {
+        Self(result)
+    }
+}
+
+impl<Response, E> Responder for EndpointResponder<Response, E>
+where
+    Response: Responder,
+    E: fmt::Debug + fmt::Display,
+{
+    type Body = BoxBody;
+
+    fn respond_to(self, request: &HttpRequest) -> HttpResponse<Self::Body> {
+        match self.0 {
+            Ok(response) => response.respond_to(request).map_into_boxed_body(),
+            Err(err) => err.error_response(),
+        }
+    }
+}
+
+/// Keeps a router spawned by [Router::spawn_on_actix] running, shutting the
+/// router down and aborting its workers once dropped.
+#[derive(Debug)]
+pub struct ActixRouterGuard<Request, Response> {
+    /// the spawned router
+    router: Router<Request, Response>,
+    /// stops the router loops once cancelled
+    shutdown_token: CancellationToken,
+    /// handles of the worker tasks
+    workers: Vec<rt::task::JoinHandle<()>>,
+}
+
+impl<Request, Response> ActixRouterGuard<Request, Response> {
+    /// Returns the spawned router.
+    pub fn router(&self) -> &Router<Request, Response> {
+        &self.router
+    }
+}
+
+impl<Request, Response> Drop for ActixRouterGuard<Request, Response> {
+    fn drop(&mut self) {
+        self.shutdown_token.cancel();
+        for worker in &self.workers {
+            worker.abort();
+        }
+    }
+}
+
+impl<Request, Response> Router<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    /// Wraps the router into actix application data.
+    pub fn into_app_data(self) -> Data<Self> {
+        Data::new(self)
+    }
+    /// Spawns the router loops and `num_workers` workers running `worker_fn`
+    /// on the actix system of the current thread.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [ActixRouterGuard] keeping the router running until it is
+    /// dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of an actix system.
+    pub fn spawn_on_actix<F>(
+        &self,
+        num_workers: usize,
+        worker_fn: impl Fn(Receiver<(Uuid, Request)>, Sender<(Uuid, Response)>) -> F,
+    ) -> ActixRouterGuard<Request, Response>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let workers = self
+            .worker_futures(num_workers, worker_fn)
+            .into_iter()
+            .map(rt::spawn)
+            .collect();
+        // the router loops stop on their own once the shutdown token is
+        // cancelled, closing the registration channel
+        let router = self.clone();
+        rt::spawn(async move { router.run().await });
+        ActixRouterGuard {
+            router: self.clone(),
+            shutdown_token: self.shutdown_token(),
+            workers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EndpointResponder;
+    use crate::channel::{Receiver, Sender};
+    use crate::router::Router;
+    use actix_web::{http::StatusCode, test, web, App};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    async fn sleep_worker(receiver: Receiver<(Uuid, u64)>, sender: Sender<(Uuid, String)>) {
+        while let Ok((uuid, millis)) = receiver.recv().await {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            sender.send((uuid, millis.to_string())).await.unwrap();
+        }
+    }
+
+    async fn handle(
+        router: web::Data<Router<u64, String>>,
+        millis: web::Path<u64>,
+    ) -> EndpointResponder<String> {
+        router
+            .endpoint(Duration::from_millis(50))
+            .handle_request(millis.into_inner())
+            .await
+            .into()
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_responder_status_codes() {
+        let router: Router<u64, String> = Router::default();
+        let guard = router.spawn_on_actix(1, sleep_worker);
+        let app = test::init_service(
+            App::new()
+                .app_data(router.clone().into_app_data())
+                .route("/{millis}", web::get().to(handle)),
+        )
+        .await;
+
+        let request = test::TestRequest::get().uri("/1").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let request = test::TestRequest::get().uri("/100").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        drop(guard);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let request = test::TestRequest::get().uri("/1").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,209 @@
+//! # Adapt Module
+//!
+//! This module provides the [MappedEndpoint] struct exposing an
+//! [Endpoint] over other request and response types, see
+//! [Endpoint::map_request], [Endpoint::map_response] and
+//! [Router::adapt](crate::router::Router::adapt).
+//!
+//! ## Overview
+//!
+//! A single router handling internal messages often serves several callers,
+//! each with its own domain types. Instead of wrapping the endpoint in a
+//! struct converting the types for every caller, a [MappedEndpoint] maps the
+//! requests of the caller before submitting them and the responses of the
+//! workers before returning them. Mappings can be chained, and a
+//! [MappedEndpoint] is cheap to clone.
+use std::{fmt, sync::Arc};
+
+use futures::{future::BoxFuture, FutureExt};
+
+use crate::{
+    context::TraceContext,
+    endpoint::{Endpoint, EndpointError},
+};
+
+/// Submits a request through the underlying endpoint, mapping the request
+/// and its response.
+type Submit<Request, Response> = Arc<
+    dyn Fn(Request, TraceContext) -> BoxFuture<'static, Result<Response, EndpointError>>
+        + Send
+        + Sync,
+>;
+
+/// An [Endpoint] over other request and response types, mapping the requests
+/// before submitting them and the responses before returning them.
+///
+/// # Type Parameters
+/// - `Request`: the request type of the callers
+/// - `Response`: the response type returned to the callers
+pub struct MappedEndpoint<Request, Response> {
+    /// submits requests through the underlying endpoint
+    submit: Submit<Request, Response>,
+}
+
+// implemented by hand, deriving would require `Request` and `Response` to
+// implement the traits as well.
+impl<Request, Response> Clone for MappedEndpoint<Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            submit: self.submit.clone(),
+        }
+    }
+}
+
+impl<Request, Response> fmt::Debug for MappedEndpoint<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedEndpoint").finish_non_exhaustive()
+    }
+}
+
+impl<Request, Response> MappedEndpoint<Request, Response>
+where
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    /// Creates a new `MappedEndpoint` submitting requests through `endpoint`
+    /// as they are.
+    pub fn new(endpoint: Endpoint<Request, Response>) -> Self {
+        Self {
+            submit: Arc::new(move |request, context| {
+                let endpoint = endpoint.clone();
+                Box::pin(
+                    async move { endpoint.handle_request_with_context(request, context).await },
+                )
+            }),
+        }
+    }
+    /// Maps the requests of the callers with `f` before submitting them.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `MappedEndpoint` taking `NewRequest` requests.
+    pub fn map_request<NewRequest>(
+        self,
+        f: impl Fn(NewRequest) -> Request + Send + Sync + 'static,
+    ) -> MappedEndpoint<NewRequest, Response> {
+        let submit = self.submit;
+        MappedEndpoint {
+            submit: Arc::new(move |request, context| submit(f(request), context)),
+        }
+    }
+    /// Maps the responses of the workers with `f` before returning them.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `MappedEndpoint` responding with `NewResponse` responses.
+    pub fn map_response<NewResponse>(
+        self,
+        f: impl Fn(Response) -> NewResponse + Send + Sync + 'static,
+    ) -> MappedEndpoint<Request, NewResponse>
+    where
+        NewResponse: Send + 'static,
+    {
+        let submit = self.submit;
+        let f = Arc::new(f);
+        MappedEndpoint {
+            submit: Arc::new(move |request, context| {
+                let f = f.clone();
+                submit(request, context)
+                    .map(move |response| response.map(|response| f(response)))
+                    .boxed()
+            }),
+        }
+    }
+    /// Maps `request`, submits it and returns its mapped response, like
+    /// [Endpoint::handle_request].
+    pub async fn handle_request(&self, request: Request) -> Result<Response, EndpointError> {
+        self.handle_request_with_context(request, TraceContext::captured())
+            .await
+    }
+    /// Handles a request like [MappedEndpoint::handle_request], propagating
+    /// the given [TraceContext] to the worker instead of the captured one.
+    pub async fn handle_request_with_context(
+        &self,
+        request: Request,
+        context: TraceContext,
+    ) -> Result<Response, EndpointError> {
+        (self.submit)(request, context).await
+    }
+    /// Handles a request like [MappedEndpoint::handle_request], for mapped
+    /// responses converting into a `Result`, like
+    /// [Endpoint::try_handle_request].
+    pub async fn try_handle_request<T, E>(&self, request: Request) -> Result<T, EndpointError<E>>
+    where
+        Response: Into<Result<T, E>>,
+    {
+        match self.handle_request(request).await {
+            Ok(response) => response.into().map_err(EndpointError::Rejected),
+            Err(err) => Err(err.widen()),
+        }
+    }
+}
+
+impl<Request, Response> Endpoint<Request, Response>
+where
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    /// Maps the requests of the callers with `f` before submitting them, see
+    /// [MappedEndpoint::map_request].
+    pub fn map_request<NewRequest>(
+        self,
+        f: impl Fn(NewRequest) -> Request + Send + Sync + 'static,
+    ) -> MappedEndpoint<NewRequest, Response> {
+        MappedEndpoint::new(self).map_request(f)
+    }
+    /// Maps the responses of the workers with `f` before returning them, see
+    /// [MappedEndpoint::map_response].
+    pub fn map_response<NewResponse>(
+        self,
+        f: impl Fn(Response) -> NewResponse + Send + Sync + 'static,
+    ) -> MappedEndpoint<Request, NewResponse>
+    where
+        NewResponse: Send + 'static,
+    {
+        MappedEndpoint::new(self).map_response(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{endpoint::EndpointError, router::Router};
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Internal {
+        Add(i64, i64),
+    }
+
+    struct Sum(i64, i64);
+
+    impl From<Sum> for Internal {
+        fn from(Sum(a, b): Sum) -> Self {
+            Internal::Add(a, b)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mapped_endpoints() {
+        let router: Router<Internal, i64> = Router::default();
+        router.spawn_workers_fn(1, |Internal::Add(a, b)| async move { a + b });
+        router.tokio_spawn();
+
+        let endpoint = router
+            .endpoint(None)
+            .map_request(|(a, b): (i64, i64)| Internal::Add(a, b))
+            .map_response(|sum| sum.to_string());
+        assert_eq!(endpoint.handle_request((1, 2)).await, Ok("3".to_string()));
+
+        // chained mappings apply in turn
+        let endpoint = router
+            .adapt::<Sum, i128>(None)
+            .map_request(|a: i64| Sum(a, a))
+            .map_response(|sum| u64::try_from(sum).map_err(|_| sum));
+        assert_eq!(endpoint.try_handle_request(21).await, Ok(42));
+        assert_eq!(
+            endpoint.try_handle_request(-1).await,
+            Err(EndpointError::Rejected(-2))
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! # Axum Module
+//!
+//! This module provides helpers for serving a [Router] from an
+//! [axum](https://docs.rs/axum) application, enabled with the `axum` feature.
+//!
+//! ## Overview
+//!
+//! - [RouterState] wraps a [Router] as the state of an axum application,
+//!   handlers extract the [Router] or an [Endpoint] from it with axum's
+//!   [State](::axum::extract::State) extractor.
+//! - [endpoint_route] turns an [Endpoint] into a `POST` route handling JSON
+//!   encoded requests and responses.
+//! - [EndpointError] implements [IntoResponse], mapping
+//!   [EndpointError::Timeout] to `504 Gateway Timeout`,
+//!   [EndpointError::Overloaded], [EndpointError::TooManyInFlight],
+//!   [EndpointError::NoWorkers], [EndpointError::Paused] and
+//!   [EndpointError::RequestSend] to `503 Service Unavailable`,
+//!   [EndpointError::Throttled] and
+//!   [EndpointError::ConcurrencyLimit] to `429 Too Many Requests`, [EndpointError::Invalid] to
+//!   `400 Bad Request`, and [EndpointError::ResponseReceive] and
+//!   [EndpointError::WorkerPanicked] to `500 Internal Server Error`. Rejected requests respond with the
+//!   rejection.
+use ::axum::{
+    extract::FromRef,
+    http::StatusCode,
+    response::{IntoResponse, Response as HttpResponse},
+    routing::{post, MethodRouter},
+    Json,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    endpoint::{Endpoint, EndpointError},
+    router::Router,
+};
+
+/// A [Router] used as the state of an axum application.
+///
+/// Handlers extract the router with `State<Router<Request, Response>>`, or
+/// an endpoint using the router's default timeout with
+/// `State<Endpoint<Request, Response>>`.
+#[derive(Debug, Clone)]
+pub struct RouterState<Request, Response>(pub Router<Request, Response>);
+
+impl<Request, Response> FromRef<RouterState<Request, Response>> for Router<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    fn from_ref(state: &RouterState<Request, Response>) -> Self {
+        state.0.clone()
+    }
+}
+
+impl<Request, Response> FromRef<RouterState<Request, Response>> for Endpoint<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    fn from_ref(state: &RouterState<Request, Response>) -> Self {
+        state.0.endpoint(None)
+    }
+}
+
+impl<E> IntoResponse for EndpointError<E>
+where
+    E: IntoResponse,
+{
+    fn into_response(self) -> HttpResponse {
+        let status = match self {
+            EndpointError::Rejected(rejection) => return rejection.into_response(),
+            EndpointError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            EndpointError::Overloaded
+            | EndpointError::TooManyInFlight
+            | EndpointError::NoWorkers
+            | EndpointError::Paused
+            | EndpointError::RequestSend => StatusCode::SERVICE_UNAVAILABLE,
+            EndpointError::Throttled | EndpointError::ConcurrencyLimit => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            EndpointError::Invalid(_) => StatusCode::BAD_REQUEST,
+            EndpointError::ResponseReceive(_) | EndpointError::WorkerPanicked => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        status.into_response()
+    }
+}
+
+/// Creates a `POST` route handling requests with `endpoint`.
+///
+/// # Arguments
+///
+/// - `endpoint`: The [Endpoint] handling the requests.
+///
+/// # Returns
+///
+/// Returns a route deserializing the JSON body of every request into a
+/// `Request`, and responding with the JSON encoded `Response`, or with the
+/// status code of the [EndpointError].
+pub fn endpoint_route<S, Request, Response>(
+    endpoint: Endpoint<Request, Response>,
+) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+    Request: DeserializeOwned + Send + 'static,
+    Response: Serialize + Send + 'static,
+{
+    post(move |Json(request): Json<Request>| async move {
+        endpoint.handle_request(request).await.map(Json)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{endpoint_route, RouterState};
+    use crate::{endpoint::Endpoint, router::Router};
+    use ::axum::{
+        body::Body,
+        extract::State,
+        http::{Request, StatusCode},
+        routing::get,
+    };
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    fn post(uri: &str, body: &str) -> Request<Body> {
+        Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_route_status_codes() {
+        let router: Router<u64, u64> = Router::default();
+        router.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, millis)) = receiver.recv().await {
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                sender.send((uuid, millis)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        let app = ::axum::Router::new()
+            .route(
+                "/",
+                endpoint_route(router.endpoint(Duration::from_millis(50))),
+            )
+            .route(
+                "/state",
+                get(|State(endpoint): State<Endpoint<u64, u64>>| async move {
+                    endpoint.handle_request(0).await.unwrap().to_string()
+                }),
+            )
+            .with_state(RouterState(router));
+
+        let response = app.clone().oneshot(post("/", "1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(post("/", "100")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let request = Request::get("/state").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,335 @@
+//! # Builder Module
+//!
+//! This module provides the [RouterBuilder] struct for configuring and
+//! creating a [Router] with named setters, and the [RouterTasks] struct
+//! holding the handles of the tasks spawned by
+//! [RouterBuilder::build_and_spawn].
+use std::{future::Future, sync::Arc, time::Duration};
+
+use crate::channel::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    dispatch::DispatchEngine,
+    id::IdGenerator,
+    response_map::DEFAULT_SHARDS,
+    router::Router,
+    spawn::{Spawn, TokioSpawn},
+};
+
+/// Builder for the [Router] struct.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use s2a4c::{builder::RouterBuilder, router::Router};
+///
+/// let router: Router<String, String> = RouterBuilder::new()
+///     .request_channel_size(Some(1_000))
+///     .default_timeout(Duration::from_secs(1))
+///     .metrics(true)
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct RouterBuilder {
+    /// capacity of the registration channel, unbounded if `None`
+    pub(crate) registration_channel_size: Option<usize>,
+    /// capacity of the request channel, unbounded if `None`
+    pub(crate) request_channel_size: Option<usize>,
+    /// capacity of the response channel, unbounded if `None`
+    pub(crate) response_channel_size: Option<usize>,
+    /// timeout used by endpoints created without an explicit one
+    pub(crate) default_timeout: Option<Duration>,
+    /// number of workers spawned by [RouterBuilder::build_and_spawn]
+    pub(crate) workers: usize,
+    /// whether the router exposes its metrics counters
+    pub(crate) metrics: bool,
+    /// generator of the request identifiers
+    pub(crate) id_generator: IdGenerator,
+    /// token stopping the router loops once cancelled
+    pub(crate) shutdown_token: CancellationToken,
+    /// number of queued requests at which endpoints reject new requests
+    pub(crate) high_watermark: Option<usize>,
+    /// maximum number of in-flight requests
+    pub(crate) max_in_flight: Option<usize>,
+    /// spawns the tasks of the router
+    pub(crate) spawner: Arc<dyn Spawn>,
+    /// time from registration to response after which requests are reported
+    /// as slow
+    pub(crate) slow_request_threshold: Option<Duration>,
+    /// prefix of the names of the tasks spawned on the tokio runtime
+    pub(crate) task_name_prefix: Arc<str>,
+    /// number of shards of the map of pending requests
+    pub(crate) response_map_shards: usize,
+    /// how requests are handed to the workers
+    pub(crate) dispatch_engine: DispatchEngine,
+}
+
+impl Default for RouterBuilder {
+    fn default() -> Self {
+        Self {
+            registration_channel_size: Some(100),
+            request_channel_size: Some(100),
+            response_channel_size: Some(100),
+            default_timeout: None,
+            workers: 1,
+            metrics: false,
+            id_generator: IdGenerator::default(),
+            shutdown_token: CancellationToken::new(),
+            high_watermark: None,
+            max_in_flight: None,
+            spawner: Arc::new(TokioSpawn),
+            slow_request_threshold: None,
+            task_name_prefix: Arc::from("s2a4c"),
+            response_map_shards: DEFAULT_SHARDS,
+            dispatch_engine: DispatchEngine::Shared,
+        }
+    }
+}
+
+/// Handles of the tasks spawned by [RouterBuilder::build_and_spawn].
+#[derive(Debug)]
+pub struct RouterTasks {
+    /// handle of the router loops task
+    pub router: tokio::task::JoinHandle<()>,
+    /// handles of the worker tasks
+    pub workers: Vec<tokio::task::JoinHandle<()>>,
+}
+
+impl RouterBuilder {
+    /// Creates a new `RouterBuilder` with the same configuration as
+    /// [Router::default].
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the capacity of the registration channel, `None` for an unbounded
+    /// channel.
+    pub fn registration_channel_size(mut self, size: Option<usize>) -> Self {
+        self.registration_channel_size = size;
+        self
+    }
+    /// Sets the capacity of the request channel, `None` for an unbounded
+    /// channel.
+    pub fn request_channel_size(mut self, size: Option<usize>) -> Self {
+        self.request_channel_size = size;
+        self
+    }
+    /// Sets the capacity of the response channel, `None` for an unbounded
+    /// channel.
+    pub fn response_channel_size(mut self, size: Option<usize>) -> Self {
+        self.response_channel_size = size;
+        self
+    }
+    /// Sets the timeout of endpoints created with
+    /// [Timeout::Default](crate::endpoint::Timeout::Default).
+    pub fn default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+        self
+    }
+    /// Sets the number of workers spawned by [RouterBuilder::build_and_spawn].
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+    /// Enables or disables exposing the metrics counters of the router through
+    /// [Router::metrics].
+    pub fn metrics(mut self, enabled: bool) -> Self {
+        self.metrics = enabled;
+        self
+    }
+    /// Sets the generator of the request identifiers.
+    pub fn id_generator(mut self, id_generator: IdGenerator) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
+    /// Sets the token stopping the router loops once cancelled.
+    pub fn shutdown_token(mut self, token: CancellationToken) -> Self {
+        self.shutdown_token = token;
+        self
+    }
+    /// Sets the number of requests queued in the registration and request
+    /// channels at which endpoints reject new requests with
+    /// [EndpointError::Overloaded](crate::endpoint::EndpointError::Overloaded)
+    /// instead of queueing work that will likely time out.
+    pub fn high_watermark(mut self, high_watermark: usize) -> Self {
+        self.high_watermark = Some(high_watermark);
+        self
+    }
+    /// Sets the maximum number of in-flight requests, registered with the
+    /// router and still waiting for their response. Once reached, endpoints
+    /// reject new requests with
+    /// [EndpointError::TooManyInFlight](crate::endpoint::EndpointError::TooManyInFlight),
+    /// bounding the memory held by pending requests when workers fall behind.
+    ///
+    /// Requests whose endpoint timed out stay in flight until their response
+    /// arrives or their worker abandons them.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = Some(max_in_flight);
+        self
+    }
+    /// Sets the [Spawn] implementation spawning the tasks of the router, see
+    /// [Router::spawn] and [Router::spawn_workers]. Defaults to [TokioSpawn].
+    ///
+    /// The timers of the router, e.g. the timeouts of its endpoints, still
+    /// require a tokio runtime, see the [spawn](crate::spawn) module.
+    pub fn spawner(mut self, spawner: impl Spawn + 'static) -> Self {
+        self.spawner = Arc::new(spawner);
+        self
+    }
+    /// Sets the time from registration to response after which requests are
+    /// reported as slow: as a `tracing` warning with the `tracing` feature,
+    /// and to the
+    /// [on_slow_request](crate::hooks::RouterHooks::on_slow_request) hook.
+    ///
+    /// Reports include the number of requests that were waiting for a worker
+    /// when the slow request was registered, telling whether the request was
+    /// slowed down by queueing or by its processing.
+    pub fn slow_request_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_request_threshold = Some(threshold);
+        self
+    }
+    /// Sets the prefix of the names of the tasks spawned on the tokio runtime
+    /// by [Router::tokio_spawn] and [Router::tokio_spawn_workers], e.g.
+    /// `s2a4c-registration-loop` or `s2a4c-worker-3`. Defaults to `s2a4c`.
+    ///
+    /// Tasks are only named when compiled with `--cfg tokio_unstable` and the
+    /// `tracing` feature, so tools like tokio-console show them by name.
+    pub fn task_name_prefix(mut self, prefix: impl Into<Arc<str>>) -> Self {
+        self.task_name_prefix = prefix.into();
+        self
+    }
+    /// Sets the number of shards the map of pending requests is split into,
+    /// picked by the low bits of the request UUIDs. Defaults to 16.
+    ///
+    /// More shards reduce the contention between the router loops and the
+    /// endpoints under high throughput, at the cost of summing up all shards
+    /// for [Router::stats].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    pub fn response_map_shards(mut self, shards: usize) -> Self {
+        assert!(shards > 0, "the response map needs at least one shard");
+        self.response_map_shards = shards;
+        self
+    }
+    /// Sets how the router hands the requests to its workers. Defaults to
+    /// [DispatchEngine::Shared], a single request channel shared by all
+    /// workers.
+    ///
+    /// The other engines give each of the configured number of
+    /// [workers](RouterBuilder::workers) its own request channel, see
+    /// [Router::with_dispatch_strategy] and [Router::with_work_stealing].
+    ///
+    /// # Panics
+    ///
+    /// Building a router panics if the number of workers is zero and the
+    /// engine is not [DispatchEngine::Shared].
+    pub fn dispatch_engine(mut self, engine: DispatchEngine) -> Self {
+        self.dispatch_engine = engine;
+        self
+    }
+    /// Creates the configured [Router].
+    pub fn build<Request, Response>(self) -> Router<Request, Response>
+    where
+        Request: Send + 'static + Clone,
+        Response: Send + 'static + Clone,
+    {
+        Router::from_builder(self)
+    }
+    /// Creates the configured [Router], spawns its loops and the configured
+    /// number of workers running `worker_fn`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the [Router] and the [RouterTasks] holding the handles of the
+    /// spawned tasks.
+    pub fn build_and_spawn<Request, Response, F>(
+        self,
+        worker_fn: impl Fn(Receiver<(Uuid, Request)>, Sender<(Uuid, Response)>) -> F,
+    ) -> (Router<Request, Response>, RouterTasks)
+    where
+        Request: Send + 'static + Clone,
+        Response: Send + 'static + Clone,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let workers = self.workers;
+        let router = self.build();
+        let tasks = RouterTasks {
+            router: router.tokio_spawn(),
+            workers: router.tokio_spawn_workers(workers, worker_fn),
+        };
+        (router, tasks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RouterBuilder;
+    use crate::channel::{Receiver, Sender};
+    use crate::{dispatch::DispatchEngine, endpoint::EndpointError, router::Router};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    async fn echo(receiver: Receiver<(Uuid, String)>, sender: Sender<(Uuid, String)>) {
+        while let Ok((uuid, request)) = receiver.recv().await {
+            sender.send((uuid, request)).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_and_spawn() {
+        let (router, tasks): (Router<String, String>, _) = RouterBuilder::new()
+            .workers(3)
+            .metrics(true)
+            .default_timeout(Duration::from_millis(100))
+            .build_and_spawn(echo);
+        assert_eq!(tasks.workers.len(), 3);
+
+        let response = router.endpoint(None).handle_request("ping".into()).await;
+        assert_eq!(response, Ok("ping".to_string()));
+        assert_eq!(router.metrics().unwrap().registered, 1);
+
+        router.shutdown();
+        tasks.router.await.unwrap();
+        let response = router.endpoint(None).handle_request("ping".into()).await;
+        assert_eq!(response, Err(EndpointError::RequestSend));
+    }
+    #[tokio::test]
+    async fn test_dispatch_engines() {
+        for engine in [
+            DispatchEngine::Shared,
+            DispatchEngine::LeastLoaded,
+            DispatchEngine::WorkStealing,
+        ] {
+            let (router, _tasks): (Router<String, String>, _) = RouterBuilder::new()
+                .workers(3)
+                .dispatch_engine(engine)
+                .build_and_spawn(echo);
+            let endpoint = router.endpoint(Duration::from_millis(100));
+            for _ in 0..6 {
+                let response = endpoint.handle_request("ping".into()).await;
+                assert_eq!(response, Ok("ping".to_string()), "{engine:?}");
+            }
+        }
+    }
+    #[tokio::test]
+    async fn test_task_names() {
+        let router: Router<String, String> =
+            RouterBuilder::new().task_name_prefix("billing").build();
+        assert_eq!(router.task_name("worker-3"), "billing-worker-3");
+        router.tokio_spawn_workers(1, echo);
+        let handle = router.tokio_spawn();
+
+        let endpoint = router.endpoint(Duration::from_millis(50));
+        let response = endpoint.handle_request("ping".into()).await;
+        assert_eq!(response, Ok("ping".to_string()));
+        // aborting the supervising task aborts the loops of the router
+        handle.abort();
+        assert!(handle.await.unwrap_err().is_cancelled());
+        let response = endpoint.handle_request("ping".into()).await;
+        assert!(matches!(response, Err(EndpointError::Timeout(_))));
+    }
+}
@@ -0,0 +1,81 @@
+//! # Bytes Module
+//!
+//! This module provides helpers for routers exchanging raw payloads as
+//! [Bytes], enabled with the `bytes` feature.
+//!
+//! ## Overview
+//!
+//! Cloning a [Bytes] only increments a reference count, so every copy the
+//! router makes of a request or response, for [Endpoint::scatter], a
+//! [Recorder](crate::recorder::Recorder) or a
+//! [ResponseCache](crate::cache::ResponseCache), shares the payload instead
+//! of copying it. A [BytesRouter] moves the payloads from the endpoints
+//! through the workers back to the endpoints by reference-counted slices,
+//! and [BytesEndpoint::handle_payload] submits owned buffers without copying
+//! them.
+//!
+//! With the `nats` feature, `Router::from_nats_bytes` and
+//! `Router::tokio_spawn_nats_service_bytes` additionally pass the NATS
+//! payloads through as they are, instead of encoding them with a
+//! [Codec](crate::codec::Codec).
+pub use ::bytes::Bytes;
+
+use crate::{
+    endpoint::{Endpoint, EndpointError},
+    router::Router,
+};
+
+/// A [Router] exchanging [Bytes] requests and responses.
+pub type BytesRouter = Router<Bytes, Bytes>;
+
+/// An [Endpoint] of a [BytesRouter].
+pub type BytesEndpoint = Endpoint<Bytes, Bytes>;
+
+impl<Response> Endpoint<Bytes, Response>
+where
+    Response: Send + 'static,
+{
+    /// Handles `payload` like [Endpoint::handle_request], converted into
+    /// [Bytes].
+    ///
+    /// `Vec<u8>`, `String`, `Box<[u8]>` and `&'static [u8]` payloads are
+    /// converted without copying them.
+    pub async fn handle_payload(
+        &self,
+        payload: impl Into<Bytes>,
+    ) -> Result<Response, EndpointError> {
+        self.handle_request(payload.into()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bytes, BytesRouter};
+
+    #[tokio::test]
+    async fn test_payloads_are_shared() {
+        let router = BytesRouter::default();
+        router.tokio_spawn_workers(3, |receiver, sender| async move {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                // responds with a slice of the request
+                let response: Bytes = request.slice(1..);
+                sender.send((uuid, response)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        let payload = vec![0u8; 64 * 1024];
+        let address = payload.as_ptr();
+        let endpoint = router.endpoint(None);
+        let response = endpoint.handle_payload(payload).await.unwrap();
+        assert_eq!(response.len(), 64 * 1024 - 1);
+        assert_eq!(response.as_ptr(), address.wrapping_add(1));
+
+        // every scattered copy shares the payload of the request
+        let request = Bytes::from(vec![1u8; 1024]);
+        let responses = endpoint.scatter(request.clone(), 3).await.unwrap();
+        assert!(responses
+            .iter()
+            .all(|response| response.as_ptr() == request.as_ptr().wrapping_add(1)));
+    }
+}
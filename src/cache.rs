@@ -0,0 +1,170 @@
+//! # Cache Module
+//!
+//! This module provides the [ResponseCache] struct, an opt-in LRU cache with
+//! a time to live placed in front of the worker dispatch of a
+//! [Router](crate::router::Router).
+//!
+//! ## Overview
+//!
+//! Requests are mapped to cache keys with a user supplied function. When a
+//! fresh response is cached for the key of a new request, the router answers
+//! immediately without dispatching the request to the workers. Otherwise the
+//! request is dispatched as usual and its response is cached by the router's
+//! response loop, so all endpoints of the router benefit from it.
+use std::{
+    any::Any,
+    fmt,
+    hash::Hash,
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+
+/// Cache key of a request, with its type erased so the router doesn't need
+/// to know it.
+pub(crate) type CacheKey = Box<dyn Any + Send + Sync>;
+
+/// Type erased interface of a [ResponseCache] used by the router loops.
+pub(crate) trait Cache<Request, Response>: fmt::Debug + Send + Sync {
+    /// Returns the cache key of `request`.
+    fn key(&self, request: &Request) -> CacheKey;
+    /// Returns the fresh cached response for `key`, if any.
+    fn get(&self, key: &CacheKey) -> Option<Response>;
+    /// Caches `response` for `key`.
+    fn insert(&self, key: CacheKey, response: Response);
+}
+
+/// An LRU cache of responses, expiring entries after a time to live.
+///
+/// # Type Parameters
+/// - `Key`: the cache key derived from a request
+/// - `Request`: the request type of the router
+/// - `Response`: the response type of the router
+pub struct ResponseCache<Key, Request, Response> {
+    /// cached responses along with the instant they were cached at
+    entries: Mutex<LruCache<Key, (Instant, Response)>>,
+    /// time after which cached responses are stale
+    ttl: Duration,
+    /// maps requests to their cache key
+    key_fn: Box<dyn Fn(&Request) -> Key + Send + Sync>,
+}
+
+impl<Key, Request, Response> ResponseCache<Key, Request, Response>
+where
+    Key: Hash + Eq,
+{
+    /// Creates a new, empty `ResponseCache`.
+    ///
+    /// # Arguments
+    ///
+    /// - `capacity`: The maximum number of cached responses, the least
+    ///   recently used response is evicted once it is reached.
+    /// - `ttl`: The [Duration] after which a cached response is stale.
+    /// - `key_fn`: A function mapping requests to their cache key, requests
+    ///   with equal keys are answered with the same response.
+    pub fn new(
+        capacity: NonZeroUsize,
+        ttl: Duration,
+        key_fn: impl Fn(&Request) -> Key + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+            ttl,
+            key_fn: Box::new(key_fn),
+        }
+    }
+}
+
+impl<Key, Request, Response> fmt::Debug for ResponseCache<Key, Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Key, Request, Response> Cache<Request, Response> for ResponseCache<Key, Request, Response>
+where
+    Key: Hash + Eq + Send + Sync + 'static,
+    Response: Clone + Send,
+{
+    fn key(&self, request: &Request) -> CacheKey {
+        Box::new((self.key_fn)(request))
+    }
+    fn get(&self, key: &CacheKey) -> Option<Response> {
+        let key = key.downcast_ref::<Key>()?;
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        match entries.get(key) {
+            Some((cached_at, response)) if cached_at.elapsed() < self.ttl => Some(response.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+    fn insert(&self, key: CacheKey, response: Response) {
+        if let Ok(key) = key.downcast::<Key>() {
+            let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+            entries.put(*key, (Instant::now(), response));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResponseCache;
+    use crate::router::Router;
+    use std::{
+        num::NonZeroUsize,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn test_cached_responses_skip_workers() {
+        let cache = ResponseCache::new(
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_millis(100),
+            |request: &String| request.to_lowercase(),
+        );
+        let router: Router<String, String> = Router::default().with_response_cache(cache);
+        let dispatched = Arc::new(AtomicUsize::new(0));
+        let worker_dispatched = dispatched.clone();
+        router.tokio_spawn_workers(1, move |receiver, sender| {
+            let dispatched = worker_dispatched.clone();
+            async move {
+                while let Ok((uuid, request)) = receiver.recv().await {
+                    dispatched.fetch_add(1, Ordering::SeqCst);
+                    sender.send((uuid, request)).await.unwrap();
+                }
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(None);
+        assert_eq!(
+            endpoint.handle_request("Hello".into()).await,
+            Ok("Hello".into())
+        );
+        // give the response loop time to populate the cache
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(
+            endpoint.handle_request("HELLO".into()).await,
+            Ok("Hello".into())
+        );
+        assert_eq!(dispatched.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            endpoint.handle_request("HELLO".into()).await,
+            Ok("HELLO".into())
+        );
+        assert_eq!(dispatched.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,349 @@
+//! # Channel Module
+//!
+//! This module provides the [Sender] and [Receiver] channel halves, and the
+//! [bounded] and [unbounded] functions creating them, the [Router] and its
+//! workers communicate over.
+//!
+//! ## Overview
+//!
+//! By default the channels are the MPMC channels of the
+//! [async-channel](https://docs.rs/async-channel) crate, re-exported as they
+//! are. With the `flume` feature the router is built on the channels of the
+//! [flume](https://docs.rs/flume) crate instead, wrapped so they offer the
+//! same operations, including closing a channel from either half, and the same
+//! errors. Code using the router only has to refer to the channel types
+//! through this module to compile with either backend.
+//!
+//! `tokio::sync::mpsc` is not offered as a backend, as its receivers can
+//! neither be cloned nor shared between workers without a lock, and its
+//! unbounded channels do not expose their length.
+#[cfg(doc)]
+use crate::router::Router;
+
+#[cfg(not(feature = "flume"))]
+pub use async_channel::{
+    bounded, unbounded, Receiver, RecvError, SendError, Sender, TryRecvError, TrySendError,
+};
+
+#[cfg(feature = "flume")]
+pub use flume_backend::{
+    bounded, unbounded, Receiver, RecvError, SendError, Sender, TryRecvError, TrySendError,
+};
+
+/// Channels of the `flume` crate, wrapped to offer the operations of the
+/// channels of the `async-channel` crate.
+#[cfg(feature = "flume")]
+mod flume_backend {
+    use std::{fmt, sync::Arc};
+
+    use thiserror::Error;
+    use tokio::sync::Notify;
+    use tokio_util::sync::CancellationToken;
+
+    /// Error returned by [Sender::send] once the channel is closed, holding
+    /// the message that could not be sent.
+    #[derive(Clone, Copy, PartialEq, Eq, Error)]
+    #[error("sending into a closed channel")]
+    pub struct SendError<T>(pub T);
+
+    // implemented by hand, deriving would require `T` to implement the trait
+    // as well.
+    impl<T> fmt::Debug for SendError<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "SendError(..)")
+        }
+    }
+
+    /// Error returned by [Sender::try_send], holding the message that could
+    /// not be sent.
+    #[derive(Clone, Copy, PartialEq, Eq, Error)]
+    pub enum TrySendError<T> {
+        /// the channel is full
+        #[error("sending into a full channel")]
+        Full(T),
+        /// the channel is closed
+        #[error("sending into a closed channel")]
+        Closed(T),
+    }
+
+    impl<T> fmt::Debug for TrySendError<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                TrySendError::Full(_) => write!(f, "Full(..)"),
+                TrySendError::Closed(_) => write!(f, "Closed(..)"),
+            }
+        }
+    }
+
+    /// Error returned by [Receiver::recv] once the channel is closed and
+    /// empty.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+    #[error("receiving from an empty and closed channel")]
+    pub struct RecvError;
+
+    /// Error returned by [Receiver::try_recv].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+    pub enum TryRecvError {
+        /// the channel is empty
+        #[error("receiving from an empty channel")]
+        Empty,
+        /// the channel is closed and empty
+        #[error("receiving from an empty and closed channel")]
+        Closed,
+    }
+
+    /// State shared by all halves of a channel.
+    #[derive(Debug, Default)]
+    struct Shared {
+        /// cancelled once the channel is closed
+        closed: CancellationToken,
+        /// wakes a sender waiting for room once a message was received
+        room: Notify,
+    }
+
+    /// The sending half of a channel.
+    pub struct Sender<T> {
+        /// the wrapped flume sender
+        inner: flume::Sender<T>,
+        /// state shared with the other halves
+        shared: Arc<Shared>,
+    }
+
+    /// The receiving half of a channel.
+    pub struct Receiver<T> {
+        /// the wrapped flume receiver
+        inner: flume::Receiver<T>,
+        /// state shared with the other halves
+        shared: Arc<Shared>,
+    }
+
+    /// Creates a channel holding at most `cap` messages.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cap` is zero, like `async_channel::bounded`.
+    pub fn bounded<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+        assert!(cap > 0, "capacity cannot be zero");
+        wrap(flume::bounded(cap))
+    }
+
+    /// Creates a channel holding any number of messages.
+    pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+        wrap(flume::unbounded())
+    }
+
+    /// Wraps the halves of a flume channel.
+    fn wrap<T>(
+        (sender, receiver): (flume::Sender<T>, flume::Receiver<T>),
+    ) -> (Sender<T>, Receiver<T>) {
+        let shared = Arc::new(Shared::default());
+        (
+            Sender {
+                inner: sender,
+                shared: shared.clone(),
+            },
+            Receiver {
+                inner: receiver,
+                shared,
+            },
+        )
+    }
+
+    impl<T> Sender<T> {
+        /// Sends `msg`, waiting for room if the channel is full.
+        ///
+        /// Fails once the channel is closed, including while waiting for room.
+        pub async fn send(&self, mut msg: T) -> Result<(), SendError<T>> {
+            loop {
+                // registered before trying, so room made meanwhile is noticed
+                let room = self.shared.room.notified();
+                tokio::pin!(room);
+                room.as_mut().enable();
+                msg = match self.try_send(msg) {
+                    Ok(()) => return Ok(()),
+                    Err(TrySendError::Full(msg)) => msg,
+                    Err(TrySendError::Closed(msg)) => return Err(SendError(msg)),
+                };
+                tokio::select! {
+                    _ = room => {}
+                    _ = self.shared.closed.cancelled() => {}
+                }
+            }
+        }
+        /// Sends `msg` if the channel has room for it.
+        pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+            if self.shared.closed.is_cancelled() {
+                return Err(TrySendError::Closed(msg));
+            }
+            self.inner.try_send(msg).map_err(|err| match err {
+                flume::TrySendError::Full(msg) => TrySendError::Full(msg),
+                flume::TrySendError::Disconnected(msg) => TrySendError::Closed(msg),
+            })
+        }
+        /// Closes the channel, see [Receiver::close].
+        pub fn close(&self) -> bool {
+            close(&self.shared)
+        }
+        /// Returns whether the channel is closed.
+        pub fn is_closed(&self) -> bool {
+            self.shared.closed.is_cancelled() || self.inner.is_disconnected()
+        }
+        /// Returns the number of messages in the channel.
+        pub fn len(&self) -> usize {
+            self.inner.len()
+        }
+        /// Returns whether the channel is empty.
+        pub fn is_empty(&self) -> bool {
+            self.inner.is_empty()
+        }
+        /// Returns whether the channel is full.
+        pub fn is_full(&self) -> bool {
+            self.inner.is_full()
+        }
+        /// Returns the capacity of the channel, `None` if it is unbounded.
+        pub fn capacity(&self) -> Option<usize> {
+            self.inner.capacity()
+        }
+    }
+
+    impl<T> Receiver<T> {
+        /// Receives a message, waiting for one if the channel is empty.
+        ///
+        /// Fails once the channel is closed and empty.
+        pub async fn recv(&self) -> Result<T, RecvError> {
+            let received = tokio::select! {
+                biased;
+                received = self.inner.recv_async() => received.ok(),
+                // messages sent before closing are still received
+                _ = self.shared.closed.cancelled() => self.inner.try_recv().ok(),
+            };
+            let msg = received.ok_or(RecvError)?;
+            self.shared.room.notify_one();
+            Ok(msg)
+        }
+        /// Receives a message if the channel is not empty.
+        pub fn try_recv(&self) -> Result<T, TryRecvError> {
+            match self.inner.try_recv() {
+                Ok(msg) => {
+                    self.shared.room.notify_one();
+                    Ok(msg)
+                }
+                Err(flume::TryRecvError::Empty) if !self.shared.closed.is_cancelled() => {
+                    Err(TryRecvError::Empty)
+                }
+                Err(_) => Err(TryRecvError::Closed),
+            }
+        }
+        /// Closes the channel: sending fails from then on, while the messages
+        /// sent before are still received.
+        ///
+        /// # Returns
+        ///
+        /// Returns `true` if this call closed the channel.
+        pub fn close(&self) -> bool {
+            close(&self.shared)
+        }
+        /// Returns whether the channel is closed.
+        pub fn is_closed(&self) -> bool {
+            self.shared.closed.is_cancelled() || self.inner.is_disconnected()
+        }
+        /// Returns the number of messages in the channel.
+        pub fn len(&self) -> usize {
+            self.inner.len()
+        }
+        /// Returns whether the channel is empty.
+        pub fn is_empty(&self) -> bool {
+            self.inner.is_empty()
+        }
+        /// Returns whether the channel is full.
+        pub fn is_full(&self) -> bool {
+            self.inner.is_full()
+        }
+        /// Returns the capacity of the channel, `None` if it is unbounded.
+        pub fn capacity(&self) -> Option<usize> {
+            self.inner.capacity()
+        }
+    }
+
+    /// Closes the channel of `shared`, returning whether it was open.
+    fn close(shared: &Shared) -> bool {
+        let was_open = !shared.closed.is_cancelled();
+        shared.closed.cancel();
+        was_open
+    }
+
+    // implemented by hand, deriving would require `T` to implement the traits
+    // as well.
+    impl<T> Clone for Sender<T> {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+                shared: self.shared.clone(),
+            }
+        }
+    }
+
+    impl<T> Clone for Receiver<T> {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+                shared: self.shared.clone(),
+            }
+        }
+    }
+
+    impl<T> fmt::Debug for Sender<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Sender")
+                .field("len", &self.len())
+                .field("closed", &self.is_closed())
+                .finish()
+        }
+    }
+
+    impl<T> fmt::Debug for Receiver<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Receiver")
+                .field("len", &self.len())
+                .field("closed", &self.is_closed())
+                .finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::channel::{bounded, RecvError, SendError, TrySendError};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_close() {
+        let (sender, receiver) = bounded(1);
+        sender.send(1).await.unwrap();
+        assert_eq!(sender.try_send(2), Err(TrySendError::Full(2)));
+
+        // a sender waiting for room fails once the channel is closed, while
+        // the messages sent before are still received
+        let waiting = tokio::spawn({
+            let sender = sender.clone();
+            async move { sender.send(2).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(receiver.close());
+        assert!(!sender.close());
+        assert_eq!(waiting.await.unwrap(), Err(SendError(2)));
+        assert_eq!(sender.send(3).await, Err(SendError(3)));
+        assert_eq!(receiver.recv().await, Ok(1));
+        assert_eq!(receiver.recv().await, Err(RecvError));
+
+        // receiving makes room for a waiting sender
+        let (sender, receiver) = bounded(1);
+        sender.send(1).await.unwrap();
+        let (sent, received) = tokio::join!(sender.send(2), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            receiver.recv().await
+        });
+        assert_eq!((sent, received), (Ok(()), Ok(1)));
+        assert_eq!(receiver.recv().await, Ok(2));
+    }
+}
@@ -0,0 +1,289 @@
+//! # Coalesce Module
+//!
+//! This module provides the [CoalescingEndpoint] struct buffering the
+//! requests submitted within a short window and submitting them to the
+//! router as a single batch, see [Endpoint::coalescing].
+//!
+//! ## Overview
+//!
+//! Every request handled by an [Endpoint] is registered with the router,
+//! dispatched to a worker and correlated with its response on its own. For
+//! chatty callers submitting many small requests, this per-request overhead
+//! dominates. A [CoalescingEndpoint] wraps an endpoint of a router whose
+//! workers handle batches of requests, `Router<Vec<Request>, Vec<Response>>`,
+//! and responding with one response per request, in the order of the
+//! requests. Requests handled within the coalescing window are buffered and
+//! submitted as one batch once the window closed, or once the batch reached
+//! its maximum size, and the responses of the batch are handed back to their
+//! callers.
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::channel::RecvError;
+use tokio::sync::oneshot;
+
+use crate::endpoint::{Endpoint, EndpointError};
+
+/// How the batch of a buffered request ended, as handed back to its caller.
+enum Outcome<Response> {
+    /// the batch was answered with the response of the request
+    Responded(Response),
+    /// the batch failed
+    Failed(EndpointError),
+}
+
+/// The requests buffered within the current coalescing window.
+struct Batch<Request, Response> {
+    /// identifies the batch to the task closing its window
+    id: u64,
+    /// the buffered requests
+    requests: Vec<Request>,
+    /// hand the outcomes back to the callers, in the order of the requests
+    callers: Vec<oneshot::Sender<Outcome<Response>>>,
+}
+
+/// The batch buffering requests, if any, and the identifier of the next one.
+struct Buffer<Request, Response> {
+    /// the batch of the current coalescing window
+    open: Option<Batch<Request, Response>>,
+    /// identifier of the next batch
+    next_id: u64,
+}
+
+/// An [Endpoint] of a router handling batches of requests, submitting the
+/// requests handled within a coalescing window as a single batch, see
+/// [Endpoint::coalescing].
+///
+/// Clones share the buffered batch.
+///
+/// # Type Parameters
+/// - `Request`: the type of the requests of a batch
+/// - `Response`: the type of the responses of a batch
+pub struct CoalescingEndpoint<Request, Response> {
+    /// the endpoint the batches are submitted through
+    endpoint: Endpoint<Vec<Request>, Vec<Response>>,
+    /// time a batch buffers requests for
+    window: Duration,
+    /// number of requests a batch is submitted at before its window closed
+    max_batch_size: Option<usize>,
+    /// the batch buffering requests, shared by all clones
+    buffer: Arc<Mutex<Buffer<Request, Response>>>,
+}
+
+// implemented by hand, deriving would require `Request` and `Response` to
+// implement the traits as well.
+impl<Request, Response> Clone for CoalescingEndpoint<Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            endpoint: self.endpoint.clone(),
+            window: self.window,
+            max_batch_size: self.max_batch_size,
+            buffer: self.buffer.clone(),
+        }
+    }
+}
+
+impl<Request, Response> fmt::Debug for CoalescingEndpoint<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoalescingEndpoint")
+            .field("endpoint", &self.endpoint)
+            .field("window", &self.window)
+            .field("max_batch_size", &self.max_batch_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Request, Response> CoalescingEndpoint<Request, Response>
+where
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    /// Creates a new `CoalescingEndpoint` submitting the requests handled
+    /// within `window` through `endpoint` as a single batch.
+    pub fn new(endpoint: Endpoint<Vec<Request>, Vec<Response>>, window: Duration) -> Self {
+        Self {
+            endpoint,
+            window,
+            max_batch_size: None,
+            buffer: Arc::new(Mutex::new(Buffer {
+                open: None,
+                next_id: 0,
+            })),
+        }
+    }
+    /// Submits batches as soon as they hold `max_batch_size` requests,
+    /// before their window closed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_batch_size` is zero.
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        assert!(max_batch_size > 0, "batches must hold at least one request");
+        self.max_batch_size = Some(max_batch_size);
+        self
+    }
+    /// Handles a request like [Endpoint::handle_request], submitting it
+    /// along with the other requests handled within the coalescing window.
+    ///
+    /// # Returns
+    ///
+    /// Returns the response at the position of the request in the response
+    /// of its batch. Requests without a response in their batch fail with
+    /// [EndpointError::ResponseReceive]. Errors of the batch are returned to
+    /// all its requests. The timeout of the endpoint is measured from the
+    /// call, including the time the request was buffered.
+    pub async fn handle_request(&self, request: Request) -> Result<Response, EndpointError> {
+        let deadline = self.endpoint.deadline(None);
+        let (sender, receiver) = oneshot::channel();
+        self.buffer_request(request, sender);
+        match deadline.wait(receiver).await? {
+            Ok(Outcome::Responded(response)) => Ok(response),
+            Ok(Outcome::Failed(err)) => Err(err),
+            Err(_) => Err(EndpointError::ResponseReceive(RecvError)),
+        }
+    }
+    /// Adds `request` to the open batch, opening a new batch if none is, and
+    /// submits the batch once it is full.
+    fn buffer_request(&self, request: Request, caller: oneshot::Sender<Outcome<Response>>) {
+        let mut buffer = self.buffer.lock().unwrap();
+        let batch = match &mut buffer.open {
+            Some(batch) => batch,
+            None => {
+                let id = buffer.next_id;
+                buffer.next_id += 1;
+                self.close_window_later(id);
+                buffer.open.insert(Batch {
+                    id,
+                    requests: Vec::new(),
+                    callers: Vec::new(),
+                })
+            }
+        };
+        batch.requests.push(request);
+        batch.callers.push(caller);
+        if self
+            .max_batch_size
+            .is_some_and(|max| batch.requests.len() >= max)
+        {
+            let batch = buffer.open.take().expect("the batch was just filled");
+            tokio::spawn(submit(self.endpoint.clone(), batch));
+        }
+    }
+    /// Spawns a task submitting the batch `id` once the window closed,
+    /// unless it was submitted before because it was full.
+    fn close_window_later(&self, id: u64) {
+        let endpoint = self.endpoint.clone();
+        let buffer = self.buffer.clone();
+        let window = self.window;
+        tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            let batch = {
+                let mut buffer = buffer.lock().unwrap();
+                match &buffer.open {
+                    Some(batch) if batch.id == id => buffer.open.take(),
+                    _ => None,
+                }
+            };
+            if let Some(batch) = batch {
+                submit(endpoint, batch).await;
+            }
+        });
+    }
+}
+
+/// Submits `batch` through `endpoint` and hands the responses back to their
+/// callers, in the order of the requests.
+async fn submit<Request, Response>(
+    endpoint: Endpoint<Vec<Request>, Vec<Response>>,
+    batch: Batch<Request, Response>,
+) where
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    let Batch {
+        requests, callers, ..
+    } = batch;
+    // callers which stopped waiting are skipped
+    match endpoint.handle_request(requests).await {
+        Ok(responses) => {
+            let mut responses = responses.into_iter();
+            for caller in callers {
+                let outcome = match responses.next() {
+                    Some(response) => Outcome::Responded(response),
+                    None => Outcome::Failed(EndpointError::ResponseReceive(RecvError)),
+                };
+                let _ = caller.send(outcome);
+            }
+        }
+        Err(err) => {
+            for caller in callers {
+                let _ = caller.send(Outcome::Failed(err.clone()));
+            }
+        }
+    }
+}
+
+impl<Request, Response> Endpoint<Vec<Request>, Vec<Response>>
+where
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    /// Coalesces the requests handled within `window` into a single batch
+    /// submitted through the endpoint, see [CoalescingEndpoint].
+    ///
+    /// The workers of the router handle batches, and respond with one
+    /// response per request of the batch, in the order of the requests.
+    pub fn coalescing(self, window: Duration) -> CoalescingEndpoint<Request, Response> {
+        CoalescingEndpoint::new(self, window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use crate::{endpoint::EndpointError, router::Router};
+
+    #[tokio::test]
+    async fn test_coalescing() {
+        let router: Router<Vec<u64>, Vec<u64>> = Router::default();
+        let batch_sizes = Arc::new(Mutex::new(Vec::new()));
+        router.spawn_workers_fn(1, {
+            let batch_sizes = batch_sizes.clone();
+            move |batch: Vec<u64>| {
+                batch_sizes.lock().unwrap().push(batch.len());
+                async move {
+                    // the worker skips the responses of zeros
+                    batch
+                        .into_iter()
+                        .filter(|n| *n > 0)
+                        .map(|n| n * 2)
+                        .collect()
+                }
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router
+            .endpoint(Duration::from_millis(200))
+            .coalescing(Duration::from_millis(20))
+            .with_max_batch_size(3);
+        let responses =
+            futures::future::join_all((1..=5).map(|n| endpoint.handle_request(n))).await;
+        assert_eq!(responses, [Ok(2), Ok(4), Ok(6), Ok(8), Ok(10)]);
+        // the first batch was full, the second one submitted once its window
+        // closed
+        assert_eq!(*batch_sizes.lock().unwrap(), [3, 2]);
+
+        assert!(matches!(
+            endpoint.handle_request(0).await,
+            Err(EndpointError::ResponseReceive(_))
+        ));
+    }
+}
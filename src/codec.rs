@@ -0,0 +1,161 @@
+//! # Codec Module
+//!
+//! This module provides the [Codec] trait encoding the requests and responses
+//! remote transports exchange, enabled with the `serde` feature, and its
+//! implementations:
+//!
+//! - [JsonCodec] (feature `json`): human-readable JSON using
+//!   [serde_json](https://docs.rs/serde_json).
+//! - [BincodeCodec] (feature `bincode`): compact binary encoding using
+//!   [bincode](https://docs.rs/bincode).
+//! - [PostcardCodec] (feature `postcard`): even more compact binary encoding
+//!   using [postcard](https://docs.rs/postcard), with variable-length
+//!   integers.
+//!
+//! ## Overview
+//!
+//! The transports of the crate, see `Router::from_nats_with_codec` and
+//! `Router::tokio_spawn_websocket_with_codec`, take a [Codec] rather than
+//! hard-coding one wire format, so users can pick compactness or
+//! debuggability. Their plain variants keep encoding as JSON.
+//!
+//! Besides plain values a codec encodes frames, pairing a value with the
+//! [Uuid] it is correlated by, for transports multiplexing several requests
+//! over a single connection.
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Wire format of the requests and responses exchanged by remote transports.
+pub trait Codec: Clone + Send + Sync + 'static {
+    /// error returned when encoding or decoding fails
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Encodes `value` into bytes.
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Self::Error>;
+
+    /// Decodes a value from `bytes`.
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::Error>;
+
+    /// Encodes `value` in a frame along with the `uuid` it is correlated by.
+    fn encode_frame<T: Serialize>(&self, uuid: Uuid, value: &T) -> Result<Vec<u8>, Self::Error> {
+        self.encode(&EncodeFrame { id: uuid, value })
+    }
+
+    /// Decodes a frame encoded by [Codec::encode_frame] from `bytes`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the [Uuid] and the value of the frame.
+    fn decode_frame<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<(Uuid, T), Self::Error> {
+        let DecodeFrame { id, value } = self.decode(bytes)?;
+        Ok((id, value))
+    }
+}
+
+/// A frame being encoded, borrowing its value.
+#[derive(Serialize)]
+struct EncodeFrame<'a, T> {
+    /// the identifier the value is correlated by
+    id: Uuid,
+    /// the framed value
+    value: &'a T,
+}
+
+/// A decoded frame, see [EncodeFrame].
+#[derive(Deserialize)]
+struct DecodeFrame<T> {
+    /// the identifier the value is correlated by
+    id: Uuid,
+    /// the framed value
+    value: T,
+}
+
+/// [Codec] encoding values as JSON.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl Codec for JsonCodec {
+    type Error = serde_json::Error;
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Self::Error> {
+        serde_json::to_vec(value)
+    }
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::Error> {
+        serde_json::from_slice(bytes)
+    }
+}
+
+/// [Codec] encoding values with bincode.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    type Error = bincode::Error;
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Self::Error> {
+        bincode::serialize(value)
+    }
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::Error> {
+        bincode::deserialize(bytes)
+    }
+}
+
+/// [Codec] encoding values with postcard.
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PostcardCodec;
+
+#[cfg(feature = "postcard")]
+impl Codec for PostcardCodec {
+    type Error = postcard::Error;
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Self::Error> {
+        postcard::to_stdvec(value)
+    }
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::Error> {
+        postcard::from_bytes(bytes)
+    }
+}
+
+#[cfg(all(test, any(feature = "json", feature = "bincode", feature = "postcard")))]
+mod tests {
+    use super::Codec;
+    use uuid::Uuid;
+
+    fn round_trip(codec: impl Codec) -> usize {
+        let request = (String::from("hello"), 7u32, Some(-1i64));
+        let encoded = codec.encode(&request).unwrap();
+        assert_eq!(
+            codec
+                .decode::<(String, u32, Option<i64>)>(&encoded)
+                .unwrap(),
+            request
+        );
+
+        let uuid = Uuid::new_v4();
+        let response: Result<String, String> = Ok("HELLO".to_string());
+        let frame = codec.encode_frame(uuid, &response).unwrap();
+        let decoded: (Uuid, Result<String, String>) = codec.decode_frame(&frame).unwrap();
+        assert_eq!(decoded, (uuid, response));
+        assert!(codec.decode::<(String, u32)>(&encoded[..2]).is_err());
+        frame.len()
+    }
+
+    #[test]
+    fn test_codecs() {
+        // from the most to the least compact encoding
+        let frame_sizes = [
+            #[cfg(feature = "postcard")]
+            round_trip(super::PostcardCodec),
+            #[cfg(feature = "bincode")]
+            round_trip(super::BincodeCodec),
+            #[cfg(feature = "json")]
+            round_trip(super::JsonCodec),
+        ];
+        assert!(frame_sizes.is_sorted());
+    }
+}
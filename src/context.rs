@@ -0,0 +1,86 @@
+//! # Context Module
+//!
+//! This module provides the [TraceContext] struct carrying a trace context
+//! alongside a request, from the [Endpoint](crate::endpoint::Endpoint)
+//! through the [Router](crate::router::Router) to the worker handling it.
+//!
+//! ## Overview
+//!
+//! A `TraceContext` holds an arbitrary propagation map (e.g. a W3C
+//! `traceparent` entry) and, with the `tracing` feature enabled, the
+//! [tracing](https://docs.rs/tracing) span that was current when the request
+//! was submitted. The context is kept by the router while the request is in
+//! flight, so the request type doesn't have to carry it:
+//!
+//! - workers spawned with
+//!   [Router::spawn_worker_instances](crate::router::Router::spawn_worker_instances)
+//!   handle every request inside the submitting span, making the worker's
+//!   spans children of the caller's span.
+//! - raw workers spawned with
+//!   [Router::tokio_spawn_workers](crate::router::Router::tokio_spawn_workers)
+//!   look the context up by the request's UUID using
+//!   [Router::trace_context](crate::router::Router::trace_context).
+use std::{collections::BTreeMap, future::Future};
+
+/// Trace context propagated with a request.
+#[derive(Debug, Clone, Default)]
+pub struct TraceContext {
+    /// propagation entries, e.g. `traceparent` and `tracestate`
+    fields: BTreeMap<String, String>,
+    /// span current at submission of the request
+    #[cfg(feature = "tracing")]
+    span: Option<tracing::Span>,
+}
+
+impl TraceContext {
+    /// Creates a new, empty `TraceContext`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Creates a new `TraceContext` capturing the current span.
+    #[cfg(feature = "tracing")]
+    pub fn current() -> Self {
+        Self::new().with_span(tracing::Span::current())
+    }
+    /// Sets the span the request is handled in.
+    #[cfg(feature = "tracing")]
+    pub fn with_span(mut self, span: tracing::Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+    /// Returns the span the request was submitted in, if any.
+    #[cfg(feature = "tracing")]
+    pub fn span(&self) -> Option<&tracing::Span> {
+        self.span.as_ref()
+    }
+    /// Inserts a propagation entry, returning the previous value of `key`.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.fields.insert(key.into(), value.into())
+    }
+    /// Returns the value of the propagation entry `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(String::as_str)
+    }
+    /// Creates the context submitted with a request by default, capturing the
+    /// current span if the `tracing` feature is enabled.
+    pub(crate) fn captured() -> Self {
+        #[cfg(feature = "tracing")]
+        return Self::current();
+        #[cfg(not(feature = "tracing"))]
+        return Self::new();
+    }
+    /// Awaits `future` inside the context's span, if any.
+    pub(crate) async fn instrument<F: Future>(&self, future: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        if let Some(span) = &self.span {
+            return tracing::Instrument::instrument(future, span.clone()).await;
+        }
+        future.await
+    }
+    /// Returns an iterator over the propagation entries.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
@@ -0,0 +1,189 @@
+//! # Deadline Module
+//!
+//! This module provides the crate-private [DeadlineQueue] struct ordering
+//! requests by their deadline for earliest-deadline-first scheduling, see
+//! [Router::with_edf_scheduling](crate::router::Router::with_edf_scheduling).
+//!
+//! ## Overview
+//!
+//! The deadline of a request is the time it was registered at plus the
+//! timeout of its endpoint. The registration loop pushes every registered
+//! request into the queue, and a dedicated loop pops the request with the
+//! soonest deadline whenever a worker is ready to take it. Requests without
+//! a deadline come last, requests with the same deadline are dispatched in
+//! registration order.
+use std::{
+    cmp::Ordering as CmpOrdering,
+    collections::BinaryHeap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use tokio::sync::Notify;
+
+use crate::dispatch::{Queued, SchedulingQueue};
+
+/// A request waiting in a [DeadlineQueue].
+struct Scheduled<Request> {
+    /// time by which the request's endpoint stops waiting, if any
+    deadline: Option<Instant>,
+    /// position of the request in registration order
+    sequence: u64,
+    /// the request itself
+    queued: Queued<Request>,
+}
+
+impl<Request> Ord for Scheduled<Request> {
+    /// Orders the request with the soonest deadline greatest, as
+    /// [BinaryHeap] pops the greatest element first.
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        let by_deadline = match (self.deadline, other.deadline) {
+            (Some(deadline), Some(other)) => other.cmp(&deadline),
+            (Some(_), None) => CmpOrdering::Greater,
+            (None, Some(_)) => CmpOrdering::Less,
+            (None, None) => CmpOrdering::Equal,
+        };
+        by_deadline.then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl<Request> PartialOrd for Scheduled<Request> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<Request> PartialEq for Scheduled<Request> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl<Request> Eq for Scheduled<Request> {}
+
+/// Requests waiting for a worker, ordered by their deadline.
+pub(crate) struct DeadlineQueue<Request> {
+    /// the waiting requests
+    heap: Mutex<BinaryHeap<Scheduled<Request>>>,
+    /// number of requests pushed so far
+    sequence: AtomicU64,
+    /// whether no further requests are pushed
+    closed: AtomicBool,
+    /// wakes the loop popping requests once a request was pushed
+    notify: Notify,
+}
+
+impl<Request> DeadlineQueue<Request> {
+    /// Creates a new, empty `DeadlineQueue`.
+    pub(crate) fn new() -> Self {
+        Self {
+            heap: Mutex::new(BinaryHeap::new()),
+            sequence: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            notify: Notify::new(),
+        }
+    }
+    /// Pushes `request`, to be popped once no request with a sooner deadline
+    /// is waiting.
+    pub(crate) fn push(&self, deadline: Option<Instant>, queued: Queued<Request>) {
+        let scheduled = Scheduled {
+            deadline,
+            sequence: self.sequence.fetch_add(1, Ordering::SeqCst),
+            queued,
+        };
+        self.heap.lock().unwrap().push(scheduled);
+        self.notify.notify_one();
+    }
+    /// Returns the number of waiting requests.
+    pub(crate) fn len(&self) -> usize {
+        self.heap.lock().unwrap().len()
+    }
+}
+
+impl<Request: Send> SchedulingQueue<Request> for DeadlineQueue<Request> {
+    /// Waits for the request with the soonest deadline and removes it from
+    /// the queue.
+    async fn pop(&self) -> Option<Queued<Request>> {
+        loop {
+            if let Some(scheduled) = self.heap.lock().unwrap().pop() {
+                return Some(scheduled.queued);
+            }
+            if self.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+            self.notify.notified().await;
+        }
+    }
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+}
+
+impl<Request> fmt::Debug for DeadlineQueue<Request> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadlineQueue")
+            .field("len", &self.len())
+            .field("closed", &self.closed.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::router::Router;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn test_earliest_deadline_first() {
+        let router: Router<u64, u64> = Router::default().with_edf_scheduling();
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let log = handled.clone();
+        router.tokio_spawn_workers(1, move |receiver, sender| {
+            let log = log.clone();
+            async move {
+                while let Ok((uuid, request)) = receiver.recv().await {
+                    log.lock().unwrap().push(request);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    sender.send((uuid, request)).await.unwrap();
+                }
+            }
+        });
+        router.tokio_spawn();
+
+        let without_deadline = router.endpoint(None);
+        let late = router.endpoint(Duration::from_secs(2));
+        let soon = router.endpoint(Duration::from_secs(1));
+        let sleep = |millis| tokio::time::sleep(Duration::from_millis(millis));
+        let responses = tokio::join!(
+            without_deadline.handle_request(1),
+            // handed over to the request channel while the worker is busy
+            async {
+                sleep(10).await;
+                without_deadline.handle_request(2).await
+            },
+            // queued while the worker is busy, in registration order
+            async {
+                sleep(20).await;
+                without_deadline.handle_request(3).await
+            },
+            async {
+                sleep(20).await;
+                late.handle_request(4).await
+            },
+            async {
+                sleep(20).await;
+                soon.handle_request(5).await
+            },
+        );
+        assert_eq!(responses, (Ok(1), Ok(2), Ok(3), Ok(4), Ok(5)));
+        assert_eq!(*handled.lock().unwrap(), [1, 2, 5, 4, 3]);
+    }
+}
@@ -0,0 +1,239 @@
+//! # Deferred Module
+//!
+//! This module provides the [ResponseHandle] struct letting workers spawned
+//! with [Router::spawn_deferred_workers] respond to a request from outside
+//! the worker loop.
+//!
+//! ## Overview
+//!
+//! Some requests can only be answered much later than they are received,
+//! e.g. once an external service called back through a webhook. A deferred
+//! worker hands every request to its handler along with the
+//! [ResponseHandle] of the request, and receives the next request as soon as
+//! the handler returns. The handler parks the handle wherever the response
+//! is produced, which responds through it from any task, without threading
+//! the UUID of the request and the router's response sender around.
+//!
+//! Requests whose handles are all dropped without responding are abandoned,
+//! their endpoints fail with
+//! [EndpointError::ResponseReceive](crate::endpoint::EndpointError::ResponseReceive).
+use std::{
+    fmt,
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::channel::{Receiver, Sender};
+use futures::FutureExt;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::router::Router;
+
+/// Abandons a request whose handles were all dropped without responding.
+type Abandon = Arc<dyn Fn(&Uuid) + Send + Sync>;
+
+/// Responds to a request handled by a deferred worker, see
+/// [Router::spawn_deferred_workers].
+///
+/// Cloning a `ResponseHandle` is cheap, all clones respond to the same
+/// request, and only the first response is delivered as the request's
+/// response. Once all clones are dropped without responding, the request is
+/// abandoned.
+///
+/// # Type Parameters
+/// - `Response`: the type of the response
+pub struct ResponseHandle<Response>(Arc<Inner<Response>>);
+
+/// The state shared by all clones of a [ResponseHandle].
+struct Inner<Response> {
+    /// UUID of the request
+    uuid: Uuid,
+    /// the router's response sender
+    sender: Sender<(Uuid, Response)>,
+    /// cancelled once nobody waits for the response anymore
+    cancellation: CancellationToken,
+    /// whether a response was sent through any clone
+    responded: AtomicBool,
+    /// abandons the request once dropped without responding
+    abandon: Abandon,
+}
+
+impl<Response> Drop for Inner<Response> {
+    fn drop(&mut self) {
+        if !self.responded.load(Ordering::SeqCst) {
+            (self.abandon)(&self.uuid);
+        }
+    }
+}
+
+// implemented by hand, deriving would require `Response` to implement the
+// trait as well.
+impl<Response> Clone for ResponseHandle<Response> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<Response> fmt::Debug for ResponseHandle<Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseHandle")
+            .field("uuid", &self.0.uuid)
+            .field("responded", &self.0.responded)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Response> ResponseHandle<Response> {
+    /// Returns the UUID of the request.
+    pub fn uuid(&self) -> Uuid {
+        self.0.uuid
+    }
+    /// Returns whether the endpoint of the request stopped waiting for the
+    /// response, e.g. because it timed out.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancellation.is_cancelled()
+    }
+    /// Returns the [CancellationToken] of the request, cancelled once nobody
+    /// waits for the response anymore.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.0.cancellation
+    }
+    /// Sends `response` as the response of the request.
+    ///
+    /// # Returns
+    ///
+    /// Returns `response` back if the request was already responded to
+    /// through another clone, or if the router was shut down.
+    pub async fn respond(self, response: Response) -> Result<(), Response> {
+        if self.0.responded.swap(true, Ordering::SeqCst) {
+            return Err(response);
+        }
+        self.0
+            .sender
+            .send((self.0.uuid, response))
+            .await
+            .map_err(|err| err.0 .1)
+    }
+}
+
+/// Asynchronous crate-private function that drives a deferred worker, see
+/// [Router::spawn_deferred_workers].
+///
+/// # Arguments
+///
+/// - `handler`: The function taking a request along with its
+///   [ResponseHandle].
+/// - `router`: The router the requests were submitted to.
+/// - `receiver`: The router's request receiver.
+/// - `sender`: The router's response sender.
+///
+/// # Behavior
+///
+/// The function receives requests until the request channel is closed, and
+/// hands each of them to the `handler` along with its [ResponseHandle],
+/// inside the request's [TraceContext](crate::context::TraceContext).
+/// Requests cancelled while queued are skipped. The next request is received
+/// once the handler returned, the response may be sent any time later.
+/// Requests whose handler panics without responding fail with
+/// [EndpointError::WorkerPanicked](crate::endpoint::EndpointError::WorkerPanicked).
+/// Health check pings are answered in between requests.
+pub(crate) async fn deferred_worker_loop<F, Fut, Request, Response>(
+    handler: Arc<F>,
+    router: Router<Request, Response>,
+    receiver: Receiver<(Uuid, Request)>,
+    sender: Sender<(Uuid, Response)>,
+) where
+    F: Fn(Request, ResponseHandle<Response>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send,
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    let health = router.health_probes().clone();
+    let probes = health.probes();
+    let abandon: Abandon = {
+        let router = router.clone();
+        Arc::new(move |uuid| router.deregister(uuid))
+    };
+    loop {
+        let (uuid, request) = tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(received) => received,
+                Err(_) => break,
+            },
+            Ok(probe) = probes.recv() => {
+                health.answer(probe).await;
+                continue;
+            }
+        };
+        let Some(cancellation) = router.cancellation_token(&uuid) else {
+            continue;
+        };
+        let context = router.trace_context(&uuid).unwrap_or_default();
+        let handle = ResponseHandle(Arc::new(Inner {
+            uuid,
+            sender: sender.clone(),
+            cancellation,
+            responded: AtomicBool::new(false),
+            abandon: abandon.clone(),
+        }));
+        // keeps the request pending while the handler unwinds, so a panic
+        // fails it rather than abandoning it
+        let kept = handle.clone();
+        let handled = AssertUnwindSafe(context.instrument(handler(request, handle)))
+            .catch_unwind()
+            .await;
+        if handled.is_err() && !kept.0.responded.load(Ordering::SeqCst) {
+            kept.0.responded.store(true, Ordering::SeqCst);
+            router.fail_panicked(&uuid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::channel::unbounded;
+    use crate::{endpoint::EndpointError, router::Router};
+
+    use super::ResponseHandle;
+
+    #[tokio::test]
+    async fn test_deferred_responses() {
+        let router: Router<u64, u64> = Router::default();
+        // the handler parks the handles, a webhook task responds later
+        let (parked, webhook) = unbounded::<(u64, ResponseHandle<u64>)>();
+        router.spawn_deferred_workers(1, move |request, handle| {
+            let parked = parked.clone();
+            async move {
+                match request {
+                    0 => drop(handle),
+                    _ => parked.send((request, handle)).await.unwrap(),
+                }
+            }
+        });
+        tokio::spawn(async move {
+            while let Ok((request, handle)) = webhook.recv().await {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let duplicate = handle.clone();
+                handle.respond(request * 2).await.unwrap();
+                assert_eq!(duplicate.respond(0).await, Err(0));
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(Duration::from_millis(200));
+        let (first, second) = tokio::join!(endpoint.handle_request(1), endpoint.handle_request(2));
+        assert_eq!((first, second), (Ok(2), Ok(4)));
+        // dropping the handle without responding abandons the request
+        assert!(matches!(
+            endpoint.handle_request(0).await,
+            Err(EndpointError::ResponseReceive(_))
+        ));
+    }
+}
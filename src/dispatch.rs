@@ -0,0 +1,603 @@
+//! # Dispatch Module
+//!
+//! This module provides the [DispatchStrategy] trait deciding which worker
+//! handles a request, its [RoundRobin], [LeastLoaded], [Weighted] and [Sticky]
+//! implementations, the [DispatchEngine] enum selecting how requests reach the
+//! workers from the [RouterBuilder](crate::builder::RouterBuilder), and the
+//! crate-private [WorkerChannels] struct replacing the router's shared request
+//! channel with one request channel per worker, see
+//! [Router::with_dispatch_strategy](crate::router::Router::with_dispatch_strategy).
+//!
+//! ## Overview
+//!
+//! By default all workers receive requests from a single MPMC channel, so any
+//! worker may handle any request. With a dispatch strategy every worker is
+//! attached to its own channel when it is spawned, and the registration loop
+//! sends each request to the channel picked by the strategy. This allows e.g.
+//! to send more requests to a pool of fast workers than to a pool of slow
+//! ones, or to handle all requests with the same key by the same worker, which
+//! lets stateful workers keep per-session state.
+//!
+//! At high fan-in, all idle workers waiting on the shared channel are woken
+//! up by the requests sent to it. Per-worker channels avoid these wakeups,
+//! but a request sent to a busy worker waits behind it even while other
+//! workers are idle. With work stealing, see
+//! [Router::with_work_stealing](crate::router::Router::with_work_stealing),
+//! idle workers take the requests waiting for busy ones.
+use std::{
+    fmt,
+    future::Future,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+use futures::future::BoxFuture;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{deadline::DeadlineQueue, tenant::TenantQueues, timed::DispatchedAt};
+
+/// Decides which worker channel the registration loop sends a request to.
+///
+/// # Type Parameters
+/// - `Request`: the request type of the router
+pub trait DispatchStrategy<Request>: Send + Sync {
+    /// Returns the index of the worker channel `request` is sent to.
+    ///
+    /// # Arguments
+    ///
+    /// - `request`: The dispatched request.
+    /// - `queue_lens`: The number of requests waiting in every worker
+    ///   channel, indexed by worker.
+    ///
+    /// # Returns
+    ///
+    /// Returns the index of the worker channel, indices beyond the number of
+    /// channels wrap around.
+    fn select(&self, request: &Request, queue_lens: &[usize]) -> usize;
+}
+
+/// Sends requests to the workers in turn.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    /// number of requests dispatched so far
+    next: AtomicUsize,
+}
+
+impl RoundRobin {
+    /// Creates a new `RoundRobin` strategy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<Request> DispatchStrategy<Request> for RoundRobin {
+    fn select(&self, _: &Request, _: &[usize]) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Sends requests to the worker with the fewest queued requests, ties are
+/// broken in turn.
+#[derive(Debug, Default)]
+pub struct LeastLoaded {
+    /// number of requests dispatched so far, rotates the tie breaking
+    next: AtomicUsize,
+}
+
+impl LeastLoaded {
+    /// Creates a new `LeastLoaded` strategy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<Request> DispatchStrategy<Request> for LeastLoaded {
+    fn select(&self, _: &Request, queue_lens: &[usize]) -> usize {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..queue_lens.len())
+            .map(|offset| (start + offset) % queue_lens.len())
+            .min_by_key(|&index| queue_lens[index])
+            .unwrap_or(0)
+    }
+}
+
+/// Sends requests to the workers in proportion to their weights, e.g. to
+/// send more requests to fast local workers than to slow remote ones.
+#[derive(Debug)]
+pub struct Weighted {
+    /// cumulative weights, indexed by worker
+    bounds: Vec<usize>,
+    /// number of requests dispatched so far
+    next: AtomicUsize,
+}
+
+impl Weighted {
+    /// Creates a new `Weighted` strategy.
+    ///
+    /// # Arguments
+    ///
+    /// - `weights`: The weight of every worker, indexed by worker. Out of
+    ///   every `sum(weights)` requests, a worker receives as many as its
+    ///   weight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the weights sum up to zero.
+    pub fn new(weights: impl IntoIterator<Item = usize>) -> Self {
+        let bounds: Vec<usize> = weights
+            .into_iter()
+            .scan(0, |total, weight| {
+                *total += weight;
+                Some(*total)
+            })
+            .collect();
+        assert!(
+            bounds.last().is_some_and(|&total| total > 0),
+            "weighted dispatch requires a positive total weight"
+        );
+        Self {
+            bounds,
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<Request> DispatchStrategy<Request> for Weighted {
+    fn select(&self, _: &Request, _: &[usize]) -> usize {
+        let total = self.bounds[self.bounds.len() - 1];
+        let slot = self.next.fetch_add(1, Ordering::Relaxed) % total;
+        self.bounds.partition_point(|&bound| bound <= slot)
+    }
+}
+
+/// Sends all requests with the same key to the same worker, by hashing the
+/// key of every request.
+pub struct Sticky<Request> {
+    /// maps requests to the hash of their key
+    key_fn: Box<dyn Fn(&Request) -> u64 + Send + Sync>,
+}
+
+impl<Request> Sticky<Request> {
+    /// Creates a new `Sticky` strategy routing requests by the key returned
+    /// by `key_fn`.
+    pub fn new<Key>(key_fn: impl Fn(&Request) -> Key + Send + Sync + 'static) -> Self
+    where
+        Key: Hash,
+    {
+        Self {
+            key_fn: Box::new(move |request| {
+                // the default hasher is deterministic, equal keys always map
+                // to the same channel
+                let mut hasher = DefaultHasher::new();
+                key_fn(request).hash(&mut hasher);
+                hasher.finish()
+            }),
+        }
+    }
+}
+
+impl<Request> fmt::Debug for Sticky<Request> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sticky").finish_non_exhaustive()
+    }
+}
+
+impl<Request> DispatchStrategy<Request> for Sticky<Request> {
+    fn select(&self, request: &Request, queue_lens: &[usize]) -> usize {
+        ((self.key_fn)(request) % queue_lens.len() as u64) as usize
+    }
+}
+
+/// How the router hands the requests to its workers, see
+/// [RouterBuilder::dispatch_engine](crate::builder::RouterBuilder::dispatch_engine).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DispatchEngine {
+    /// all workers receive the requests from the router's shared request
+    /// channel
+    #[default]
+    Shared,
+    /// every worker has its own request channel, requests are sent to the
+    /// one with the fewest queued requests, see [LeastLoaded]
+    LeastLoaded,
+    /// every worker has its own request channel, requests are sent to the
+    /// channels in turn and idle workers steal the requests queued for busy
+    /// ones, see
+    /// [Router::with_work_stealing](crate::router::Router::with_work_stealing)
+    WorkStealing,
+}
+
+/// Request channels of the workers, one per worker.
+pub(crate) struct WorkerChannels<Request> {
+    /// senders of the request channels, indexed by worker
+    senders: Vec<Sender<(Uuid, Request)>>,
+    /// receivers of the request channels, indexed by worker
+    receivers: Vec<Receiver<(Uuid, Request)>>,
+    /// number of workers attached so far, the next worker is attached to the
+    /// channel at this index modulo the number of channels
+    attached: AtomicUsize,
+    /// picks the channel every request is sent to
+    strategy: Box<dyn DispatchStrategy<Request>>,
+    /// the channels idle workers steal from, if work stealing is enabled
+    stealing: Option<Arc<Stealing<Request>>>,
+}
+
+/// The request channels of the workers as seen by the feeders stealing from
+/// them, see [WorkerChannels::attach].
+///
+/// Holds no senders, so the channels close once the router is dropped.
+struct Stealing<Request> {
+    /// receivers of the request channels, indexed by worker
+    receivers: Vec<Receiver<(Uuid, Request)>>,
+    /// receivers of the inboxes of the attached workers, in the order they
+    /// were attached
+    inboxes: Mutex<Vec<Receiver<(Uuid, Request)>>>,
+    /// wakes an idle feeder once a request was dispatched, or once a request
+    /// was put into the inbox of a busy worker
+    dispatched: Notify,
+}
+
+impl<Request> Stealing<Request> {
+    /// Takes a request from the channel of another worker than `index`,
+    /// preferring the channel with the most queued requests, or else from
+    /// the inbox of another worker than the one attached as `inbox`.
+    fn steal(&self, index: usize, inbox: usize) -> Option<(Uuid, Request)> {
+        let mut victims: Vec<&Receiver<(Uuid, Request)>> = self
+            .receivers
+            .iter()
+            .enumerate()
+            .filter(|(victim, receiver)| *victim != index && !receiver.is_empty())
+            .map(|(_, receiver)| receiver)
+            .collect();
+        victims.sort_by_key(|receiver| std::cmp::Reverse(receiver.len()));
+        if let Some(stolen) = victims
+            .into_iter()
+            .find_map(|receiver| receiver.try_recv().ok())
+        {
+            return Some(stolen);
+        }
+        self.inboxes
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|(victim, _)| *victim != inbox)
+            .find_map(|(_, receiver)| receiver.try_recv().ok())
+    }
+}
+
+/// Asynchronous private function that feeds the worker attached to the
+/// channel `index` through its `inbox`, the `inbox_index`th one attached,
+/// see [WorkerChannels::attach].
+///
+/// # Behavior
+///
+/// The function takes the next request from the worker's own channel, or
+/// steals one if its own is empty: from the channel of another worker, or
+/// else from the inbox of another, busy worker. It puts the request into the
+/// worker's `inbox`, which holds a single request, waiting for the worker to
+/// take the previous one if needed. A busy worker thereby holds at most one
+/// request which can not be stolen, the one the feeder waits to put into its
+/// inbox. Without any request to take, it waits for the next request to be
+/// dispatched. It returns once all channels are closed and empty, or once the
+/// worker dropped the `inbox`.
+async fn feed<Request>(
+    index: usize,
+    inbox_index: usize,
+    stealing: Arc<Stealing<Request>>,
+    inbox: Sender<(Uuid, Request)>,
+) {
+    let own = &stealing.receivers[index];
+    loop {
+        // registered before looking, so requests dispatched meanwhile are
+        // noticed
+        let dispatched = stealing.dispatched.notified();
+        tokio::pin!(dispatched);
+        dispatched.as_mut().enable();
+        let next = match own.try_recv() {
+            Ok(next) => Some(next),
+            Err(_) => stealing.steal(index, inbox_index),
+        };
+        let next = match next {
+            Some(next) => next,
+            None => tokio::select! {
+                received = own.recv() => match received {
+                    Ok(next) => next,
+                    // the router was dropped, the channels are drained
+                    Err(_) => match stealing.steal(index, inbox_index) {
+                        Some(next) => next,
+                        None => return,
+                    },
+                },
+                _ = dispatched => continue,
+            },
+        };
+        match inbox.try_send(next) {
+            Ok(()) => {}
+            // the worker is busy, the request can be stolen by an idle
+            // worker once it is in the inbox
+            Err(TrySendError::Full(next)) => {
+                if inbox.send(next).await.is_err() {
+                    return;
+                }
+                stealing.dispatched.notify_one();
+            }
+            Err(TrySendError::Closed(_)) => return,
+        }
+    }
+}
+
+impl<Request> WorkerChannels<Request> {
+    /// Creates the channels of `workers` workers, dispatching requests with
+    /// `strategy`.
+    ///
+    /// # Arguments
+    ///
+    /// - `workers`: The number of channels, must not be zero.
+    /// - `capacity`: The capacity of every channel, unbounded if `None`.
+    /// - `strategy`: The strategy picking the channel of every request.
+    pub(crate) fn new(
+        workers: usize,
+        capacity: Option<usize>,
+        strategy: impl DispatchStrategy<Request> + 'static,
+    ) -> Self {
+        assert!(workers > 0, "dispatching requires at least one worker");
+        let (senders, receivers) = (0..workers)
+            .map(|_| match capacity {
+                Some(b) => bounded(b),
+                None => unbounded(),
+            })
+            .unzip();
+        Self {
+            senders,
+            receivers,
+            attached: AtomicUsize::new(0),
+            strategy: Box::new(strategy),
+            stealing: None,
+        }
+    }
+    /// Creates the channels of `workers` workers like [WorkerChannels::new],
+    /// dispatching requests in turn and letting idle workers steal the
+    /// requests queued for busy ones.
+    pub(crate) fn work_stealing(workers: usize, capacity: Option<usize>) -> Self {
+        let mut channels = Self::new(workers, capacity, RoundRobin::new());
+        channels.stealing = Some(Arc::new(Stealing {
+            receivers: channels.receivers.clone(),
+            inboxes: Mutex::new(Vec::new()),
+            dispatched: Notify::new(),
+        }));
+        channels
+    }
+    /// Returns the sender of the channel `request` is dispatched to.
+    pub(crate) fn sender(&self, request: &Request) -> &Sender<(Uuid, Request)> {
+        let queue_lens: Vec<usize> = self.senders.iter().map(Sender::len).collect();
+        let index = self.strategy.select(request, &queue_lens) % self.senders.len();
+        &self.senders[index]
+    }
+    /// Notifies the channels that a request was sent to one of them.
+    pub(crate) fn dispatched(&self) {
+        if let Some(stealing) = &self.stealing {
+            stealing.dispatched.notify_one();
+        }
+    }
+    /// Returns the number of requests waiting in all channels.
+    pub(crate) fn len(&self) -> usize {
+        self.senders.iter().map(Sender::len).sum()
+    }
+}
+
+impl<Request> WorkerChannels<Request>
+where
+    Request: Send + 'static,
+{
+    /// Attaches a new worker.
+    ///
+    /// # Returns
+    ///
+    /// Returns the receiver the worker receives its requests from. With work
+    /// stealing, the receiver is the worker's inbox, returned along with the
+    /// feeder filling it, which has to be spawned for the worker to receive
+    /// any requests.
+    pub(crate) fn attach(&self) -> (Receiver<(Uuid, Request)>, Option<BoxFuture<'static, ()>>) {
+        let index = self.attached.fetch_add(1, Ordering::SeqCst) % self.receivers.len();
+        match &self.stealing {
+            Some(stealing) => {
+                let (inbox, receiver) = bounded(1);
+                let inbox_index = {
+                    let mut inboxes = stealing.inboxes.lock().unwrap();
+                    inboxes.push(receiver.clone());
+                    inboxes.len() - 1
+                };
+                let feeder = Box::pin(feed(index, inbox_index, stealing.clone(), inbox));
+                (receiver, Some(feeder))
+            }
+            None => (self.receivers[index].clone(), None),
+        }
+    }
+}
+
+impl<Request> fmt::Debug for WorkerChannels<Request> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkerChannels")
+            .field("channels", &self.senders.len())
+            .field("attached", &self.attached)
+            .field("stealing", &self.stealing.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A request waiting in a [SchedulingQueue].
+pub(crate) struct Queued<Request> {
+    /// UUID of the request
+    pub(crate) uuid: Uuid,
+    /// the request itself
+    pub(crate) request: Request,
+    /// cancelled once nobody waits for the response anymore
+    pub(crate) cancellation: CancellationToken,
+    /// stamped once the request is dispatched, if its endpoint times it
+    pub(crate) dispatched_at: Option<DispatchedAt>,
+}
+
+/// A queue registered requests wait in, in an order of its own, before the
+/// router dispatches them to the workers.
+pub(crate) trait SchedulingQueue<Request>: Send + Sync {
+    /// Waits for the next request to dispatch and removes it from the queue.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` once the queue was closed and is empty.
+    fn pop(&self) -> impl Future<Output = Option<Queued<Request>>> + Send;
+    /// Closes the queue, [SchedulingQueue::pop] returns `None` once the
+    /// remaining requests were popped.
+    fn close(&self);
+}
+
+/// The channels and queues requests wait in for a worker.
+pub(crate) struct RequestQueues<Request> {
+    /// sends requests to the router's shared request channel
+    pub(crate) request_sender: Sender<(Uuid, Request)>,
+    /// per-worker request channels replacing the shared request channel,
+    /// if a dispatch strategy is set
+    pub(crate) worker_channels: Option<Arc<WorkerChannels<Request>>>,
+    /// orders requests by deadline before they are dispatched, if
+    /// earliest-deadline-first scheduling is enabled
+    pub(crate) deadline_queue: Option<Arc<DeadlineQueue<Request>>>,
+    /// per-tenant queues dispatched in turn, if tenancy is enabled
+    pub(crate) tenant_queues: Option<Arc<TenantQueues<Request>>>,
+}
+
+impl<Request> RequestQueues<Request> {
+    /// Returns the sender of the channel `request` is dispatched to.
+    pub(crate) fn sender(&self, request: &Request) -> &Sender<(Uuid, Request)> {
+        match &self.worker_channels {
+            Some(channels) => channels.sender(request),
+            None => &self.request_sender,
+        }
+    }
+    /// Notifies the per-worker channels, if any, that a request was sent to
+    /// the workers.
+    pub(crate) fn dispatched(&self) {
+        if let Some(channels) = &self.worker_channels {
+            channels.dispatched();
+        }
+    }
+    /// Returns the number of requests waiting for a worker.
+    pub(crate) fn len(&self) -> usize {
+        self.request_sender.len()
+            + self
+                .worker_channels
+                .as_ref()
+                .map_or(0, |channels| channels.len())
+            + self.deadline_queue.as_ref().map_or(0, |queue| queue.len())
+            + self.tenant_queues.as_ref().map_or(0, |queues| queues.len())
+    }
+}
+
+// implemented by hand, deriving would require `Request` to implement the
+// traits as well.
+impl<Request> Clone for RequestQueues<Request> {
+    fn clone(&self) -> Self {
+        Self {
+            request_sender: self.request_sender.clone(),
+            worker_channels: self.worker_channels.clone(),
+            deadline_queue: self.deadline_queue.clone(),
+            tenant_queues: self.tenant_queues.clone(),
+        }
+    }
+}
+
+impl<Request> fmt::Debug for RequestQueues<Request> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestQueues")
+            .field("request_sender", &self.request_sender)
+            .field("worker_channels", &self.worker_channels)
+            .field("deadline_queue", &self.deadline_queue)
+            .field("tenant_queues", &self.tenant_queues)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DispatchStrategy, LeastLoaded, RoundRobin, Weighted};
+    use crate::{endpoint::EndpointError, router::Router};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn test_sticky_routing() {
+        let router: Router<u32, (u32, usize)> =
+            Router::default().with_sticky_routing(3, |request: &u32| request % 5);
+        let next_worker = Arc::new(AtomicUsize::new(0));
+        router.tokio_spawn_workers(3, |receiver, sender| {
+            let worker = next_worker.fetch_add(1, Ordering::SeqCst);
+            async move {
+                while let Ok((uuid, request)) = receiver.recv().await {
+                    sender.send((uuid, (request, worker))).await.unwrap();
+                }
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(None);
+        let mut workers = [None; 5];
+        for request in 0..50 {
+            let (_, worker) = endpoint.handle_request(request).await.unwrap();
+            let key = (request % 5) as usize;
+            assert_eq!(*workers[key].get_or_insert(worker), worker);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_work_stealing() {
+        let router: Router<u64, u64> = Router::default().with_work_stealing(2);
+        router.spawn_workers_fn(2, |millis| async move {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            millis
+        });
+        router.tokio_spawn();
+
+        // one worker is kept busy while requests keep being sent to both
+        // channels in turn
+        let busy = tokio::spawn({
+            let endpoint = router.endpoint(None);
+            async move { endpoint.handle_request(400).await }
+        });
+        let endpoint = router.endpoint(Duration::from_millis(200));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let responses = futures::future::join_all((0..8).map(|_| endpoint.handle_request(5))).await;
+        // the idle worker steals the requests sent to the busy one, except
+        // for at most the one waiting to be put into its inbox
+        let timed_out = responses
+            .iter()
+            .filter(|response| matches!(response, Err(EndpointError::Timeout(_))))
+            .count();
+        assert!(timed_out <= 1, "{timed_out} requests timed out");
+        assert_eq!(busy.await.unwrap(), Ok(400));
+    }
+
+    #[test]
+    fn test_strategies() {
+        let round_robin = RoundRobin::new();
+        let selected: Vec<usize> = (0..4).map(|_| round_robin.select(&(), &[0; 2])).collect();
+        assert_eq!(selected, [0, 1, 2, 3]);
+
+        let least_loaded = LeastLoaded::new();
+        assert_eq!(least_loaded.select(&(), &[3, 1, 2]), 1);
+        assert_eq!(least_loaded.select(&(), &[0, 0, 5]), 1);
+
+        let weighted = Weighted::new([3, 1]);
+        let selected: Vec<usize> = (0..8).map(|_| weighted.select(&(), &[0; 2])).collect();
+        assert_eq!(selected, [0, 0, 0, 1, 0, 0, 0, 1]);
+    }
+}
@@ -10,30 +10,631 @@
 //! communication is non-blocking and efficient.
 //!
 //! The `EndpointError` enum defines various errors that can occur during the operation of an `Endpoint`,
-//! including errors related to sending requests, receiving responses, and timeouts.
-use async_channel::{bounded, RecvError, SendError, Sender};
+//! including errors related to sending requests, receiving responses, and timeouts. Routers whose
+//! workers respond with a `Result` can surface the worker's error as `EndpointError::Rejected`, see
+//! [Endpoint::try_handle_request].
+//!
+//! Endpoints can fall back on a response of their own once a request timed
+//! out, see [Endpoint::with_fallback], so call sites serving a default or
+//! stale response don't have to match [EndpointError::Timeout].
+//!
+//! The timeout of an endpoint bounds the whole request: the deadline of a request is computed once
+//! when it is submitted, and every wait of the request, for a paused router to be resumed, for a
+//! concurrency slot, for the throttle of its key or for its response, is charged against it.
+//!
+//! On `wasm32` targets, where tokio has no timer, timeouts are applied with a timer backed by the
+//! browser's `setTimeout`, so endpoints can be compiled into a wasm client.
+use std::{
+    convert::Infallible,
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::channel::{bounded, Receiver, RecvError, SendError, Sender};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::timed::Timed;
+use futures::{future::BoxFuture, FutureExt};
 use thiserror::Error;
-use tokio::time::{error::Elapsed, timeout};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use tokio::time::{timeout, timeout_at, Instant as TimerInstant};
+use tokio_util::sync::CancellationToken;
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::Instant as TimerInstant;
+
+use uuid::Uuid;
+
+use crate::{
+    context::TraceContext,
+    dispatch::RequestQueues,
+    id::IdGenerator,
+    pause::{Intake, PauseMode},
+    recorder::Recording,
+    tenant::TenantQueues,
+    throttle::{KeyThrottle, ThrottleSlot},
+    timed::DispatchedAt,
+};
+
+/// Error returned when a request times out, unlike tokio's `Elapsed` error it
+/// can be cloned, and it is available on `wasm32` targets as well.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("deadline has elapsed")]
+pub struct Elapsed(());
+
+/// Requires `future` to complete before `duration` has elapsed, like tokio's
+/// `timeout`, using a timer backed by the browser's `setTimeout`.
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn timeout<F: Future>(
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, Elapsed> {
+    use futures::future::{select, Either};
+
+    match select(std::pin::pin!(future), futures_timer::Delay::new(duration)).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(Elapsed(())),
+    }
+}
+
+/// Requires `future` to complete before `deadline`, like tokio's
+/// `timeout_at`, using a timer backed by the browser's `setTimeout`.
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn timeout_at<F: Future>(
+    deadline: TimerInstant,
+    future: F,
+) -> Result<F::Output, Elapsed> {
+    timeout(
+        deadline.saturating_duration_since(TimerInstant::now()),
+        future,
+    )
+    .await
+}
+
+/// The time a request times out at, computed once when the request is
+/// submitted, so every wait of the request is charged against the timeout of
+/// its endpoint.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(Option<TimerInstant>);
+
+impl Deadline {
+    /// Requires `future` to complete before the deadline, if there is one.
+    pub(crate) async fn wait<F: Future>(self, future: F) -> Result<F::Output, Elapsed> {
+        match self.0 {
+            Some(deadline) => timeout_at(deadline, future).await.map_err(|_| Elapsed(())),
+            None => Ok(future.await),
+        }
+    }
+}
 
-#[derive(Error, Debug, PartialEq, Eq)]
-pub enum EndpointError {
+/// Errors returned by an [Endpoint].
+///
+/// A request can be refused at two points: [EndpointError::Invalid] is
+/// returned by the endpoint itself when the validator of the router refuses
+/// the request, before it is submitted, while [EndpointError::Rejected] holds
+/// the error a worker responded with after handling the request.
+///
+/// # Type Parameters
+/// - `E`: the application-level error of a request rejected by its worker,
+///   [Infallible] unless requests are handled with
+///   [Endpoint::try_handle_request]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum EndpointError<E = Infallible> {
     #[error("Error sending request")]
     RequestSend,
     #[error("Error receiving response")]
     ResponseReceive(#[from] RecvError),
     #[error("Request timed out")]
     Timeout(#[from] Elapsed),
+    #[error("Router is overloaded")]
+    Overloaded,
+    #[error("Too many requests in flight")]
+    TooManyInFlight,
+    #[error("Too many requests for the same key")]
+    Throttled,
+    #[error("Too many requests in flight for the endpoint")]
+    ConcurrencyLimit,
+    #[error("No workers are attached to the router")]
+    NoWorkers,
+    #[error("Router is paused")]
+    Paused,
+    /// the validator of the router refused the request before it was
+    /// submitted, no worker has seen it, see
+    /// [Router::with_validator](crate::router::Router::with_validator)
+    #[error("Invalid request: {0}")]
+    Invalid(RejectReason),
+    #[error("Worker panicked while handling the request")]
+    WorkerPanicked,
+    /// a worker handled the request and responded with an error, see
+    /// [Endpoint::try_handle_request]
+    #[error("Request rejected: {0}")]
+    Rejected(E),
 }
 
-impl<Request, Response> From<SendError<(Request, Sender<Response>)>> for EndpointError {
-    fn from(_: SendError<(Request, Sender<Response>)>) -> Self {
+impl EndpointError {
+    /// Converts the error into an `EndpointError` of a request that could
+    /// have been rejected with an `E`.
+    pub(crate) fn widen<E>(self) -> EndpointError<E> {
+        match self {
+            EndpointError::RequestSend => EndpointError::RequestSend,
+            EndpointError::ResponseReceive(err) => EndpointError::ResponseReceive(err),
+            EndpointError::Timeout(elapsed) => EndpointError::Timeout(elapsed),
+            EndpointError::Overloaded => EndpointError::Overloaded,
+            EndpointError::TooManyInFlight => EndpointError::TooManyInFlight,
+            EndpointError::Throttled => EndpointError::Throttled,
+            EndpointError::ConcurrencyLimit => EndpointError::ConcurrencyLimit,
+            EndpointError::NoWorkers => EndpointError::NoWorkers,
+            EndpointError::Paused => EndpointError::Paused,
+            EndpointError::Invalid(reason) => EndpointError::Invalid(reason),
+            EndpointError::WorkerPanicked => EndpointError::WorkerPanicked,
+            EndpointError::Rejected(never) => match never {},
+        }
+    }
+}
+
+/// Reason a request was refused by the validator of its router, returned as
+/// [EndpointError::Invalid], see
+/// [Router::with_validator](crate::router::Router::with_validator). Errors of
+/// the workers are returned as [EndpointError::Rejected] instead.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Hash)]
+#[error("{0}")]
+pub struct RejectReason(String);
+
+impl RejectReason {
+    /// Creates a new `RejectReason` described by `reason`.
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self(reason.into())
+    }
+    /// Returns the description of the reason.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for RejectReason {
+    fn from(reason: String) -> Self {
+        Self(reason)
+    }
+}
+
+impl From<&str> for RejectReason {
+    fn from(reason: &str) -> Self {
+        Self(reason.to_string())
+    }
+}
+
+impl<Request, Response, E> From<SendError<Registration<Request, Response>>> for EndpointError<E> {
+    fn from(_: SendError<Registration<Request, Response>>) -> Self {
         EndpointError::RequestSend
     }
 }
 
+/// Timeout of the requests handled by an [Endpoint], see
+/// [Router::endpoint](crate::router::Router::endpoint).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Timeout {
+    /// the router's default timeout, or no timeout if the router has none
+    #[default]
+    Default,
+    /// no timeout, requests wait for their response forever
+    None,
+    /// requests time out after the given duration
+    After(Duration),
+}
+
+impl Timeout {
+    /// Resolves the timeout against the router's `default` timeout.
+    pub(crate) fn resolve(self, default: Option<Duration>) -> Option<Duration> {
+        match self {
+            Timeout::Default => default,
+            Timeout::None => None,
+            Timeout::After(duration) => Some(duration),
+        }
+    }
+}
+
+impl From<Duration> for Timeout {
+    fn from(duration: Duration) -> Self {
+        Timeout::After(duration)
+    }
+}
+
+/// `None` converts to [Timeout::Default], not to [Timeout::None].
+impl From<Option<Duration>> for Timeout {
+    fn from(duration: Option<Duration>) -> Self {
+        duration.map_or(Timeout::Default, Timeout::After)
+    }
+}
+
+/// Delivered to an [Endpoint] instead of a response once the worker handling
+/// its request panicked.
+#[derive(Debug)]
+pub(crate) struct WorkerPanicked;
+
+/// A response, or the panic of its worker, delivered to an [Endpoint].
+pub(crate) type Delivery<Response> = Result<Response, WorkerPanicked>;
+
+/// Submitted to the registration loop of the router, either a request
+/// submitted by an [Endpoint], or a health check ping, see
+/// [Router::health_check](crate::router::Router::health_check).
+#[derive(Debug)]
+pub struct Registration<Request, Response>(pub(crate) RegistrationKind<Request, Response>);
+
+/// The kinds of [Registration].
+// pings are rare, boxing the requests would allocate for every request
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub(crate) enum RegistrationKind<Request, Response> {
+    /// a request submitted by an [Endpoint]
+    Request(Submission<Request, Response>),
+    /// a health check ping identified by its UUID, dispatched to the managed
+    /// workers once registered
+    Ping(Uuid),
+}
+
+/// A request submitted to the router by an [Endpoint], along with its unique
+/// identifier, the sender its response is delivered to, the context propagated to the worker, the
+/// token cancelled once nobody waits for the response anymore, the timeout, the tenant of the
+/// endpoint, the slots it holds in the router's limits, and the time it is dispatched at, if it is timed.
+#[derive(Debug)]
+pub(crate) struct Submission<Request, Response> {
+    pub(crate) id: Uuid,
+    pub(crate) request: Request,
+    pub(crate) response_sender: Sender<Delivery<Response>>,
+    pub(crate) context: TraceContext,
+    pub(crate) cancellation: CancellationToken,
+    pub(crate) copies: usize,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) slots: Vec<InFlightSlot>,
+    pub(crate) tenant: Option<Arc<str>>,
+    pub(crate) not_before: Option<Instant>,
+    pub(crate) dispatched_at: Option<DispatchedAt>,
+}
+
 pub struct Endpoint<Request, Response> {
-    registration_sender: Sender<(Request, Sender<Response>)>,
+    registration_sender: Sender<Registration<Request, Response>>,
     timeout_interval: Option<std::time::Duration>,
+    id_generator: IdGenerator,
+    load_shedding: Option<LoadShedding<Request>>,
+    in_flight_limits: Vec<InFlightLimit>,
+    tenancy: Option<Tenancy<Request>>,
+    throttle: Option<KeyThrottle<Request>>,
+    concurrency_limit: Option<ConcurrencyLimit>,
+    concurrency_mode: ConcurrencyMode,
+    workers: Option<Arc<AtomicUsize>>,
+    intake: Option<Arc<Intake>>,
+    pause_mode: PauseMode,
+    fallback: Option<Fallback<Request, Response>>,
+    validator: Option<Validator<Request>>,
+    recording: Option<Arc<dyn Recording<Request, Response>>>,
+    deregister: Option<Deregister>,
+    report_timeout: Option<ReportTimeout>,
+}
+
+/// Removes a pending request from the router, used when nobody waits for its
+/// response anymore.
+pub(crate) type Deregister = Arc<dyn Fn(&Uuid) + Send + Sync>;
+
+/// Reports a request to the router once its endpoint timed out.
+pub(crate) type ReportTimeout = Arc<dyn Fn(&Uuid) + Send + Sync>;
+
+/// Cancels a registered request and removes it from the router when dropped
+/// before being disarmed, e.g. because the future awaiting the response was
+/// dropped. Holds the slots of the request in the concurrency limits of its
+/// endpoint and of its key until dropped.
+pub(crate) struct PendingGuard {
+    /// UUID of the request
+    id: Uuid,
+    /// cancelled once nobody waits for the response anymore
+    cancellation: CancellationToken,
+    /// removes the request from the router
+    deregister: Option<Deregister>,
+    /// reports the request to the router once it timed out
+    report_timeout: Option<ReportTimeout>,
+    /// whether the guard still acts when dropped
+    armed: bool,
+    /// the slot of the request in the concurrency limit of its endpoint,
+    /// released once the endpoint stops waiting for the response, whether it
+    /// was received, the request timed out or the future was dropped
+    _concurrency_permit: Option<OwnedSemaphorePermit>,
+    /// the slot of the request in the concurrency limit of its key, released
+    /// like the slot in the concurrency limit of its endpoint
+    _throttle_slot: Option<ThrottleSlot>,
+}
+
+impl PendingGuard {
+    /// Cancels the request once its endpoint timed out, reporting the
+    /// timeout to the router.
+    pub(crate) fn time_out(&self) {
+        self.cancellation.cancel();
+        if let Some(report_timeout) = &self.report_timeout {
+            report_timeout(&self.id);
+        }
+    }
+    /// Disarms the guard, once the responses of the request were received or
+    /// receiving them failed.
+    pub(crate) fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl fmt::Debug for PendingGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingGuard")
+            .field("id", &self.id)
+            .field("armed", &self.armed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        if self.armed {
+            // cancelling first makes the registration loop drop a request
+            // registered after it was deregistered
+            self.cancellation.cancel();
+            if let Some(deregister) = &self.deregister {
+                deregister(&self.id);
+            }
+        }
+    }
+}
+
+/// Router-level load shedding policy applied by endpoints before submitting
+/// a request.
+pub(crate) struct LoadShedding<Request> {
+    /// number of queued requests at which new requests are rejected
+    pub(crate) high_watermark: usize,
+    /// the router's request queues, used to observe the request queue depth
+    pub(crate) queues: RequestQueues<Request>,
+}
+
+/// Tenant an endpoint submits requests for, along with the router's tenant
+/// queues whose limits endpoints apply before submitting a request.
+pub(crate) struct Tenancy<Request> {
+    /// key of the tenant
+    pub(crate) tenant: Arc<str>,
+    /// the router's tenant queues, used to observe the tenant's queue depth
+    pub(crate) queues: Arc<TenantQueues<Request>>,
+}
+
+/// What happens to the requests of an [Endpoint] exceeding its concurrency
+/// limit, see [Endpoint::with_concurrency_mode].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConcurrencyMode {
+    /// requests exceeding the limit are rejected with
+    /// [EndpointError::ConcurrencyLimit]
+    #[default]
+    Reject,
+    /// requests exceeding the limit wait for a slot, the wait counts towards
+    /// the timeout of the request, failing with [EndpointError::Timeout]
+    /// once it elapsed
+    Wait,
+}
+
+/// Limit of the number of in-flight requests of an [Endpoint] and its
+/// clones, see [Endpoint::with_concurrency_limit].
+#[derive(Debug, Clone)]
+struct ConcurrencyLimit {
+    /// maximum number of in-flight requests
+    max: usize,
+    /// holds a permit for every slot left
+    semaphore: Arc<Semaphore>,
+}
+
+/// Router-level or tenant-level limit of the number of in-flight requests,
+/// enforced by endpoints before submitting a request.
+#[derive(Debug, Clone)]
+pub(crate) struct InFlightLimit {
+    /// maximum number of in-flight requests
+    max: usize,
+    /// number of requests holding an [InFlightSlot]
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl InFlightLimit {
+    /// Creates a new `InFlightLimit` of `max` requests.
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            max,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+    /// Reserves a slot for a new request, or returns `None` if the limit was
+    /// reached.
+    fn try_acquire(&self) -> Option<InFlightSlot> {
+        self.in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |in_flight| {
+                (in_flight < self.max).then_some(in_flight + 1)
+            })
+            .ok()
+            .map(|_| InFlightSlot(self.in_flight.clone()))
+    }
+}
+
+/// Counts a request towards an [InFlightLimit] until dropped along
+/// with the request's registration, or with its pending entry in the router.
+#[derive(Debug)]
+pub(crate) struct InFlightSlot(Arc<AtomicUsize>);
+
+impl Drop for InFlightSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Responds in place of the workers to requests that timed out, see
+/// [Endpoint::with_fallback].
+pub(crate) struct Fallback<Request, Response> {
+    /// copies requests before they are submitted, to fall back on them
+    copy_request: fn(&Request) -> Request,
+    /// produces the response of a request that timed out
+    respond: Arc<dyn Fn(Request) -> BoxFuture<'static, Response> + Send + Sync>,
+}
+
+impl<Request, Response> Fallback<Request, Response>
+where
+    Request: Clone,
+{
+    /// Creates a new `Fallback` responding with the response of `fallback`.
+    pub(crate) fn new(fallback: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self
+    where
+        Response: Send + 'static,
+    {
+        Self::new_async(move |request| std::future::ready(fallback(&request)))
+    }
+    /// Creates a new `Fallback` responding with the response `fallback`
+    /// resolves to.
+    pub(crate) fn new_async<F>(fallback: impl Fn(Request) -> F + Send + Sync + 'static) -> Self
+    where
+        F: Future<Output = Response> + Send + 'static,
+    {
+        Self {
+            copy_request: Request::clone,
+            respond: Arc::new(move |request| fallback(request).boxed()),
+        }
+    }
+}
+
+// implemented by hand, deriving would require `Request` and `Response` to
+// implement the trait as well.
+impl<Request, Response> Clone for Fallback<Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            copy_request: self.copy_request,
+            respond: self.respond.clone(),
+        }
+    }
+}
+
+impl<Request, Response> fmt::Debug for Fallback<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fallback").finish_non_exhaustive()
+    }
+}
+
+/// Validates a request, resolving to the reason it was rejected for, if any.
+type Validate<Request> =
+    Arc<dyn Fn(&Request) -> BoxFuture<'static, Result<(), RejectReason>> + Send + Sync>;
+
+/// Validates requests before they are registered with the router, see
+/// [Router::with_validator](crate::router::Router::with_validator).
+pub(crate) struct Validator<Request>(Validate<Request>);
+
+impl<Request> Validator<Request> {
+    /// Creates a new `Validator` validating requests with `validator`.
+    pub(crate) fn new(
+        validator: impl Fn(&Request) -> Result<(), RejectReason> + Send + Sync + 'static,
+    ) -> Self {
+        Self::new_async(move |request| std::future::ready(validator(request)))
+    }
+    /// Creates a new `Validator` validating requests with the future
+    /// `validator` returns.
+    pub(crate) fn new_async<F>(validator: impl Fn(&Request) -> F + Send + Sync + 'static) -> Self
+    where
+        F: Future<Output = Result<(), RejectReason>> + Send + 'static,
+    {
+        Self(Arc::new(move |request| validator(request).boxed()))
+    }
+}
+
+impl<Request> Clone for Validator<Request> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<Request> fmt::Debug for Validator<Request> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Validator").finish_non_exhaustive()
+    }
+}
+
+impl<Request> Clone for LoadShedding<Request> {
+    fn clone(&self) -> Self {
+        Self {
+            high_watermark: self.high_watermark,
+            queues: self.queues.clone(),
+        }
+    }
+}
+
+impl<Request> Clone for Tenancy<Request> {
+    fn clone(&self) -> Self {
+        Self {
+            tenant: self.tenant.clone(),
+            queues: self.queues.clone(),
+        }
+    }
+}
+
+// implemented by hand, deriving would require `Request` and `Response` to
+// implement the traits as well.
+impl<Request, Response> Clone for Endpoint<Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            registration_sender: self.registration_sender.clone(),
+            timeout_interval: self.timeout_interval,
+            id_generator: self.id_generator.clone(),
+            load_shedding: self.load_shedding.clone(),
+            in_flight_limits: self.in_flight_limits.clone(),
+            tenancy: self.tenancy.clone(),
+            throttle: self.throttle.clone(),
+            concurrency_limit: self.concurrency_limit.clone(),
+            concurrency_mode: self.concurrency_mode,
+            workers: self.workers.clone(),
+            intake: self.intake.clone(),
+            pause_mode: self.pause_mode,
+            fallback: self.fallback.clone(),
+            validator: self.validator.clone(),
+            recording: self.recording.clone(),
+            deregister: self.deregister.clone(),
+            report_timeout: self.report_timeout.clone(),
+        }
+    }
+}
+
+impl<Request, Response> fmt::Debug for Endpoint<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Endpoint")
+            .field("registration_sender", &self.registration_sender)
+            .field("timeout_interval", &self.timeout_interval)
+            .field("id_generator", &self.id_generator)
+            .field(
+                "high_watermark",
+                &self
+                    .load_shedding
+                    .as_ref()
+                    .map(|policy| policy.high_watermark),
+            )
+            .field(
+                "max_in_flight",
+                &self
+                    .in_flight_limits
+                    .iter()
+                    .map(|limit| limit.max)
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "tenant",
+                &self.tenancy.as_ref().map(|tenancy| &tenancy.tenant),
+            )
+            .field("throttle", &self.throttle)
+            .field(
+                "concurrency_limit",
+                &self.concurrency_limit.as_ref().map(|limit| limit.max),
+            )
+            .field("concurrency_mode", &self.concurrency_mode)
+            .field("pause_mode", &self.pause_mode)
+            .field("fallback", &self.fallback.is_some())
+            .field("validator", &self.validator.is_some())
+            .field("recording", &self.recording.is_some())
+            .finish()
+    }
 }
 
 impl<Request, Response> Endpoint<Request, Response>
@@ -42,22 +643,609 @@ where
     Response: Send + 'static,
 {
     pub fn new(
-        registration_sender: Sender<(Request, Sender<Response>)>,
+        registration_sender: Sender<Registration<Request, Response>>,
         timeout_interval: Option<std::time::Duration>,
     ) -> Self {
         Self {
             registration_sender,
             timeout_interval,
+            id_generator: IdGenerator::default(),
+            load_shedding: None,
+            in_flight_limits: Vec::new(),
+            tenancy: None,
+            throttle: None,
+            concurrency_limit: None,
+            concurrency_mode: ConcurrencyMode::default(),
+            workers: None,
+            intake: None,
+            pause_mode: PauseMode::default(),
+            fallback: None,
+            validator: None,
+            recording: None,
+            deregister: None,
+            report_timeout: None,
         }
     }
+    /// Sets the function removing requests from the router once the future
+    /// awaiting their response is dropped.
+    pub(crate) fn with_deregistration(mut self, deregister: Deregister) -> Self {
+        self.deregister = Some(deregister);
+        self
+    }
+    /// Sets the function reporting requests to the router once they timed
+    /// out.
+    pub(crate) fn with_timeout_reporting(mut self, report_timeout: ReportTimeout) -> Self {
+        self.report_timeout = Some(report_timeout);
+        self
+    }
+    /// Sets the generator of the identifiers assigned to submitted requests.
+    pub(crate) fn with_id_generator(mut self, id_generator: IdGenerator) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
     pub async fn handle_request(&self, request: Request) -> Result<Response, EndpointError> {
-        let (response_sender, response_receiver) = bounded(100);
+        self.handle_request_with_context(request, TraceContext::captured())
+            .await
+    }
+    /// Handles a request like [Endpoint::handle_request], propagating the
+    /// given [TraceContext] to the worker instead of the captured one.
+    ///
+    /// If the request times out, its cancellation token is cancelled so that
+    /// workers can abandon it, see
+    /// [Router::cancellation_token](crate::router::Router::cancellation_token).
+    /// If the returned future is dropped before the response arrived, the
+    /// request is additionally removed from the router right away.
+    pub async fn handle_request_with_context(
+        &self,
+        request: Request,
+        context: TraceContext,
+    ) -> Result<Response, EndpointError> {
+        self.submit(self.id_generator.generate(), request, context, None, None)
+            .await
+    }
+    /// Handles a request like [Endpoint::handle_request], annotating the
+    /// response with the time the request spent in the router.
+    ///
+    /// # Returns
+    ///
+    /// Returns the response along with the time from the submission of the
+    /// request until the router dispatched it to the workers, the time from
+    /// its dispatch until its response was received, and the total latency,
+    /// see [Timed]. Fallback responses count the time until the request
+    /// timed out and the fallback responded as processing time.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn handle_request_timed(
+        &self,
+        request: Request,
+    ) -> Result<Timed<Response>, EndpointError> {
+        let dispatched_at = DispatchedAt::default();
+        let submitted_at = Instant::now();
+        let response = self
+            .submit(
+                self.id_generator.generate(),
+                request,
+                TraceContext::captured(),
+                None,
+                Some(dispatched_at.clone()),
+            )
+            .await?;
+        Ok(Timed::new(response, submitted_at, dispatched_at.get()))
+    }
+    /// Handles a request like [Endpoint::handle_request], dispatched to the
+    /// workers once `delay` has passed, see [Endpoint::handle_request_at].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn handle_request_after(
+        &self,
+        delay: Duration,
+        request: Request,
+    ) -> Result<Response, EndpointError> {
+        self.handle_request_at(Instant::now() + delay, request)
+            .await
+    }
+    /// Handles a request like [Endpoint::handle_request], dispatched to the
+    /// workers at `at`.
+    ///
+    /// The request is registered with the router right away, counting
+    /// towards the in-flight limits, and held by the router in a timer wheel
+    /// until `at`. The timeout of the endpoint is measured from `at`.
+    /// Requests scheduled in the past are dispatched right away. Dropping
+    /// the returned future cancels the request, which is then never
+    /// dispatched.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn handle_request_at(
+        &self,
+        at: Instant,
+        request: Request,
+    ) -> Result<Response, EndpointError> {
+        let id = self.id_generator.generate();
+        self.submit(id, request, TraceContext::captured(), Some(at), None)
+            .await
+    }
+    /// Handles a request like [Endpoint::handle_request], exposing the UUID
+    /// assigned to it before the response is awaited.
+    ///
+    /// # Returns
+    ///
+    /// Returns the UUID of the request, which workers receive along with the
+    /// request, and the future resolving to the response. The request is only
+    /// submitted once the future is polled.
+    pub fn handle_request_with_id(
+        &self,
+        request: Request,
+    ) -> (
+        Uuid,
+        impl Future<Output = Result<Response, EndpointError>> + '_,
+    ) {
+        let uuid = self.id_generator.generate();
+        (
+            uuid,
+            self.submit(uuid, request, TraceContext::captured(), None, None),
+        )
+    }
+    /// Handles a request like [Endpoint::handle_request_with_context], under
+    /// the given UUID instead of a generated one.
+    pub(crate) async fn handle_request_as(
+        &self,
+        id: Uuid,
+        request: Request,
+        context: TraceContext,
+    ) -> Result<Response, EndpointError> {
+        self.submit(id, request, context, None, None).await
+    }
+    /// Generates the UUID of a new request.
+    pub(crate) fn generate_id(&self) -> Uuid {
+        self.id_generator.generate()
+    }
+    /// Sets the load shedding policy of the router.
+    pub(crate) fn with_load_shedding(mut self, load_shedding: LoadShedding<Request>) -> Self {
+        self.load_shedding = Some(load_shedding);
+        self
+    }
+    /// Adds a limit of the number of in-flight requests, of the router or of
+    /// the endpoint's tenant.
+    pub(crate) fn with_in_flight_limit(mut self, in_flight_limit: InFlightLimit) -> Self {
+        self.in_flight_limits.push(in_flight_limit);
+        self
+    }
+    /// Sets the recorder of the router, recording every request submitted
+    /// through [Endpoint::submit].
+    pub(crate) fn with_recording(
+        mut self,
+        recording: Arc<dyn Recording<Request, Response>>,
+    ) -> Self {
+        self.recording = Some(recording);
+        self
+    }
+    /// Sets the tenant the endpoint submits requests for.
+    pub(crate) fn with_tenancy(mut self, tenancy: Tenancy<Request>) -> Self {
+        self.tenancy = Some(tenancy);
+        self
+    }
+    /// Responds with the response of `fallback` to requests that timed out,
+    /// instead of failing with [EndpointError::Timeout], replacing the
+    /// fallback of the router, see
+    /// [Router::with_fallback](crate::router::Router::with_fallback).
+    ///
+    /// Requests are copied before being submitted, to call `fallback` with
+    /// them. Only requests handled with [Endpoint::handle_request] and its
+    /// variants fall back, scattered requests still time out. Recorders
+    /// record the timeout rather than the fallback response.
+    pub fn with_fallback(
+        mut self,
+        fallback: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> Self
+    where
+        Request: Clone,
+    {
+        self.fallback = Some(Fallback::new(fallback));
+        self
+    }
+    /// Responds with the response `fallback` resolves to to requests that
+    /// timed out, like [Endpoint::with_fallback].
+    pub fn with_async_fallback<F>(
+        mut self,
+        fallback: impl Fn(Request) -> F + Send + Sync + 'static,
+    ) -> Self
+    where
+        Request: Clone,
+        F: Future<Output = Response> + Send + 'static,
+    {
+        self.fallback = Some(Fallback::new_async(fallback));
+        self
+    }
+    /// Sets the fallback of the router.
+    pub(crate) fn with_fallback_of(mut self, fallback: Fallback<Request, Response>) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+    /// Sets the validator of the router.
+    pub(crate) fn with_validator(mut self, validator: Validator<Request>) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+    /// Limits the number of in-flight requests of the endpoint and its
+    /// clones to `max`, so a single consumer of a shared router, e.g. one
+    /// HTTP route, can not occupy all of its capacity. Requests exceeding the
+    /// limit fail with [EndpointError::ConcurrencyLimit], or wait for a slot,
+    /// see [Endpoint::with_concurrency_mode].
+    ///
+    /// A request holds its slot until the call submitting it returned,
+    /// whether its response was received, it timed out or it failed, or until
+    /// the future of the call was dropped. Unlike the in-flight limits of the
+    /// router, a request whose worker never answers does not keep its slot
+    /// once it timed out.
+    /// The limit applies independently of the limits and the load shedding of
+    /// the router.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    pub fn with_concurrency_limit(mut self, max: usize) -> Self {
+        assert!(max > 0, "the concurrency limit must be positive");
+        self.concurrency_limit = Some(ConcurrencyLimit {
+            max,
+            semaphore: Arc::new(Semaphore::new(max)),
+        });
+        self
+    }
+    /// Sets what happens to the requests exceeding the concurrency limit of
+    /// the endpoint, see [Endpoint::with_concurrency_limit]. Requests are
+    /// rejected by default.
+    pub fn with_concurrency_mode(mut self, mode: ConcurrencyMode) -> Self {
+        self.concurrency_mode = mode;
+        self
+    }
+    /// Sets the per-key throttling of the router.
+    pub(crate) fn with_throttle(mut self, throttle: KeyThrottle<Request>) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+    /// Sets the number of running workers of the router, requests fail with
+    /// [EndpointError::NoWorkers] while it is zero.
+    pub(crate) fn with_worker_count(mut self, workers: Arc<AtomicUsize>) -> Self {
+        self.workers = Some(workers);
+        self
+    }
+    /// Sets the intake of the router and what happens to the requests
+    /// submitted while it is paused.
+    pub(crate) fn with_intake(mut self, intake: Arc<Intake>, mode: PauseMode) -> Self {
+        self.intake = Some(intake);
+        self.pause_mode = mode;
+        self
+    }
+    /// Returns whether the number of requests queued in the router's
+    /// registration and request channels reached the high watermark, or the
+    /// queue of the endpoint's tenant is full.
+    fn is_overloaded(&self) -> bool {
+        self.load_shedding.as_ref().is_some_and(|policy| {
+            self.registration_sender.len() + policy.queues.len() >= policy.high_watermark
+        }) || self
+            .tenancy
+            .as_ref()
+            .is_some_and(|tenancy| tenancy.queues.is_full(&tenancy.tenant))
+    }
+    /// Handles a request like [Endpoint::handle_request], for routers whose
+    /// workers respond with a `Result`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the worker's successful response, or
+    /// [EndpointError::Rejected] holding the worker's error.
+    pub async fn try_handle_request<T, E>(&self, request: Request) -> Result<T, EndpointError<E>>
+    where
+        Response: Into<Result<T, E>>,
+    {
+        match self.handle_request(request).await {
+            Ok(response) => response.into().map_err(EndpointError::Rejected),
+            Err(err) => Err(err.widen()),
+        }
+    }
+    /// Dispatches `request` to `n` workers and gathers their responses.
+    ///
+    /// The router dispatches `n` copies of the request under a single UUID
+    /// and keeps the request pending until all `n` correlated responses
+    /// arrived. The timeout of the endpoint applies to gathering all of them.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `n` responses in the order they arrived.
+    pub async fn scatter(
+        &self,
+        request: Request,
+        n: usize,
+    ) -> Result<Vec<Response>, EndpointError> {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let id = self.id_generator.generate();
+        let deadline = self.deadline(None);
+        let (response_receiver, mut guard) = self
+            .register(
+                id,
+                request,
+                TraceContext::captured(),
+                n,
+                None,
+                None,
+                deadline,
+            )
+            .await?;
+        let responses = self.receive(&response_receiver, &guard, n, deadline).await;
+        guard.disarm();
+        responses
+    }
+    /// Submits a request under the given UUID, to be dispatched not before
+    /// `not_before` if set, and awaits its response, falling back on the
+    /// endpoint's fallback if it times out. The dispatch time of the request
+    /// is stamped into `dispatched_at` if set.
+    async fn submit(
+        &self,
+        id: Uuid,
+        request: Request,
+        context: TraceContext,
+        not_before: Option<Instant>,
+        dispatched_at: Option<DispatchedAt>,
+    ) -> Result<Response, EndpointError> {
+        let Some(fallback) = &self.fallback else {
+            return self
+                .submit_recorded(id, request, context, not_before, dispatched_at)
+                .await;
+        };
+        let copy = (fallback.copy_request)(&request);
+        match self
+            .submit_recorded(id, request, context, not_before, dispatched_at)
+            .await
+        {
+            Err(EndpointError::Timeout(_)) => Ok((fallback.respond)(copy).await),
+            result => result,
+        }
+    }
+    /// Submits a request like [Endpoint::submit], without falling back,
+    /// recording it if the router has a recorder.
+    async fn submit_recorded(
+        &self,
+        id: Uuid,
+        request: Request,
+        context: TraceContext,
+        not_before: Option<Instant>,
+        dispatched_at: Option<DispatchedAt>,
+    ) -> Result<Response, EndpointError> {
+        let Some(recording) = &self.recording else {
+            return self
+                .submit_unrecorded(id, request, context, not_before, dispatched_at)
+                .await;
+        };
+        let recorded = recording.copy_request(&request);
+        let submitted_at = SystemTime::now();
+        let result = self
+            .submit_unrecorded(id, request, context, not_before, dispatched_at)
+            .await;
+        recording.record(id, recorded, &result, submitted_at, self.timeout_interval);
+        result
+    }
+    /// Submits a request like [Endpoint::submit_recorded], without recording
+    /// it.
+    async fn submit_unrecorded(
+        &self,
+        id: Uuid,
+        request: Request,
+        context: TraceContext,
+        not_before: Option<Instant>,
+        dispatched_at: Option<DispatchedAt>,
+    ) -> Result<Response, EndpointError> {
+        let deadline = self.deadline(not_before);
+        let (response_receiver, mut guard) = self
+            .register(id, request, context, 1, not_before, dispatched_at, deadline)
+            .await?;
+        let responses = self.receive(&response_receiver, &guard, 1, deadline).await;
+        guard.disarm();
+        Ok(responses?.remove(0))
+    }
+    /// Registers a request, to be dispatched as `copies` copies not before
+    /// `not_before` if set, with the router, which stamps the dispatch time
+    /// into `dispatched_at` if set.
+    ///
+    /// Fails with [EndpointError::Invalid] without submitting the request if
+    /// the validator of the router rejects it, with [EndpointError::Paused] if
+    /// the router is paused and rejects requests meanwhile, otherwise the
+    /// request waits for the router to be resumed until the `deadline` of the
+    /// request, see [PauseMode], with [EndpointError::NoWorkers] if no workers are attached to the router,
+    /// with
+    /// [EndpointError::Overloaded] if the router or the queue of the endpoint's tenant is overloaded, or
+    /// with [EndpointError::TooManyInFlight] if the in-flight limit of the
+    /// router or of the tenant was reached. Requests exceeding the concurrency limit of the
+    /// endpoint fail with [EndpointError::ConcurrencyLimit], or wait for a slot until the `deadline`
+    /// of the request, see [ConcurrencyMode]. Requests exceeding the limits of their key
+    /// fail with [EndpointError::Throttled], or wait for the limits to admit
+    /// them until the `deadline` of the request, see [KeyThrottle].
+    ///
+    /// # Returns
+    ///
+    /// Returns the receiver the responses are delivered to and the
+    /// [PendingGuard] of the request, to be disarmed once the responses were
+    /// received. The request is cancelled and deregistered if the returned
+    /// future or the guard is dropped.
+    #[allow(clippy::too_many_arguments)]
+    async fn register(
+        &self,
+        id: Uuid,
+        request: Request,
+        context: TraceContext,
+        copies: usize,
+        not_before: Option<Instant>,
+        dispatched_at: Option<DispatchedAt>,
+        deadline: Deadline,
+    ) -> Result<(Receiver<Delivery<Response>>, PendingGuard), EndpointError> {
+        self.register_with(
+            id,
+            request,
+            context,
+            copies,
+            not_before,
+            dispatched_at,
+            deadline,
+            bounded(copies),
+        )
+        .await
+    }
+    /// Registers a request like [Endpoint::register], delivering its
+    /// responses to the given channel.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn register_with(
+        &self,
+        id: Uuid,
+        request: Request,
+        context: TraceContext,
+        copies: usize,
+        not_before: Option<Instant>,
+        dispatched_at: Option<DispatchedAt>,
+        deadline: Deadline,
+        (response_sender, response_receiver): (
+            Sender<Delivery<Response>>,
+            Receiver<Delivery<Response>>,
+        ),
+    ) -> Result<(Receiver<Delivery<Response>>, PendingGuard), EndpointError> {
+        if let Some(validator) = &self.validator {
+            // the validation future doesn't borrow the request
+            let validation = (validator.0)(&request);
+            validation.await.map_err(EndpointError::Invalid)?;
+        }
+        if let Some(intake) = self.intake.as_ref().filter(|intake| intake.is_paused()) {
+            match self.pause_mode {
+                PauseMode::Reject => return Err(EndpointError::Paused),
+                PauseMode::Wait => deadline.wait(intake.resumed()).await?,
+            }
+        }
+        // without workers the request would only sit until it times out,
+        // requests to a router shut down fail with `RequestSend` instead
+        if self
+            .workers
+            .as_ref()
+            .is_some_and(|workers| workers.load(Ordering::SeqCst) == 0)
+            && !self.registration_sender.is_closed()
+        {
+            return Err(EndpointError::NoWorkers);
+        }
+        if self.is_overloaded() {
+            return Err(EndpointError::Overloaded);
+        }
+        let concurrency_permit = match &self.concurrency_limit {
+            Some(limit) => Some(self.acquire_concurrency_permit(limit, deadline).await?),
+            None => None,
+        };
+        let throttle_slot = match &self.throttle {
+            Some(throttle) => {
+                let acquire = throttle.acquire(throttle.key(&request));
+                Some(
+                    deadline
+                        .wait(acquire)
+                        .await?
+                        .ok_or(EndpointError::Throttled)?,
+                )
+            }
+            None => None,
+        };
+        // slots acquired before a limit was reached are released on return
+        let slots = self
+            .in_flight_limits
+            .iter()
+            .map(|limit| limit.try_acquire().ok_or(EndpointError::TooManyInFlight))
+            .collect::<Result<Vec<_>, _>>()?;
+        let cancellation = CancellationToken::new();
+        let mut guard = PendingGuard {
+            id,
+            cancellation: cancellation.clone(),
+            deregister: self.deregister.clone(),
+            report_timeout: self.report_timeout.clone(),
+            armed: true,
+            _concurrency_permit: concurrency_permit,
+            _throttle_slot: throttle_slot,
+        };
         let registration_sender = self.registration_sender.clone();
-        registration_sender.send((request, response_sender)).await?;
-        let response = match self.timeout_interval {
-            Some(interval) => timeout(interval, response_receiver.recv()).await?,
-            None => response_receiver.recv().await,
+        let sent = registration_sender
+            .send(Registration(RegistrationKind::Request(Submission {
+                id,
+                request,
+                response_sender,
+                context,
+                cancellation,
+                copies,
+                timeout: self.timeout_interval,
+                slots,
+                tenant: self.tenancy.as_ref().map(|tenancy| tenancy.tenant.clone()),
+                not_before,
+                dispatched_at,
+            })))
+            .await;
+        if let Err(err) = sent {
+            guard.disarm();
+            return Err(err.into());
+        }
+        Ok((response_receiver, guard))
+    }
+    /// Reserves a slot of the endpoint's concurrency `limit`, waiting for one
+    /// until the `deadline` of the request if its [ConcurrencyMode] is
+    /// [ConcurrencyMode::Wait].
+    async fn acquire_concurrency_permit(
+        &self,
+        limit: &ConcurrencyLimit,
+        deadline: Deadline,
+    ) -> Result<OwnedSemaphorePermit, EndpointError> {
+        let semaphore = limit.semaphore.clone();
+        match self.concurrency_mode {
+            ConcurrencyMode::Reject => semaphore
+                .try_acquire_owned()
+                .map_err(|_| EndpointError::ConcurrencyLimit),
+            ConcurrencyMode::Wait => {
+                let permit = deadline.wait(semaphore.acquire_owned()).await?;
+                // the semaphore is never closed
+                permit.map_err(|_| EndpointError::ConcurrencyLimit)
+            }
+        }
+    }
+    /// Returns the [Deadline] of a request submitted now, its timeout is
+    /// measured from `not_before` if the request is scheduled for later.
+    pub(crate) fn deadline(&self, not_before: Option<Instant>) -> Deadline {
+        #[cfg(not(target_arch = "wasm32"))]
+        let submitted_at = not_before.map_or_else(TimerInstant::now, |not_before| {
+            TimerInstant::from_std(not_before).max(TimerInstant::now())
+        });
+        // requests can't be scheduled on `wasm32` targets
+        #[cfg(target_arch = "wasm32")]
+        let submitted_at = {
+            let _ = not_before;
+            TimerInstant::now()
         };
-        response.map_err(|e| e.into())
+        Deadline(
+            self.timeout_interval
+                .map(|interval| submitted_at + interval),
+        )
+    }
+    /// Receives `count` responses until the `deadline` of the request,
+    /// cancelling the request and reporting it to the router if it times
+    /// out, see [PendingGuard::time_out], or failing with
+    /// [EndpointError::WorkerPanicked] once its worker panicked.
+    pub(crate) async fn receive(
+        &self,
+        response_receiver: &Receiver<Delivery<Response>>,
+        guard: &PendingGuard,
+        count: usize,
+        deadline: Deadline,
+    ) -> Result<Vec<Response>, EndpointError> {
+        let gather = async {
+            let mut responses = Vec::with_capacity(count);
+            for _ in 0..count {
+                let response = response_receiver.recv().await?;
+                responses.push(response.map_err(|WorkerPanicked| EndpointError::WorkerPanicked)?);
+            }
+            Ok(responses)
+        };
+        match deadline.wait(gather).await {
+            Ok(responses) => responses,
+            Err(elapsed) => {
+                guard.time_out();
+                Err(elapsed.into())
+            }
+        }
     }
 }
@@ -0,0 +1,133 @@
+//! # Envelope Module
+//!
+//! This module provides the [Envelope] struct wrapping a request along with
+//! its [Metadata], so transport concerns don't have to be added to the request
+//! type itself.
+//!
+//! ## Overview
+//!
+//! A router whose request type is an `Envelope<Request>` preserves the
+//! metadata of every request and hands it to the workers along with the
+//! request. Endpoints of such a router attach the metadata per call with
+//! [Endpoint::handle_request_with_metadata], which also stamps the time the
+//! request was submitted at.
+use std::{collections::BTreeMap, time::SystemTime};
+
+use crate::endpoint::{Endpoint, EndpointError};
+
+/// Metadata travelling alongside a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// tenant the request is submitted on behalf of
+    pub tenant_id: Option<String>,
+    /// authenticated subject submitting the request
+    pub auth_subject: Option<String>,
+    /// identifier of the trace the request belongs to
+    pub trace_id: Option<String>,
+    /// time the request was submitted at
+    pub submitted_at: Option<SystemTime>,
+    /// any further application defined headers
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    /// Creates new, empty `Metadata`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the tenant the request is submitted on behalf of.
+    pub fn tenant_id(mut self, tenant_id: impl Into<String>) -> Self {
+        self.tenant_id = Some(tenant_id.into());
+        self
+    }
+    /// Sets the authenticated subject submitting the request.
+    pub fn auth_subject(mut self, auth_subject: impl Into<String>) -> Self {
+        self.auth_subject = Some(auth_subject.into());
+        self
+    }
+    /// Sets the identifier of the trace the request belongs to.
+    pub fn trace_id(mut self, trace_id: impl Into<String>) -> Self {
+        self.trace_id = Some(trace_id.into());
+        self
+    }
+    /// Sets the application defined header `key` to `value`.
+    pub fn header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// A request along with its [Metadata].
+///
+/// # Type Parameters
+/// - `Request`: the type of the wrapped request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope<Request> {
+    /// the wrapped request
+    pub request: Request,
+    /// the metadata of the request
+    pub metadata: Metadata,
+}
+
+impl<Request> Envelope<Request> {
+    /// Creates a new `Envelope` wrapping `request` with empty metadata.
+    pub fn new(request: Request) -> Self {
+        Self::with_metadata(request, Metadata::default())
+    }
+    /// Creates a new `Envelope` wrapping `request` with `metadata`.
+    pub fn with_metadata(request: Request, metadata: Metadata) -> Self {
+        Self { request, metadata }
+    }
+    /// Returns the wrapped request and its metadata.
+    pub fn into_parts(self) -> (Request, Metadata) {
+        (self.request, self.metadata)
+    }
+}
+
+impl<Request, Response> Endpoint<Envelope<Request>, Response>
+where
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    /// Handles `request` like [Endpoint::handle_request], wrapped in an
+    /// [Envelope] along with `metadata`.
+    ///
+    /// The submission time of the metadata is set to the current time, unless
+    /// it is already set.
+    pub async fn handle_request_with_metadata(
+        &self,
+        request: Request,
+        mut metadata: Metadata,
+    ) -> Result<Response, EndpointError> {
+        metadata.submitted_at.get_or_insert_with(SystemTime::now);
+        self.handle_request(Envelope::with_metadata(request, metadata))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Envelope, Metadata};
+    use crate::router::Router;
+
+    #[tokio::test]
+    async fn test_metadata_reaches_workers() {
+        let router: Router<Envelope<String>, String> = Router::default();
+        router.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, envelope)) = receiver.recv().await {
+                let (request, metadata) = envelope.into_parts();
+                assert!(metadata.submitted_at.is_some());
+                let tenant_id = metadata.tenant_id.unwrap_or_default();
+                let response = format!("{}:{}", tenant_id, request);
+                sender.send((uuid, response)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        let response = router
+            .endpoint(None)
+            .handle_request_with_metadata("ping".into(), Metadata::new().tenant_id("acme"))
+            .await;
+        assert_eq!(response, Ok("acme:ping".to_string()));
+    }
+}
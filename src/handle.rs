@@ -0,0 +1,197 @@
+//! # Handle Module
+//!
+//! This module provides the [RouterHandle] struct owning the tasks spawned
+//! for a [Router], see [Router::tokio_spawn_handle].
+//!
+//! ## Overview
+//!
+//! [Router::tokio_spawn] and [Router::tokio_spawn_workers] return join
+//! handles the caller has to keep track of, and the spawned tasks outlive the
+//! router. A [RouterHandle] owns the handles of the router loops and of its
+//! workers instead: dropping it stops the loops and aborts the workers,
+//! unless disabled with [RouterHandle::abort_on_drop], and
+//! [RouterHandle::await_all] waits for all of them to complete. The loops are
+//! stopped through a child of the router's shutdown token, so the token
+//! itself, which may be shared with other tasks through
+//! [RouterBuilder::shutdown_token](crate::builder::RouterBuilder::shutdown_token),
+//! is never cancelled by the handle.
+use std::future::Future;
+
+use crate::channel::{Receiver, Sender};
+use tokio::task::{JoinError, JoinHandle};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::router::Router;
+
+/// Owns the tasks spawned for a [Router], shutting the router down and
+/// aborting its workers once dropped.
+///
+/// # Type Parameters
+/// - `Request`: the request type of the router
+/// - `Response`: the response type of the router
+#[derive(Debug)]
+pub struct RouterHandle<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    /// the router the tasks were spawned for
+    router: Router<Request, Response>,
+    /// handle of the router loops task, taken by [RouterHandle::await_all]
+    loops: Option<JoinHandle<()>>,
+    /// stops the router loops once cancelled, a child of the router's
+    /// shutdown token
+    stop: CancellationToken,
+    /// handles of the worker tasks
+    workers: Vec<JoinHandle<()>>,
+    /// whether dropping the handle stops the tasks
+    abort_on_drop: bool,
+}
+
+impl<Request, Response> RouterHandle<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    /// Returns the router the tasks were spawned for.
+    pub fn router(&self) -> &Router<Request, Response> {
+        &self.router
+    }
+    /// Spawns `num_workers` workers running `worker_fn` like
+    /// [Router::tokio_spawn_workers], owning their handles.
+    pub fn spawn_workers<F>(
+        &mut self,
+        num_workers: usize,
+        worker_fn: impl Fn(Receiver<(Uuid, Request)>, Sender<(Uuid, Response)>) -> F,
+    ) -> &mut Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let workers = self.router.tokio_spawn_workers(num_workers, worker_fn);
+        self.manage(workers)
+    }
+    /// Takes ownership of the handles of workers spawned otherwise, e.g. with
+    /// [Router::spawn_worker_instances] or [Router::spawn_blocking_workers].
+    pub fn manage(&mut self, workers: impl IntoIterator<Item = JoinHandle<()>>) -> &mut Self {
+        self.workers.extend(workers);
+        self
+    }
+    /// Sets whether dropping the handle shuts the router down and aborts its
+    /// workers, `true` by default. With `false` the tasks keep running
+    /// detached once the handle is dropped.
+    pub fn abort_on_drop(&mut self, enabled: bool) -> &mut Self {
+        self.abort_on_drop = enabled;
+        self
+    }
+    /// Stops the router loops, closing the registration channel so further
+    /// requests fail with
+    /// [EndpointError::RequestSend](crate::endpoint::EndpointError::RequestSend),
+    /// and aborts the workers of the handle.
+    ///
+    /// Unlike [Router::shutdown], the router's shutdown token is not
+    /// cancelled.
+    pub fn abort(&self) {
+        self.stop.cancel();
+        for worker in &self.workers {
+            worker.abort();
+        }
+    }
+    /// Waits for the router loops and all workers to complete.
+    ///
+    /// The router loops complete once the router is shut down, the workers
+    /// once their worker functions return.
+    ///
+    /// # Returns
+    ///
+    /// Returns the error of the first task that panicked or was aborted, if
+    /// any, after all tasks completed.
+    pub async fn await_all(mut self) -> Result<(), JoinError> {
+        let mut result = Ok(());
+        let tasks = self.loops.take().into_iter().chain(self.workers.drain(..));
+        for task in tasks.collect::<Vec<_>>() {
+            if let (Err(err), Ok(())) = (task.await, &result) {
+                result = Err(err);
+            }
+        }
+        result
+    }
+}
+
+impl<Request, Response> Drop for RouterHandle<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    fn drop(&mut self) {
+        if self.abort_on_drop {
+            self.abort();
+        }
+    }
+}
+
+impl<Request, Response> Router<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    /// Spawns the router loops like [Router::tokio_spawn].
+    ///
+    /// # Returns
+    ///
+    /// Returns a [RouterHandle] owning the router loops task, workers are
+    /// spawned with [RouterHandle::spawn_workers].
+    pub fn tokio_spawn_handle(&self) -> RouterHandle<Request, Response> {
+        let stop = self.shutdown_token().child_token();
+        RouterHandle {
+            router: self.clone(),
+            loops: Some(self.tokio_spawn_until(stop.clone())),
+            stop,
+            workers: Vec::new(),
+            abort_on_drop: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{builder::RouterBuilder, endpoint::EndpointError, router::Router};
+    use std::time::Duration;
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn test_router_handle() {
+        let router: Router<u32, u32> = Router::default();
+        let mut handle = router.tokio_spawn_handle();
+        handle.spawn_workers(2, |receiver, sender| async move {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                sender.send((uuid, request + 1)).await.unwrap();
+            }
+        });
+        let endpoint = router.endpoint(None);
+        assert_eq!(endpoint.handle_request(1).await, Ok(2));
+        assert_eq!(router.stats().workers, 2);
+
+        drop(handle);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(router.stats().workers, 0);
+        assert_eq!(
+            endpoint.handle_request(1).await,
+            Err(EndpointError::RequestSend)
+        );
+
+        // the shutdown token shared with the caller is left alone
+        let token = CancellationToken::new();
+        let router: Router<u32, u32> = RouterBuilder::new().shutdown_token(token.clone()).build();
+        drop(router.tokio_spawn_handle());
+        assert!(!token.is_cancelled());
+
+        // workers returning on their own complete the handle once the router
+        // is shut down
+        let router: Router<u32, u32> = Router::default();
+        let mut handle = router.tokio_spawn_handle();
+        handle.spawn_workers(2, |_, _| async {});
+        router.shutdown();
+        assert!(handle.await_all().await.is_ok());
+    }
+}
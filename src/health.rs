@@ -0,0 +1,205 @@
+//! # Health Module
+//!
+//! This module provides the [HealthReport] struct returned by
+//! [Router::health_check](crate::router::Router::health_check), and the
+//! crate-private [HealthProbes] channels carrying synthetic pings through the
+//! router.
+//!
+//! ## Overview
+//!
+//! A health check submits a ping, identified by a UUID, to the registration
+//! channel of the router as an internal registration kind, so it waits behind
+//! the requests submitted before it and isn't received while the router is
+//! paused. Once registered, the registration loop dispatches the ping on a
+//! dedicated probe channel instead of the request channel, so no `Request`
+//! value has to be invented for it. Workers managed by the router (see
+//! [Router::spawn_worker_instances](crate::router::Router::spawn_worker_instances))
+//! only answer pings while no request is waiting for them, the answer travels
+//! back through the router's response loop to the waiting health check.
+//!
+//! The latency of a ping thus covers the whole path of a request: a blocked
+//! registration loop, or workers not keeping up with their backlog, delay the
+//! ping past the timeout of the health check, and the router is reported
+//! unhealthy.
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::channel::{bounded, Receiver, Sender};
+use scc::HashMap;
+use uuid::Uuid;
+
+/// Maximum number of pings waiting for a worker.
+const PROBE_CHANNEL_SIZE: usize = 16;
+
+/// Outcome of a health check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthReport {
+    /// whether a worker answered the ping within the timeout
+    pub answered: bool,
+    /// round trip time of the ping, if it was answered
+    pub latency: Option<Duration>,
+    /// number of running workers spawned by the router
+    pub workers: usize,
+}
+
+/// Channels carrying pings from health checks to workers and back.
+#[derive(Debug, Clone)]
+pub(crate) struct HealthProbes {
+    /// used by the registration loop to dispatch pings to workers
+    probe_sender: Sender<Uuid>,
+    /// used by workers to receive pings
+    probe_receiver: Receiver<Uuid>,
+    /// used by workers to answer pings
+    answer_sender: Sender<Uuid>,
+    /// used by the response loop to receive answers
+    answer_receiver: Receiver<Uuid>,
+    /// maps pending pings to the health check waiting for the answer
+    pending: Arc<HashMap<Uuid, Sender<()>>>,
+}
+
+impl HealthProbes {
+    pub(crate) fn new() -> Self {
+        let (probe_sender, probe_receiver) = bounded(PROBE_CHANNEL_SIZE);
+        let (answer_sender, answer_receiver) = bounded(PROBE_CHANNEL_SIZE);
+        Self {
+            probe_sender,
+            probe_receiver,
+            answer_sender,
+            answer_receiver,
+            pending: Arc::new(HashMap::new()),
+        }
+    }
+    /// Submits a ping with `submit` and waits up to `timeout` for a worker to
+    /// answer it.
+    ///
+    /// # Arguments
+    ///
+    /// - `timeout`: The time the ping has to be submitted and answered in.
+    /// - `submit`: Submits the ping identified by the given UUID to the
+    ///   registration channel, returning whether it was submitted.
+    ///
+    /// # Returns
+    ///
+    /// Returns the round trip time of the ping, or `None` if it wasn't
+    /// answered in time.
+    pub(crate) async fn ping<F, Fut>(&self, timeout: Duration, submit: F) -> Option<Duration>
+    where
+        F: FnOnce(Uuid) -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let uuid = Uuid::new_v4();
+        let (sender, receiver) = bounded(1);
+        let _ = self.pending.insert(uuid, sender);
+        let start = Instant::now();
+        let round_trip = async { submit(uuid).await && receiver.recv().await.is_ok() };
+        let answered = matches!(tokio::time::timeout(timeout, round_trip).await, Ok(true));
+        self.pending.remove(&uuid);
+        answered.then(|| start.elapsed())
+    }
+    /// Dispatches the registered ping identified by `uuid` to the workers,
+    /// called by the registration loop.
+    ///
+    /// # Behavior
+    ///
+    /// A full probe channel means workers aren't picking up pings, the ping
+    /// is dropped and its health check times out.
+    pub(crate) fn dispatch(&self, uuid: Uuid) {
+        let _ = self.probe_sender.try_send(uuid);
+    }
+    /// Returns the receiver workers receive pings on.
+    pub(crate) fn probes(&self) -> Receiver<Uuid> {
+        self.probe_receiver.clone()
+    }
+    /// Answers the ping identified by `uuid`, called by workers.
+    pub(crate) async fn answer(&self, uuid: Uuid) {
+        let _ = self.answer_sender.send(uuid).await;
+    }
+    /// Returns the receiver the response loop receives answers on.
+    pub(crate) fn answers(&self) -> Receiver<Uuid> {
+        self.answer_receiver.clone()
+    }
+    /// Delivers the answer of the ping identified by `uuid` to its health
+    /// check, called by the response loop.
+    pub(crate) fn deliver(&self, uuid: Uuid) {
+        if let Some((_, sender)) = self.pending.remove(&uuid) {
+            let _ = sender.try_send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{router::Router, worker::Worker};
+    use std::time::Duration;
+
+    struct Echo;
+
+    impl Worker<String, String> for Echo {
+        async fn handle(&self, request: String) -> String {
+            request
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let router: Router<String, String> = Router::default();
+        router.tokio_spawn();
+        let report = router.health_check(Duration::from_millis(50)).await;
+        assert!(!report.answered);
+        assert_eq!(report.latency, None);
+        assert_eq!(report.workers, 0);
+
+        router.spawn_worker_instances(2, || Echo);
+        let report = router.health_check(Duration::from_millis(50)).await;
+        assert!(report.answered);
+        assert!(report.latency.is_some());
+        assert_eq!(report.workers, 2);
+
+        // a paused registration loop doesn't pick the ping up
+        router.pause();
+        let report = router.health_check(Duration::from_millis(50)).await;
+        assert!(!report.answered);
+        router.resume();
+        assert!(
+            router
+                .health_check(Duration::from_millis(50))
+                .await
+                .answered
+        );
+    }
+
+    struct Sleepy;
+
+    impl Worker<u64, u64> for Sleepy {
+        async fn handle(&self, millis: u64) -> u64 {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            millis
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_check_backlog() {
+        let router: Router<u64, u64> = Router::default();
+        router.tokio_spawn();
+        router.spawn_worker_instances(1, || Sleepy);
+        let endpoint = router.endpoint(None);
+        let requests: Vec<_> = (0..3)
+            .map(|_| {
+                let endpoint = endpoint.clone();
+                tokio::spawn(async move { endpoint.handle_request(100).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // the ping waits for the backlog of the only worker
+        let report = router.health_check(Duration::from_millis(100)).await;
+        assert!(!report.answered);
+        let report = router.health_check(Duration::from_millis(500)).await;
+        assert!(report.answered);
+        for request in requests {
+            assert_eq!(request.await.unwrap().unwrap(), 100);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+//! # Hooks Module
+//!
+//! This module provides the [RouterHooks] struct holding callbacks invoked by
+//! the [Router](crate::router::Router) loops at every step of a request's
+//! lifecycle, e.g. for custom metrics or audit logging, see
+//! [Router::with_hooks](crate::router::Router::with_hooks).
+//!
+//! ## Overview
+//!
+//! - `on_registered` is called once a request was registered with the router.
+//! - `on_dispatched` is called once a request was sent to the workers, for
+//!   every copy of a scattered request.
+//! - `on_response` is called once a response was delivered to its endpoint,
+//!   along with the time passed since the request was registered.
+//! - `on_late_response` is called for responses arriving after their
+//!   endpoint timed out, along with the time passed since the endpoint's
+//!   deadline. Responses to requests whose endpoint future was dropped are
+//!   reported to `on_orphan_response` instead.
+//! - `on_timeout` is called once the endpoint of a request timed out, as soon
+//!   as its deadline passed, whether or not a worker answers later.
+//! - `on_orphan_response` is called for responses nobody waits for anymore.
+//! - `on_slow_request` is called for requests whose response took longer than
+//!   the router's
+//!   [slow request threshold](crate::builder::RouterBuilder::slow_request_threshold).
+//!
+//! Hooks are called from within the router loops, and `on_timeout` from
+//! within the task of the timed out endpoint, so they should return quickly.
+use std::{fmt, sync::Arc, time::Duration};
+
+use uuid::Uuid;
+
+/// Callback receiving the UUID of a request.
+type UuidHook = Arc<dyn Fn(Uuid) + Send + Sync>;
+/// Callback receiving the UUID of a request and the request.
+type RequestHook<Request> = Arc<dyn Fn(Uuid, &Request) + Send + Sync>;
+/// Callback receiving the UUID of a request, its response and its latency.
+type ResponseHook<Response> = Arc<dyn Fn(Uuid, &Response, Duration) + Send + Sync>;
+/// Callback receiving the UUID of a request, its late response and its
+/// lateness.
+type LateResponseHook<Response> = Arc<dyn Fn(Uuid, Response, Duration) + Send + Sync>;
+/// Callback receiving a slow request report.
+type SlowRequestHook = Arc<dyn Fn(&SlowRequest) + Send + Sync>;
+
+/// A request whose response took longer than the router's slow request
+/// threshold, see
+/// [RouterBuilder::slow_request_threshold](crate::builder::RouterBuilder::slow_request_threshold).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowRequest {
+    /// UUID of the request
+    pub uuid: Uuid,
+    /// time passed from the registration of the request to its last response
+    pub elapsed: Duration,
+    /// number of requests waiting for a worker when the request was
+    /// registered
+    pub queue_depth: usize,
+}
+
+/// Callbacks invoked by the router at every step of a request's lifecycle.
+///
+/// # Type Parameters
+/// - `Request`: the request type of the router
+/// - `Response`: the response type of the router
+pub struct RouterHooks<Request, Response> {
+    on_registered: Option<RequestHook<Request>>,
+    on_dispatched: Option<UuidHook>,
+    on_response: Option<ResponseHook<Response>>,
+    on_late_response: Option<LateResponseHook<Response>>,
+    on_timeout: Option<UuidHook>,
+    on_orphan_response: Option<UuidHook>,
+    on_slow_request: Option<SlowRequestHook>,
+}
+
+impl<Request, Response> RouterHooks<Request, Response> {
+    /// Creates new `RouterHooks` without any callbacks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the callback called once a request was registered with the
+    /// router.
+    pub fn on_registered(mut self, hook: impl Fn(Uuid, &Request) + Send + Sync + 'static) -> Self {
+        self.on_registered = Some(Arc::new(hook));
+        self
+    }
+    /// Sets the callback called once a request was sent to the workers.
+    pub fn on_dispatched(mut self, hook: impl Fn(Uuid) + Send + Sync + 'static) -> Self {
+        self.on_dispatched = Some(Arc::new(hook));
+        self
+    }
+    /// Sets the callback called once a response was delivered to its
+    /// endpoint, along with the time passed since its request was registered.
+    pub fn on_response(
+        mut self,
+        hook: impl Fn(Uuid, &Response, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_response = Some(Arc::new(hook));
+        self
+    }
+    /// Sets the callback called for responses arriving after their endpoint
+    /// timed out, e.g. to cache them or to alert on systemic lateness.
+    ///
+    /// Dropping the future awaiting a response removes its request from the
+    /// router, so its response is reported to `on_orphan_response` instead.
+    ///
+    /// The callback receives the response along with the time passed since
+    /// the deadline of its endpoint, or since its registration if the
+    /// endpoint had no timeout.
+    pub fn on_late_response(
+        mut self,
+        hook: impl Fn(Uuid, Response, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_late_response = Some(Arc::new(hook));
+        self
+    }
+    /// Sets the callback called once the endpoint of a request timed out.
+    ///
+    /// The callback is called by the endpoint as soon as the deadline of the
+    /// request passed, also for requests whose worker never answers. A
+    /// response arriving later is reported to `on_late_response` instead.
+    pub fn on_timeout(mut self, hook: impl Fn(Uuid) + Send + Sync + 'static) -> Self {
+        self.on_timeout = Some(Arc::new(hook));
+        self
+    }
+    /// Sets the callback called for responses nobody waits for anymore.
+    pub fn on_orphan_response(mut self, hook: impl Fn(Uuid) + Send + Sync + 'static) -> Self {
+        self.on_orphan_response = Some(Arc::new(hook));
+        self
+    }
+    /// Sets the callback called for requests whose response took longer than
+    /// the router's slow request threshold.
+    pub fn on_slow_request(mut self, hook: impl Fn(&SlowRequest) + Send + Sync + 'static) -> Self {
+        self.on_slow_request = Some(Arc::new(hook));
+        self
+    }
+    /// Returns whether the `on_response` or `on_late_response` callback is
+    /// set, so the router only measures latencies if needed.
+    pub(crate) fn measures_latency(&self) -> bool {
+        self.on_response.is_some() || self.on_late_response.is_some()
+    }
+    pub(crate) fn registered(&self, uuid: Uuid, request: &Request) {
+        if let Some(hook) = &self.on_registered {
+            hook(uuid, request);
+        }
+    }
+    pub(crate) fn dispatched(&self, uuid: Uuid) {
+        if let Some(hook) = &self.on_dispatched {
+            hook(uuid);
+        }
+    }
+    pub(crate) fn response(&self, uuid: Uuid, response: &Response, latency: Duration) {
+        if let Some(hook) = &self.on_response {
+            hook(uuid, response, latency);
+        }
+    }
+    pub(crate) fn late_response(&self, uuid: Uuid, response: &Response, lateness: Duration)
+    where
+        Response: Clone,
+    {
+        if let Some(hook) = &self.on_late_response {
+            hook(uuid, response.clone(), lateness);
+        }
+    }
+    pub(crate) fn timeout(&self, uuid: Uuid) {
+        if let Some(hook) = &self.on_timeout {
+            hook(uuid);
+        }
+    }
+    pub(crate) fn orphan_response(&self, uuid: Uuid) {
+        if let Some(hook) = &self.on_orphan_response {
+            hook(uuid);
+        }
+    }
+    pub(crate) fn slow_request(&self, slow: &SlowRequest) {
+        if let Some(hook) = &self.on_slow_request {
+            hook(slow);
+        }
+    }
+}
+
+impl<Request, Response> Default for RouterHooks<Request, Response> {
+    fn default() -> Self {
+        Self {
+            on_registered: None,
+            on_dispatched: None,
+            on_response: None,
+            on_late_response: None,
+            on_timeout: None,
+            on_orphan_response: None,
+            on_slow_request: None,
+        }
+    }
+}
+
+// implemented by hand, deriving would require `Request` and `Response` to
+// implement the traits as well.
+impl<Request, Response> Clone for RouterHooks<Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            on_registered: self.on_registered.clone(),
+            on_dispatched: self.on_dispatched.clone(),
+            on_response: self.on_response.clone(),
+            on_late_response: self.on_late_response.clone(),
+            on_timeout: self.on_timeout.clone(),
+            on_orphan_response: self.on_orphan_response.clone(),
+            on_slow_request: self.on_slow_request.clone(),
+        }
+    }
+}
+
+impl<Request, Response> fmt::Debug for RouterHooks<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RouterHooks")
+            .field("on_registered", &self.on_registered.is_some())
+            .field("on_dispatched", &self.on_dispatched.is_some())
+            .field("on_response", &self.on_response.is_some())
+            .field("on_late_response", &self.on_late_response.is_some())
+            .field("on_timeout", &self.on_timeout.is_some())
+            .field("on_orphan_response", &self.on_orphan_response.is_some())
+            .field("on_slow_request", &self.on_slow_request.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RouterHooks;
+    use crate::{builder::RouterBuilder, endpoint::EndpointError, router::Router};
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn test_lifecycle_hooks() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = |name: &'static str| {
+            let events = events.clone();
+            move |_| events.lock().unwrap().push(name)
+        };
+        let registered = events.clone();
+        let responded = events.clone();
+        let hooks = RouterHooks::new()
+            .on_registered(move |_, request: &u64| {
+                registered.lock().unwrap().push(match request {
+                    0 => "registered fast",
+                    _ => "registered slow",
+                })
+            })
+            .on_dispatched(log("dispatched"))
+            .on_response(move |_, _: &u64, _| responded.lock().unwrap().push("response"))
+            .on_timeout(log("timeout"))
+            .on_orphan_response(log("orphan response"));
+        let router: Router<u64, u64> = Router::default().with_hooks(hooks);
+        router.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, millis)) = receiver.recv().await {
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                sender.send((uuid, millis)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(Duration::from_millis(50));
+        assert_eq!(endpoint.handle_request(0).await, Ok(0));
+        let response = endpoint.handle_request(100).await;
+        assert!(matches!(response, Err(EndpointError::Timeout(_))));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "registered fast",
+                "dispatched",
+                "response",
+                "registered slow",
+                "dispatched",
+                "timeout",
+                "orphan response",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout_without_response() {
+        let timeouts = Arc::new(Mutex::new(Vec::new()));
+        let reported = timeouts.clone();
+        let router: Router<u64, u64> = RouterBuilder::new()
+            .metrics(true)
+            .build::<u64, u64>()
+            .with_hooks(RouterHooks::new().on_timeout(move |uuid| {
+                reported.lock().unwrap().push(uuid);
+            }));
+        // the worker never answers
+        router.tokio_spawn_workers(1, |receiver, _sender| async move {
+            while receiver.recv().await.is_ok() {}
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(Duration::from_millis(20));
+        let (uuid, response) = endpoint.handle_request_with_id(0);
+        let response = response.await;
+        assert!(matches!(response, Err(EndpointError::Timeout(_))));
+        assert_eq!(*timeouts.lock().unwrap(), [uuid]);
+        assert_eq!(router.metrics().unwrap().timed_out, 1);
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_reports() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let reported = reports.clone();
+        let router: Router<u64, u64> = RouterBuilder::new()
+            .slow_request_threshold(Duration::from_millis(50))
+            .build::<u64, u64>()
+            .with_hooks(RouterHooks::new().on_slow_request(move |slow| {
+                reported.lock().unwrap().push(*slow);
+            }));
+        router.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, millis)) = receiver.recv().await {
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                sender.send((uuid, millis)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(None);
+        assert_eq!(endpoint.handle_request(0).await, Ok(0));
+        assert!(reports.lock().unwrap().is_empty());
+        // the second request waits behind the first one
+        let (first, second) = tokio::join!(endpoint.handle_request(80), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            endpoint.handle_request(0).await
+        });
+        assert_eq!((first, second), (Ok(80), Ok(0)));
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert!(reports
+            .iter()
+            .all(|slow| slow.elapsed > Duration::from_millis(50)));
+    }
+
+    #[tokio::test]
+    async fn test_late_responses() {
+        let late = Arc::new(Mutex::new(Vec::new()));
+        let reported = late.clone();
+        let router: Router<u64, u64> = RouterBuilder::new()
+            .metrics(true)
+            .build::<u64, u64>()
+            .with_hooks(
+                RouterHooks::new().on_late_response(move |_, response, lateness| {
+                    reported.lock().unwrap().push((response, lateness));
+                }),
+            );
+        router.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, millis)) = receiver.recv().await {
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                sender.send((uuid, millis)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(Duration::from_millis(50));
+        assert_eq!(endpoint.handle_request(0).await, Ok(0));
+        let response = endpoint.handle_request(100).await;
+        assert!(matches!(response, Err(EndpointError::Timeout(_))));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let late = late.lock().unwrap();
+        assert_eq!(late.len(), 1);
+        assert_eq!(late[0].0, 100);
+        assert!(late[0].1 >= Duration::from_millis(40));
+        assert_eq!(router.metrics().unwrap().late, 1);
+    }
+}
@@ -0,0 +1,74 @@
+//! # Id Module
+//!
+//! This module provides the [IdGenerator] struct used by the
+//! [Router](crate::router::Router) to create the unique identifiers that
+//! correlate requests with their responses.
+//!
+//! ## Overview
+//!
+//! Random (version 4) UUIDs are used by default. With the `v7` feature,
+//! [IdGenerator::v7] produces time-ordered (version 7) UUIDs instead, which
+//! sort by submission time, making the identifiers of requests easier to
+//! correlate in logs, and improving the locality of the router's response map
+//! under high throughput.
+use std::{fmt, sync::Arc};
+
+use uuid::Uuid;
+
+/// Generates the unique identifiers assigned to requests by the
+/// [Router](crate::router::Router).
+///
+/// Cloning an `IdGenerator` is cheap, all clones share the same generating
+/// function.
+#[derive(Clone)]
+pub struct IdGenerator {
+    generate: Arc<dyn Fn() -> Uuid + Send + Sync>,
+}
+
+impl IdGenerator {
+    /// Creates a new `IdGenerator` from a generating function.
+    pub fn new(generate: impl Fn() -> Uuid + Send + Sync + 'static) -> Self {
+        Self {
+            generate: Arc::new(generate),
+        }
+    }
+    /// Creates a new `IdGenerator` producing random (version 4) UUIDs.
+    pub fn v4() -> Self {
+        Self::new(Uuid::new_v4)
+    }
+    /// Creates a new `IdGenerator` producing time-ordered (version 7) UUIDs.
+    ///
+    /// The UUIDs generated within the process are monotonically increasing,
+    /// even when generated within the same millisecond.
+    #[cfg(feature = "v7")]
+    pub fn v7() -> Self {
+        Self::new(Uuid::now_v7)
+    }
+    /// Generates a new identifier.
+    pub fn generate(&self) -> Uuid {
+        (self.generate)()
+    }
+}
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        Self::v4()
+    }
+}
+
+impl fmt::Debug for IdGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdGenerator").finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "v7"))]
+mod tests {
+    #[test]
+    fn test_v7_ids_are_ordered() {
+        let generator = super::IdGenerator::v7();
+        let ids: Vec<_> = (0..100).map(|_| generator.generate()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(ids[0].get_version_num(), 7);
+    }
+}
@@ -0,0 +1,423 @@
+//! # Kafka Module
+//!
+//! This module provides a [Kafka](https://kafka.apache.org) adapter for the
+//! [Router], enabled with the `kafka` feature.
+//!
+//! ## Overview
+//!
+//! [Router::from_kafka] creates a [Router] whose workers are existing Kafka
+//! consumers. Every request received through an
+//! [Endpoint](crate::endpoint::Endpoint) is produced to the request topic,
+//! keyed by its UUID and carrying the reply topic in its
+//! [REPLY_TOPIC_HEADER] header. The responders produce their responses to the
+//! reply topic under the key of the request, where the adapter consumes them
+//! and feeds them into the router's response loop.
+//!
+//! The adapter owns the consumer of the reply topic: it disables the
+//! automatic commits of the given configuration and commits the offset of
+//! every reply once it was handed to the router, or once it was skipped
+//! because it could not be decoded. Replies to requests which timed out or
+//! were answered before are dropped by the router. Requests which can not be
+//! encoded or delivered, or whose reply can not be decoded, fail with
+//! [EndpointError::ResponseReceive](crate::endpoint::EndpointError::ResponseReceive)
+//! and are reported as
+//! [RouterError::RemoteFailed](crate::router::RouterError::RemoteFailed).
+//!
+//! At most [MAX_PENDING_DELIVERIES] requests are being delivered at a time,
+//! further requests wait in the router's request channel, and the consumer
+//! backs off while the broker is unreachable.
+//!
+//! Every router consumes all replies of its consumer group, so routers
+//! sharing a reply topic need distinct `group.id`s, otherwise the replies of
+//! one router are committed by another one.
+//!
+//! Requests and responses are encoded as JSON using
+//! [serde_json](https://docs.rs/serde_json), or with the [Codec] passed to
+//! [Router::from_kafka_with_codec].
+use std::{fmt, sync::Arc, time::Duration};
+
+use crate::channel::{Receiver, Sender};
+use rdkafka::{
+    config::ClientConfig,
+    consumer::{CommitMode, Consumer, StreamConsumer},
+    error::KafkaResult,
+    message::{Header, Message, OwnedHeaders},
+    producer::{FutureProducer, FutureRecord},
+    util::Timeout,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+use crate::{
+    codec::{Codec, JsonCodec},
+    router::Router,
+};
+
+/// Name of the header of every request naming the topic its response is
+/// expected on.
+pub const REPLY_TOPIC_HEADER: &str = "s2a4c-reply-topic";
+
+/// Maximum number of requests being delivered to the broker at a time.
+pub const MAX_PENDING_DELIVERIES: usize = 1024;
+
+/// Delay before consuming again after the first failure of the consumer,
+/// doubled with every further failure.
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Maximum delay before consuming again after a failure of the consumer.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The topics a [Router] created by [Router::from_kafka] exchanges messages
+/// on.
+struct Topics {
+    /// topic the requests are produced to
+    requests: String,
+    /// topic the responses are consumed from
+    replies: String,
+}
+
+/// Logs a failure of the adapter as a `tracing` warning with the `tracing`
+/// feature.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn warn(source: &str, err: &dyn fmt::Debug) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(source, error = ?err, "kafka error");
+}
+
+/// Decodes a reply from its `key` and `payload`.
+///
+/// # Returns
+///
+/// Returns the UUID of the request the reply answers along with the
+/// response, or the error of decoding the payload, or `None` if the key is
+/// not a UUID.
+fn decode_reply<Response, C>(
+    key: Option<&[u8]>,
+    payload: Option<&[u8]>,
+    codec: &C,
+) -> Option<(Uuid, Result<Response, C::Error>)>
+where
+    Response: DeserializeOwned,
+    C: Codec,
+{
+    let uuid = key.map(Uuid::try_parse_ascii)?.ok()?;
+    Some((uuid, codec.decode(payload.unwrap_or_default())))
+}
+
+/// Asynchronous private function that produces the requests of the router
+/// to the request topic.
+///
+/// # Behavior
+///
+/// Every request is encoded and produced in its own task so that a slow
+/// delivery does not block the requests queued behind it, up to
+/// [MAX_PENDING_DELIVERIES] requests at a time. Requests which can not be
+/// encoded or delivered are failed through [Router::fail_remote]. The
+/// function returns once the request channel is closed.
+async fn produce_requests<Request, Response, C>(
+    receiver: Receiver<(Uuid, Request)>,
+    producer: FutureProducer,
+    topics: Arc<Topics>,
+    codec: C,
+    router: Router<Request, Response>,
+) where
+    Request: Serialize + Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+    C: Codec,
+{
+    let deliveries = Arc::new(Semaphore::new(MAX_PENDING_DELIVERIES));
+    loop {
+        // the semaphore is never closed
+        let Ok(permit) = deliveries.clone().acquire_owned().await else {
+            return;
+        };
+        let Ok((uuid, request)) = receiver.recv().await else {
+            return;
+        };
+        let payload = match codec.encode(&request) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn("kafka producer", &err);
+                router.fail_remote(&uuid);
+                continue;
+            }
+        };
+        let producer = producer.clone();
+        let topics = topics.clone();
+        let router = router.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            let key = uuid.to_string();
+            let headers = OwnedHeaders::new().insert(Header {
+                key: REPLY_TOPIC_HEADER,
+                value: Some(&topics.replies),
+            });
+            let record = FutureRecord::to(&topics.requests)
+                .key(&key)
+                .payload(&payload)
+                .headers(headers);
+            if let Err((err, _)) = producer.send(record, Timeout::Never).await {
+                warn("kafka producer", &err);
+                router.fail_remote(&uuid);
+            }
+        });
+    }
+}
+
+/// Asynchronous private function that consumes the replies and feeds them
+/// into the router's response loop.
+///
+/// # Behavior
+///
+/// The offset of every reply is committed once the response was handed to
+/// the router, or once the reply was skipped because it could not be
+/// decoded, failing its request through [Router::fail_remote]. After a
+/// failure of the consumer, e.g. while the broker is unreachable, consuming
+/// is retried with an exponential backoff. The function returns once the
+/// response channel is closed, without committing the reply it could not
+/// hand over.
+async fn collect_replies<Request, Response, C>(
+    consumer: Arc<StreamConsumer>,
+    sender: Sender<(Uuid, Response)>,
+    codec: C,
+    router: Router<Request, Response>,
+) where
+    Request: Send + 'static + Clone,
+    Response: DeserializeOwned + Send + 'static + Clone,
+    C: Codec,
+{
+    let mut retry_delay = MIN_RETRY_DELAY;
+    loop {
+        let message = match consumer.recv().await {
+            Ok(message) => message,
+            Err(err) => {
+                warn("kafka consumer", &err);
+                tokio::time::sleep(retry_delay).await;
+                retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                continue;
+            }
+        };
+        retry_delay = MIN_RETRY_DELAY;
+        match decode_reply(message.key(), message.payload(), &codec) {
+            Some((uuid, Ok(response))) => {
+                if sender.send((uuid, response)).await.is_err() {
+                    return;
+                }
+            }
+            Some((uuid, Err(err))) => {
+                warn("kafka consumer", &err);
+                router.fail_remote(&uuid);
+            }
+            None => warn("kafka consumer", &"reply without a request UUID key"),
+        }
+        if let Err(err) = consumer.commit_message(&message, CommitMode::Async) {
+            warn("kafka consumer", &err);
+        }
+    }
+}
+
+impl<Request, Response> Router<Request, Response>
+where
+    Request: Serialize + DeserializeOwned + Send + 'static + Clone,
+    Response: Serialize + DeserializeOwned + Send + 'static + Clone,
+{
+    /// Creates a new [Router] with default channel capacities whose workers
+    /// are Kafka consumers of `request_topic`, replying on `reply_topic`.
+    ///
+    /// # Arguments
+    ///
+    /// - `config`: The configuration of the Kafka clients, naming at least
+    ///   the `bootstrap.servers` and the `group.id` of the reply consumer.
+    ///   Automatic commits are disabled, the adapter commits the replies
+    ///   itself.
+    /// - `request_topic`: The topic requests are produced to.
+    /// - `reply_topic`: The topic responses are consumed from.
+    ///
+    /// # Returns
+    ///
+    /// Returns the new [Router], or the error of creating the Kafka clients
+    /// or of subscribing to `reply_topic`. The router loops still need to be
+    /// started with [Router::tokio_spawn].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn from_kafka(
+        config: &ClientConfig,
+        request_topic: impl Into<String>,
+        reply_topic: impl Into<String>,
+    ) -> KafkaResult<Self> {
+        Self::from_kafka_with_codec(config, request_topic, reply_topic, JsonCodec)
+    }
+
+    /// Creates a new [Router] like [Router::from_kafka], encoding requests
+    /// and responses with `codec` instead of JSON.
+    ///
+    /// # Arguments
+    ///
+    /// - `config`: The configuration of the Kafka clients, see
+    ///   [Router::from_kafka].
+    /// - `request_topic`: The topic requests are produced to.
+    /// - `reply_topic`: The topic responses are consumed from.
+    /// - `codec`: The [Codec] the remote responders decode requests and
+    ///   encode responses with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn from_kafka_with_codec(
+        config: &ClientConfig,
+        request_topic: impl Into<String>,
+        reply_topic: impl Into<String>,
+        codec: impl Codec,
+    ) -> KafkaResult<Self> {
+        let topics = Arc::new(Topics {
+            requests: request_topic.into(),
+            replies: reply_topic.into(),
+        });
+        let producer: FutureProducer = config.create()?;
+        let consumer: StreamConsumer =
+            config.clone().set("enable.auto.commit", "false").create()?;
+        consumer.subscribe(&[&topics.replies])?;
+        let consumer = Arc::new(consumer);
+
+        let router = Self::default();
+        let failing = router.clone();
+        router.tokio_spawn_workers(1, move |receiver, sender| {
+            let requests = produce_requests(
+                receiver,
+                producer.clone(),
+                topics.clone(),
+                codec.clone(),
+                failing.clone(),
+            );
+            let replies = collect_replies(consumer.clone(), sender, codec.clone(), failing.clone());
+            async move {
+                // replies are no longer awaited once the router is closed
+                tokio::select! {
+                    _ = requests => {}
+                    _ = replies => {}
+                }
+            }
+        });
+        Ok(router)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rdkafka::{
+        config::ClientConfig,
+        consumer::{BaseConsumer, Consumer, StreamConsumer},
+        message::{Headers, Message},
+        mocking::MockCluster,
+        producer::{FutureProducer, FutureRecord},
+        util::Timeout,
+        Offset, TopicPartitionList,
+    };
+    use uuid::Uuid;
+
+    use crate::{codec::JsonCodec, endpoint::EndpointError, router::Router};
+
+    use super::{decode_reply, REPLY_TOPIC_HEADER};
+
+    #[test]
+    fn test_decode_reply() {
+        let uuid = Uuid::new_v4();
+        let key = uuid.to_string();
+        assert!(matches!(
+            decode_reply(Some(key.as_bytes()), Some(b"42"), &JsonCodec),
+            Some((decoded, Ok(42u64))) if decoded == uuid
+        ));
+        assert!(decode_reply::<u64, _>(Some(b"not a uuid"), Some(b"42"), &JsonCodec).is_none());
+        assert!(matches!(
+            decode_reply::<u64, _>(Some(key.as_bytes()), Some(b"x"), &JsonCodec),
+            Some((decoded, Err(_))) if decoded == uuid
+        ));
+    }
+
+    #[test]
+    fn test_request_reply() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("requests", 1, 1).unwrap();
+        cluster.create_topic("replies", 1, 1).unwrap();
+        let mut config = ClientConfig::new();
+        config
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .set("auto.offset.reset", "earliest");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(request_reply(config));
+        // the clients leave the cluster before it is gone
+        drop(runtime);
+    }
+
+    /// Exchanges requests and replies with a responder through the cluster
+    /// `config` connects to.
+    async fn request_reply(mut config: ClientConfig) {
+        let router: Router<u64, u64> = Router::from_kafka(
+            config.clone().set("group.id", "router"),
+            "requests",
+            "replies",
+        )
+        .unwrap();
+        router.tokio_spawn();
+
+        // a responder doubling the requests, replying to 0 with an invalid
+        // payload
+        let responder: StreamConsumer = config
+            .clone()
+            .set("group.id", "responder")
+            .create()
+            .unwrap();
+        responder.subscribe(&["requests"]).unwrap();
+        let producer: FutureProducer = config.create().unwrap();
+        tokio::spawn(async move {
+            while let Ok(message) = responder.recv().await {
+                let headers = message.headers().unwrap();
+                let reply_topic = headers
+                    .iter()
+                    .find(|header| header.key == REPLY_TOPIC_HEADER)
+                    .and_then(|header| header.value)
+                    .map(|topic| String::from_utf8(topic.to_vec()).unwrap())
+                    .unwrap();
+                let request: u64 = serde_json::from_slice(message.payload().unwrap()).unwrap();
+                let payload = match request {
+                    0 => b"x".to_vec(),
+                    request => serde_json::to_vec(&(request * 2)).unwrap(),
+                };
+                let record = FutureRecord::to(&reply_topic)
+                    .key(message.key().unwrap())
+                    .payload(&payload);
+                producer.send(record, Timeout::Never).await.unwrap();
+            }
+        });
+
+        // replies are correlated with their requests by the UUID key
+        let endpoint = router.endpoint(Duration::from_secs(30));
+        let (doubled, failed) =
+            tokio::join!(endpoint.handle_request(21), endpoint.handle_request(0));
+        assert_eq!(doubled, Ok(42));
+        assert!(matches!(failed, Err(EndpointError::ResponseReceive(_))));
+
+        // both replies are committed, the invalid one as well
+        let committed: BaseConsumer = config.set("group.id", "router").create().unwrap();
+        let mut partitions = TopicPartitionList::new();
+        partitions.add_partition("replies", 0);
+        let mut offset = Offset::Invalid;
+        for _ in 0..100 {
+            offset = committed
+                .committed_offsets(partitions.clone(), Duration::from_secs(5))
+                .unwrap()
+                .elements()[0]
+                .offset();
+            if offset == Offset::Offset(2) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(offset, Offset::Offset(2));
+    }
+}
@@ -5,13 +5,99 @@
 //!
 //! ## Modules
 //!
+//! - [adapt]: Provides the [MappedEndpoint](adapt::MappedEndpoint) struct
+//!   exposing an [Endpoint](endpoint::Endpoint) over other request and
+//!   response types.
+//! - [builder]: Provides the [RouterBuilder](builder::RouterBuilder) struct
+//!   for configuring a [Router](router::Router) with named setters.
+//! - [cache]: Provides the [ResponseCache](cache::ResponseCache) struct, an
+//!   opt-in LRU cache answering requests without dispatching them to workers.
+//! - [channel]: Provides the [Sender](channel::Sender) and
+//!   [Receiver](channel::Receiver) channel halves the router and its workers
+//!   communicate over, backed by async-channel or, with the `flume` feature,
+//!   by [flume](https://docs.rs/flume).
+//! - [coalesce]: Provides the
+//!   [CoalescingEndpoint](coalesce::CoalescingEndpoint) struct submitting the
+//!   requests handled within a short window as a single batch.
+//! - [context]: Provides the [TraceContext](context::TraceContext) struct
+//!   propagating a trace context alongside requests to workers.
+//! - [deferred]: Provides the [ResponseHandle](deferred::ResponseHandle)
+//!   struct letting workers respond to requests from outside the worker
+//!   loop.
+//! - [dispatch]: Provides the
+//!   [DispatchStrategy](dispatch::DispatchStrategy) trait and its
+//!   implementations deciding which worker handles a request.
 //! - [endpoint]: Provides the
-//!     [Endpoint](endpoint::Endpoint) struct and
-//!     [EndpointError](endpoint::EndpointError) enum for handling
-//!     asynchronous communication with a timeout mechanism.
+//!   [Endpoint](endpoint::Endpoint) struct and
+//!   [EndpointError](endpoint::EndpointError) enum for handling
+//!   asynchronous communication with a timeout mechanism.
+//! - [envelope]: Provides the [Envelope](envelope::Envelope) struct carrying
+//!   request metadata from endpoints to workers.
+//! - [handle]: Provides the [RouterHandle](handle::RouterHandle) struct
+//!   owning the tasks spawned for a router.
+//! - [health]: Provides the [HealthReport](health::HealthReport) struct
+//!   describing the outcome of a router health check.
+//! - [hooks]: Provides the [RouterHooks](hooks::RouterHooks) struct holding
+//!   callbacks invoked at every step of a request's lifecycle.
+//! - [id]: Provides the [IdGenerator](id::IdGenerator) struct generating
+//!   the unique request identifiers.
+//! - [metrics]: Provides the [MetricsSnapshot](metrics::MetricsSnapshot)
+//!   struct exposing the router's metrics counters.
+//! - [pause]: Provides the [PauseMode](pause::PauseMode) enum deciding what
+//!   happens to the requests submitted to a paused [Router](router::Router).
+//! - [pipeline]: Provides the [Pipeline](pipeline::Pipeline) struct chaining
+//!   several routers into a multi-stage request-response flow.
+//! - [progress]: Provides the [Update](progress::Update) enum letting
+//!   workers report the progress of long-running requests.
+//! - [recorder]: Provides the [Recorder](recorder::Recorder) struct, an
+//!   opt-in audit log of handled requests which can be replayed.
 //! - [router]: Provides the [Router](router::Router)
-//!     struct for routing request-response communication using
-//!     [async-channel](https://docs.rs/async-channel).
+//!   struct for routing request-response communication using
+//!   [async-channel](https://docs.rs/async-channel).
+//! - [scheduler]: Provides the [Scheduler](scheduler::Scheduler) struct
+//!   pushing recurring jobs, run at a fixed period or by cron expression,
+//!   through a [Router](router::Router).
+//! - [worker]: Provides the [Worker](worker::Worker) trait for writing
+//!   request handlers managed by the [Router](router::Router), and the
+//!   [WorkerAttachment](worker::WorkerAttachment) struct for running workers
+//!   on other executors.
+//! - [spawn]: Provides the [Spawn](spawn::Spawn) trait for spawning the
+//!   router's tasks on executors other than tokio.
+//! - [stats]: Provides the [RouterStats](stats::RouterStats) struct exposing
+//!   the router's queue depths and in-flight request count.
+//! - [tenant]: Provides the [TenantLimits](tenant::TenantLimits) struct
+//!   bounding the queued and in-flight requests of each tenant of a router.
+//! - [throttle]: Provides the [KeyThrottle](throttle::KeyThrottle) struct
+//!   limiting the concurrent and per-second requests of a router per request
+//!   key.
+//! - [timed]: Provides the [Timed](timed::Timed) struct annotating a
+//!   response with the time its request waited in the router and was
+//!   processed for.
+//! - `actix` (feature `actix`): Provides helpers for serving a
+//!   [Router](router::Router) from an [actix-web](https://docs.rs/actix-web)
+//!   application.
+//! - `axum` (feature `axum`): Provides helpers for serving a
+//!   [Router](router::Router) from an [axum](https://docs.rs/axum)
+//!   application.
+//! - `bytes` (feature `bytes`): Provides the `BytesRouter` type exchanging
+//!   `Bytes` payloads by reference-counted slices, without copying them.
+//! - `RequestEnum` (feature `derive`): Derives the route key, typed
+//!   endpoints and worker of a [Router](router::Router) multiplexing the
+//!   variants of a request enum, see the `s2a4c-derive` crate.
+//! - `codec` (feature `serde`): Provides the `Codec` trait encoding the
+//!   requests and responses of remote transports, implemented for JSON,
+//!   bincode and postcard behind the `json`, `bincode` and `postcard`
+//!   features.
+//! - `kafka` (feature `kafka`): Provides a [Kafka](https://kafka.apache.org)
+//!   adapter producing requests to a topic and collecting the responses from
+//!   a reply topic.
+//! - `nats` (feature `nats`): Provides a [NATS](https://nats.io) adapter
+//!   mapping the [Router](router::Router) onto NATS request/reply.
+//! - `testing` (feature `testing`): Provides the `MockRouter` struct
+//!   answering requests with scripted replies, for unit-testing code taking
+//!   an [Endpoint](endpoint::Endpoint).
+//! - `websocket` (feature `websocket`): Provides a WebSocket server that lets
+//!   remote clients act as endpoints of a [Router](router::Router).
 //!
 //! ## Overview
 //!
@@ -43,7 +129,7 @@
 //!
 //! Here is a simple example demonstrating how to use the `Router' struct:
 //! ```rust
-//! use async_channel::{bounded, Sender, Receiver};
+//! use s2a4c::channel::{bounded, Sender, Receiver};
 //! use tokio::time::Duration;
 //! use s2a4c::router::Router;
 //! use uuid::Uuid;
@@ -83,18 +169,87 @@
 //! ## Dependencies
 //!
 //! - [`async-channel`](https://docs.rs/async-channel) for asynchronous message passing
+//! - [`flume`](https://docs.rs/flume) as the alternative channel backend of the `flume`
+//!   feature, see [channel]
 //! - [`tokio`](https://docs.rs/tokio) for asynchronous operations
 //! - [`uuid`](https://docs.rs/uuid) for generating unique identifiers
 //! - [`scc`](https://docs.rs/scc) for a concurrent HashMap used for mapping UUIDs to respon
 //! - [`thiserror`](https://docs.rs/thiserror) for error handling
 
+#[cfg(feature = "actix")]
+pub mod actix;
+pub mod adapt;
+#[cfg(feature = "axum")]
+pub mod axum;
+pub mod builder;
+#[cfg(feature = "bytes")]
+pub mod bytes;
+pub mod cache;
+pub mod channel;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod coalesce;
+#[cfg(feature = "serde")]
+pub mod codec;
+pub mod context;
+mod deadline;
+pub mod deferred;
+pub mod dispatch;
 pub mod endpoint;
+pub mod envelope;
+pub mod handle;
+pub mod health;
+pub mod hooks;
+pub mod id;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod metrics;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod pause;
+pub mod pipeline;
+pub mod progress;
+pub mod recorder;
+mod response_map;
 pub mod router;
+pub mod scheduler;
+pub mod spawn;
+pub mod stats;
+pub mod tenant;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod throttle;
+pub mod timed;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+pub mod worker;
+
+#[cfg(feature = "derive")]
+pub use s2a4c_derive::RequestEnum;
+
+// lets the tests expand derived code referring to the crate as `::s2a4c`
+#[cfg(all(test, feature = "derive"))]
+extern crate self as s2a4c;
+
+/// Items the code generated by the derive macros refers to, not part of the
+/// public API.
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod __private {
+    pub use tokio::task::JoinHandle;
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::{endpoint::EndpointError, router::Router};
-    use async_channel::{Receiver, Sender};
+    use crate::channel::{Receiver, Sender};
+    use crate::{
+        builder::RouterBuilder,
+        context::TraceContext,
+        endpoint::{ConcurrencyMode, EndpointError, RejectReason, Timeout},
+        pause::PauseMode,
+        router::{Router, RouterError},
+        stats::DrainReport,
+        throttle::{Excess, KeyThrottle},
+    };
     use test_case::test_case;
     use tokio::time::Duration;
     use uuid::Uuid;
@@ -136,4 +291,711 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn test_router_stats() {
+        let router: Router<String, String> = Router::default();
+        // workers that never pick up a request
+        async fn idle_worker(_: Receiver<(Uuid, String)>, _: Sender<(Uuid, String)>) {
+            std::future::pending::<()>().await
+        }
+        let handles = router.tokio_spawn_workers(2, idle_worker);
+        assert_eq!(router.stats().workers, 2);
+
+        let endpoint = router.endpoint(None);
+        tokio::spawn(async move { endpoint.handle_request("queued".into()).await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(router.stats().registration_queue_len, 1);
+
+        router.tokio_spawn();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let stats = router.stats();
+        assert_eq!(stats.registration_queue_len, 0);
+        assert_eq!(stats.request_queue_len, 1);
+        assert_eq!(stats.in_flight, 1);
+
+        handles.iter().for_each(|handle| handle.abort());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(router.stats().workers, 0);
+    }
+
+    #[tokio::test]
+    async fn test_stats_snapshot() {
+        let router: Router<u64, u64> = RouterBuilder::new().metrics(true).build();
+        assert_eq!(router.stats().uptime, None);
+        router.spawn_workers_fn(1, |request| async move {
+            tokio::time::sleep(Duration::from_millis(request)).await;
+            request
+        });
+        router.tokio_spawn();
+        let endpoint = router.endpoint(None);
+        for request in 1..=10 {
+            assert_eq!(endpoint.handle_request(request * 5).await, Ok(request * 5));
+        }
+
+        let stats = router.stats();
+        assert!(stats.uptime.unwrap() >= Duration::from_millis(275));
+        assert_eq!(stats.counts.unwrap().responded, 10);
+        let latency = stats.latency.unwrap();
+        assert_eq!(latency.samples, 10);
+        // the latencies are measured on the wall clock, only the lower
+        // bounds are guaranteed on a loaded machine
+        assert!(latency.p50 >= Duration::from_millis(25));
+        assert!(latency.p90 >= Duration::from_millis(45) && latency.p90 <= latency.p99);
+        assert!(latency.p50 <= latency.p90);
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_value(stats).unwrap();
+            assert_eq!(json["counts"]["responded"], 10);
+            assert_eq!(json["latency"]["samples"], 10);
+        }
+
+        // latencies are only measured with metrics enabled
+        let router: Router<u64, u64> = Router::default();
+        router.spawn_workers_fn(1, |request| async move { request });
+        router.tokio_spawn();
+        assert_eq!(router.endpoint(None).handle_request(1).await, Ok(1));
+        let stats = router.stats();
+        assert!(stats.counts.is_none() && stats.latency.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_router_errors() {
+        let router: Router<u32, u32> = Router::default();
+        let errors = router.errors();
+        let unknown = Uuid::new_v4();
+        router.tokio_spawn_workers(1, move |receiver, sender| async move {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                sender.send((unknown, request)).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                sender.send((uuid, request)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        // the response arrives once the endpoint timed out and is gone
+        let endpoint = router.endpoint(Duration::from_millis(10));
+        let (uuid, response) = endpoint.handle_request_with_id(1);
+        assert!(matches!(response.await, Err(EndpointError::Timeout(_))));
+        assert_eq!(
+            errors.recv().await,
+            Ok(RouterError::UnknownResponse(unknown))
+        );
+        assert_eq!(errors.recv().await, Ok(RouterError::ResponseDropped(uuid)));
+        router.shutdown();
+        assert!(errors.recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delayed_requests() {
+        let router: Router<u32, u32> = Router::default();
+        let handled = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = handled.clone();
+        router.tokio_spawn_workers(1, move |receiver, sender| {
+            let log = log.clone();
+            async move {
+                while let Ok((uuid, request)) = receiver.recv().await {
+                    log.lock().unwrap().push(request);
+                    sender.send((uuid, request)).await.unwrap();
+                }
+            }
+        });
+        router.tokio_spawn();
+
+        // the timeout is measured from the time the request is dispatched at
+        let endpoint = router.endpoint(Duration::from_millis(20));
+        let start = std::time::Instant::now();
+        let response = endpoint
+            .handle_request_after(Duration::from_millis(40), 1)
+            .await;
+        assert_eq!(response, Ok(1));
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        // requests are dispatched in the order of their scheduled times, and
+        // never if they are cancelled before
+        let start = std::time::Instant::now();
+        let (late, early, cancelled) = tokio::join!(
+            endpoint.handle_request_at(start + Duration::from_millis(30), 2),
+            endpoint.handle_request_at(start + Duration::from_millis(10), 3),
+            tokio::time::timeout(
+                Duration::from_millis(5),
+                endpoint.handle_request_after(Duration::from_millis(20), 4)
+            ),
+        );
+        assert_eq!((late, early), (Ok(2), Ok(3)));
+        assert!(cancelled.is_err());
+        assert_eq!(*handled.lock().unwrap(), [1, 3, 2]);
+        assert_eq!(router.stats().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn test_trace_context_propagation() {
+        let router: Router<String, String> = Router::default();
+        let worker_router = router.clone();
+        router.tokio_spawn_workers(1, move |receiver, sender| {
+            let router = worker_router.clone();
+            async move {
+                while let Ok((uuid, _)) = receiver.recv().await {
+                    let context = router.trace_context(&uuid).unwrap();
+                    let traceparent = context.get("traceparent").unwrap_or_default();
+                    sender.send((uuid, traceparent.to_string())).await.unwrap();
+                }
+            }
+        });
+        router.tokio_spawn();
+
+        let mut context = TraceContext::new();
+        context.insert("traceparent", "00-0af7651916cd43dd8448eb211c80319c-01");
+        let response = router
+            .endpoint(None)
+            .handle_request_with_context("Hello".to_string(), context)
+            .await;
+        assert_eq!(
+            response,
+            Ok("00-0af7651916cd43dd8448eb211c80319c-01".to_string())
+        );
+    }
+
+    #[test_case(500, DrainReport { completed: 2, timed_out: 1, abandoned: 0 })]
+    #[test_case(50, DrainReport { completed: 0, timed_out: 1, abandoned: 2 })]
+    #[tokio::test]
+    async fn test_drain_report(deadline_in_msecs: u64, expected: DrainReport) {
+        let router: Router<String, String> = Router::default();
+        async fn worker_100ms(receiver: Receiver<(Uuid, String)>, sender: Sender<(Uuid, String)>) {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                sender.send((uuid, request)).await.unwrap();
+            }
+        }
+        router.tokio_spawn_workers(3, worker_100ms);
+        router.tokio_spawn();
+
+        for timeout in [None, None, Some(Duration::from_millis(20))] {
+            let endpoint = router.endpoint(timeout);
+            tokio::spawn(async move { endpoint.handle_request("drain".into()).await });
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let report = router.drain(Duration::from_millis(deadline_in_msecs)).await;
+        assert_eq!(report, expected);
+        let response = router.endpoint(None).handle_request("late".into()).await;
+        assert_eq!(response, Err(EndpointError::RequestSend));
+    }
+
+    #[tokio::test]
+    async fn test_shared_endpoint() {
+        let router: Router<String, String> = Router::default();
+        async fn echo(receiver: Receiver<(Uuid, String)>, sender: Sender<(Uuid, String)>) {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                sender.send((uuid, request)).await.unwrap();
+            }
+        }
+        router.tokio_spawn_workers(2, echo);
+        router.tokio_spawn();
+
+        let endpoint = router.shared_endpoint(Some(Duration::from_millis(100)));
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let endpoint = endpoint.clone();
+                tokio::spawn(async move { endpoint.handle_request(i.to_string()).await })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.await.unwrap(), Ok(i.to_string()));
+        }
+        let cloned = (*endpoint).clone();
+        assert_eq!(format!("{:?}", cloned), format!("{:?}", endpoint));
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_with_id() {
+        let router: Router<String, String> = Router::default();
+        async fn uuid_echo(receiver: Receiver<(Uuid, String)>, sender: Sender<(Uuid, String)>) {
+            while let Ok((uuid, _)) = receiver.recv().await {
+                sender.send((uuid, uuid.to_string())).await.unwrap();
+            }
+        }
+        router.tokio_spawn_workers(1, uuid_echo);
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(None);
+        let (uuid, response) = endpoint.handle_request_with_id("Hello".to_string());
+        assert_eq!(response.await, Ok(uuid.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_load_shedding() {
+        let router: Router<String, String> = RouterBuilder::new().high_watermark(2).build();
+        router.tokio_spawn_workers(1, |_, _| std::future::pending());
+        // the router loops aren't running, so requests pile up in the
+        // registration channel
+        for _ in 0..2 {
+            let endpoint = router.endpoint(None);
+            tokio::spawn(async move { endpoint.handle_request("queued".into()).await });
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let response = router.endpoint(None).handle_request("shed".into()).await;
+        assert_eq!(response, Err(EndpointError::Overloaded));
+    }
+
+    #[tokio::test]
+    async fn test_no_workers() {
+        let router: Router<u32, u32> = Router::default();
+        router.tokio_spawn();
+        let endpoint = router.endpoint(Duration::from_secs(10));
+        assert_eq!(
+            endpoint.handle_request(1).await,
+            Err(EndpointError::NoWorkers)
+        );
+
+        let workers = router.spawn_workers_fn(1, |request| async move { request + 1 });
+        assert_eq!(endpoint.handle_request(1).await, Ok(2));
+        // requests fail fast again once all workers are gone
+        for worker in workers {
+            worker.abort();
+            let _ = worker.await;
+        }
+        assert_eq!(
+            endpoint.handle_request(1).await,
+            Err(EndpointError::NoWorkers)
+        );
+        assert_eq!(router.stats().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight() {
+        let router: Router<u64, u64> = RouterBuilder::new().max_in_flight(2).build();
+        router.tokio_spawn_workers(2, |receiver, sender| async move {
+            while let Ok((uuid, millis)) = receiver.recv().await {
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                sender.send((uuid, millis)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.shared_endpoint(None);
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let endpoint = endpoint.clone();
+                tokio::spawn(async move { endpoint.handle_request(50).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(
+            endpoint.handle_request(0).await,
+            Err(EndpointError::TooManyInFlight)
+        );
+        for handle in handles {
+            assert_eq!(handle.await.unwrap(), Ok(50));
+        }
+        assert_eq!(endpoint.handle_request(0).await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_concurrency_limit() {
+        let router: Router<u64, u64> = Router::default();
+        router.spawn_workers_fn(4, |millis: u64| async move {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            millis
+        });
+        router.tokio_spawn();
+
+        // clones share the limit of the endpoint
+        let limited = router.endpoint(None).with_concurrency_limit(1);
+        let busy = tokio::spawn({
+            let limited = limited.clone();
+            async move { limited.handle_request(50).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(
+            limited.handle_request(0).await,
+            Err(EndpointError::ConcurrencyLimit)
+        );
+        // other endpoints of the router are not limited
+        assert_eq!(router.endpoint(None).handle_request(0).await, Ok(0));
+
+        // waiting requests are submitted once the slot is released
+        let waiting = limited.with_concurrency_mode(ConcurrencyMode::Wait);
+        assert_eq!(waiting.handle_request(0).await, Ok(0));
+        assert_eq!(busy.await.unwrap(), Ok(50));
+
+        // the wait for a slot counts towards the timeout of the request
+        let timed = router
+            .endpoint(Duration::from_millis(100))
+            .with_concurrency_limit(1)
+            .with_concurrency_mode(ConcurrencyMode::Wait);
+        let busy = tokio::spawn({
+            let timed = timed.clone();
+            async move { timed.handle_request(80).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(matches!(
+            timed.handle_request(80).await,
+            Err(EndpointError::Timeout(_))
+        ));
+        assert_eq!(busy.await.unwrap(), Ok(80));
+
+        // a timed out request releases its slot, even if its worker never
+        // answers in time
+        let timed = router
+            .endpoint(Duration::from_millis(20))
+            .with_concurrency_limit(1);
+        assert!(matches!(
+            timed.handle_request(300).await,
+            Err(EndpointError::Timeout(_))
+        ));
+        assert_eq!(timed.handle_request(0).await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume() {
+        let router: Router<u64, u64> = Router::default();
+        router.spawn_workers_fn(2, |millis: u64| async move {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            millis
+        });
+        router.tokio_spawn();
+        let endpoint = router.shared_endpoint(Duration::from_secs(1));
+
+        // in-flight requests finish while the router is paused
+        let in_flight = tokio::spawn({
+            let endpoint = endpoint.clone();
+            async move { endpoint.handle_request(100).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(router.pause());
+        assert!(!router.pause());
+        let waiting = tokio::spawn({
+            let endpoint = endpoint.clone();
+            async move { endpoint.handle_request(0).await }
+        });
+        assert_eq!(in_flight.await.unwrap(), Ok(100));
+        assert!(!waiting.is_finished());
+        assert!(matches!(
+            router
+                .endpoint(Duration::from_millis(20))
+                .handle_request(0)
+                .await,
+            Err(EndpointError::Timeout(_))
+        ));
+        assert_eq!(router.stats().in_flight, 0);
+
+        // waiting requests are registered once the router is resumed
+        assert!(router.resume());
+        assert_eq!(waiting.await.unwrap(), Ok(0));
+
+        // the wait for the router counts towards the timeout of the request
+        router.pause();
+        let timed = tokio::spawn({
+            let endpoint = router.endpoint(Duration::from_millis(100));
+            async move { endpoint.handle_request(80).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        router.resume();
+        assert!(matches!(
+            timed.await.unwrap(),
+            Err(EndpointError::Timeout(_))
+        ));
+
+        let rejecting = router.clone().with_pause_mode(PauseMode::Reject);
+        router.pause();
+        assert_eq!(
+            rejecting.endpoint(None).handle_request(0).await,
+            Err(EndpointError::Paused)
+        );
+        router.resume();
+        assert_eq!(rejecting.endpoint(None).handle_request(0).await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn test_key_throttling() {
+        // requests are (customer, millis) pairs throttled by customer
+        let router: Router<(u32, u64), u64> = Router::default().with_key_throttling(
+            KeyThrottle::new(|(customer, _): &(u32, u64)| *customer)
+                .max_concurrent(1)
+                .on_excess(Excess::Queue(1)),
+        );
+        router.tokio_spawn_workers(4, |receiver, sender| async move {
+            while let Ok((uuid, (_, millis))) = receiver.recv().await {
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                sender.send((uuid, millis)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.shared_endpoint(None);
+        let handles: Vec<_> = [50, 0]
+            .into_iter()
+            .map(|millis| {
+                let endpoint = endpoint.clone();
+                tokio::spawn(async move { endpoint.handle_request((1, millis)).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // the buffer of the customer holds the second request
+        assert_eq!(
+            endpoint.handle_request((1, 0)).await,
+            Err(EndpointError::Throttled)
+        );
+        assert_eq!(endpoint.handle_request((2, 0)).await, Ok(0));
+        for (handle, millis) in handles.into_iter().zip([50, 0]) {
+            assert_eq!(handle.await.unwrap(), Ok(millis));
+        }
+
+        // the wait in the buffer counts towards the timeout of the request
+        let timed = router.shared_endpoint(Duration::from_millis(100));
+        let busy = tokio::spawn({
+            let timed = timed.clone();
+            async move { timed.handle_request((1, 80)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(matches!(
+            timed.handle_request((1, 80)).await,
+            Err(EndpointError::Timeout(_))
+        ));
+        assert_eq!(busy.await.unwrap(), Ok(80));
+
+        // a timed out request releases the slot of its key, even if its
+        // worker never answers in time
+        let timed = router.endpoint(Duration::from_millis(20));
+        assert!(matches!(
+            timed.handle_request((3, 300)).await,
+            Err(EndpointError::Timeout(_))
+        ));
+        assert_eq!(timed.handle_request((3, 0)).await, Ok(0));
+
+        let router: Router<(u32, u64), u64> = Router::default().with_key_throttling(
+            KeyThrottle::new(|(customer, _): &(u32, u64)| *customer)
+                .max_per_second(10)
+                .on_excess(Excess::Queue(10)),
+        );
+        router.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, (_, millis))) = receiver.recv().await {
+                sender.send((uuid, millis)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        // a burst of 10 requests is dispatched right away, the next one once
+        // a token was refilled after 100ms
+        let endpoint = router.endpoint(None);
+        let started = std::time::Instant::now();
+        let responses =
+            futures::future::join_all((0..11).map(|_| endpoint.handle_request((1, 0)))).await;
+        assert!(responses.iter().all(|response| response == &Ok(0)));
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn test_scatter_gather() {
+        let router: Router<u32, u32> = Router::default();
+        async fn square(receiver: Receiver<(Uuid, u32)>, sender: Sender<(Uuid, u32)>) {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                sender.send((uuid, request * request)).await.unwrap();
+            }
+        }
+        router.tokio_spawn_workers(2, square);
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(Some(Duration::from_millis(100)));
+        assert_eq!(endpoint.scatter(3, 5).await, Ok(vec![9; 5]));
+        assert_eq!(endpoint.scatter(3, 0).await, Ok(vec![]));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(router.stats().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn test_try_handle_request() {
+        let router: Router<u32, Result<u32, String>> = Router::default();
+        router.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                let response = match request % 2 {
+                    0 => Ok(request / 2),
+                    _ => Err(format!("{} is odd", request)),
+                };
+                sender.send((uuid, response)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(None);
+        assert_eq!(endpoint.try_handle_request(4).await, Ok(2));
+        assert_eq!(
+            endpoint.try_handle_request(3).await,
+            Err(EndpointError::Rejected("3 is odd".to_string()))
+        );
+        router.shutdown();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(
+            endpoint.try_handle_request(4).await,
+            Err(EndpointError::<String>::RequestSend)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_default_timeout() {
+        let router: Router<String, String> =
+            Router::default().with_default_timeout(Duration::from_millis(20));
+        router.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                sender.send((uuid, request)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        let response = router.endpoint(None).handle_request("a".into()).await;
+        assert!(matches!(response, Err(EndpointError::Timeout(_))));
+        let response = router
+            .endpoint(Duration::from_millis(200))
+            .handle_request("b".into())
+            .await;
+        assert_eq!(response, Ok("b".to_string()));
+        let response = router
+            .endpoint(Timeout::None)
+            .handle_request("c".into())
+            .await;
+        assert_eq!(response, Ok("c".to_string()));
+    }
+    #[tokio::test]
+    async fn test_fallback() {
+        let router: Router<u64, String> =
+            Router::default().with_fallback(|millis| format!("stale {millis}"));
+        router.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, millis)) = receiver.recv().await {
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                sender.send((uuid, millis.to_string())).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(Duration::from_millis(20));
+        assert_eq!(endpoint.handle_request(0).await, Ok("0".to_string()));
+        assert_eq!(
+            endpoint.handle_request(50).await,
+            Ok("stale 50".to_string())
+        );
+        // the endpoint's fallback replaces the router's
+        let endpoint =
+            endpoint.with_async_fallback(|millis| async move { format!("default {millis}") });
+        assert_eq!(
+            endpoint.handle_request(50).await,
+            Ok("default 50".to_string())
+        );
+        // scattered requests still time out
+        let response = endpoint.scatter(50, 2).await;
+        assert!(matches!(response, Err(EndpointError::Timeout(_))));
+    }
+    #[tokio::test]
+    async fn test_validator() {
+        let router: Router<String, String> = RouterBuilder::new()
+            .metrics(true)
+            .build()
+            .with_validator(|request: &String| match request.is_empty() {
+                true => Err("empty request".into()),
+                false => Ok(()),
+            });
+        router.spawn_workers_fn(1, |request: String| async move { request });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(None);
+        assert_eq!(
+            endpoint.handle_request(String::new()).await,
+            Err(EndpointError::Invalid(RejectReason::new("empty request")))
+        );
+        assert_eq!(endpoint.handle_request("a".into()).await, Ok("a".into()));
+        // the invalid request was never registered
+        assert_eq!(router.metrics().unwrap().registered, 1);
+
+        let router = router.with_async_validator(|request| {
+            let len = request.len();
+            async move {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                match len {
+                    0..=3 => Ok(()),
+                    _ => Err(RejectReason::new(format!("{len} bytes is too long"))),
+                }
+            }
+        });
+        let response = router.endpoint(None).handle_request("abcd".into()).await;
+        assert_eq!(
+            response.unwrap_err().to_string(),
+            "Invalid request: 4 bytes is too long"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timed_responses() {
+        // the request channel only holds a single request
+        let router: Router<u64, u64> = Router::bounded(None, Some(1), None);
+        router.spawn_workers_fn(1, |request: u64| async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            request
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(None);
+        let timed = endpoint.handle_request_timed(1).await.unwrap();
+        assert_eq!(*timed, 1);
+        assert!(timed.processing >= Duration::from_millis(50));
+        assert!(timed.queue_wait < Duration::from_millis(50));
+        assert_eq!(timed.total, timed.queue_wait + timed.processing);
+
+        // the third request waits for the worker to take the second one
+        let (first, second, third) = tokio::join!(
+            endpoint.handle_request_timed(1),
+            endpoint.handle_request_timed(2),
+            endpoint.handle_request_timed(3),
+        );
+        let waits = [first, second, third].map(|timed| timed.unwrap().queue_wait);
+        assert!(waits.iter().max().unwrap() >= &Duration::from_millis(40));
+    }
+
+    #[cfg(feature = "derive")]
+    #[tokio::test]
+    async fn test_derived_request_enum() {
+        use crate::RequestEnum;
+
+        #[derive(Clone, RequestEnum)]
+        #[s2a4c(response = ApiResponse)]
+        enum ApiRequest {
+            #[s2a4c(response = String)]
+            GetUser(u64),
+            #[s2a4c(response = bool)]
+            DeleteUser(u64),
+        }
+
+        #[derive(Clone)]
+        enum ApiResponse {
+            GetUser(String),
+            DeleteUser(bool),
+        }
+
+        struct Users;
+
+        impl ApiRequestHandler for Users {
+            async fn get_user(&self, id: u64) -> String {
+                format!("user {id}")
+            }
+            async fn delete_user(&self, id: u64) -> bool {
+                id > 0
+            }
+        }
+
+        assert_eq!(
+            ApiRequest::DeleteUser(1).route(),
+            ApiRequestRoute::DeleteUser
+        );
+        assert_eq!(
+            ApiRequestRoute::ALL.map(ApiRequestRoute::name),
+            ["GetUser", "DeleteUser"]
+        );
+
+        let router: Router<ApiRequest, ApiResponse> = Router::default();
+        router.spawn_handler_workers(2, Users);
+        router.tokio_spawn();
+        let get_user = router.get_user_endpoint(None);
+        let delete_user = router.delete_user_endpoint(None);
+        assert_eq!(get_user.handle_request(7).await.unwrap(), "user 7");
+        assert_eq!(delete_user.handle_request(0).await, Ok(false));
+    }
 }
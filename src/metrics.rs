@@ -0,0 +1,138 @@
+//! # Metrics Module
+//!
+//! This module provides the [RouterMetrics] counters maintained by the
+//! [Router](crate::router::Router) loops, and the [MetricsSnapshot] struct
+//! exposing their values when metrics are enabled through the
+//! [RouterBuilder](crate::builder::RouterBuilder), along with the
+//! [LatencyPercentiles] of the most recent responses.
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Number of most recent response latencies the percentiles are computed
+/// from.
+const LATENCY_WINDOW: usize = 1024;
+
+/// Counters updated by the router loops.
+#[derive(Debug, Default)]
+pub(crate) struct RouterMetrics {
+    /// number of requests registered by the registration loop
+    pub(crate) registered: AtomicU64,
+    /// number of responses delivered to their endpoints
+    pub(crate) responded: AtomicU64,
+    /// number of responses without a matching pending request
+    pub(crate) orphaned: AtomicU64,
+    /// number of requests whose endpoint timed out
+    pub(crate) timed_out: AtomicU64,
+    /// number of requests answered from the response cache
+    pub(crate) cache_hits: AtomicU64,
+    /// number of responses arriving after their endpoint timed out
+    pub(crate) late: AtomicU64,
+    /// latencies of the most recent responses, if they are measured
+    pub(crate) latencies: Option<Latencies>,
+}
+
+impl RouterMetrics {
+    /// Creates new counters, keeping the latencies of the most recent
+    /// responses if `latencies` is `true`.
+    ///
+    /// Latencies are never kept on `wasm32` targets, where reading the clock
+    /// panics.
+    pub(crate) fn new(latencies: bool) -> Self {
+        Self {
+            latencies: (latencies && cfg!(not(target_arch = "wasm32"))).then(Latencies::default),
+            ..Self::default()
+        }
+    }
+    /// Increments `counter` by one.
+    pub(crate) fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Returns the current values of the counters.
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            registered: self.registered.load(Ordering::Relaxed),
+            responded: self.responded.load(Ordering::Relaxed),
+            orphaned: self.orphaned.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            late: self.late.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point in time values of the router's metrics counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MetricsSnapshot {
+    /// number of requests registered by the registration loop
+    pub registered: u64,
+    /// number of responses delivered to their endpoints
+    pub responded: u64,
+    /// number of responses without a matching pending request, e.g. because
+    /// the endpoint timed out
+    pub orphaned: u64,
+    /// number of requests whose endpoint timed out, counted once the deadline
+    /// of the endpoint passed, whether or not a worker answers later
+    pub timed_out: u64,
+    /// number of requests answered from the response cache without being
+    /// dispatched to the workers
+    pub cache_hits: u64,
+    /// number of responses arriving after their endpoint timed out, see
+    /// [RouterHooks::on_late_response](crate::hooks::RouterHooks::on_late_response)
+    pub late: u64,
+}
+
+/// The latencies, from registration to final response, of the most recent
+/// responses.
+#[derive(Debug, Default)]
+pub(crate) struct Latencies(Mutex<VecDeque<Duration>>);
+
+impl Latencies {
+    /// Records the latency of a response, dropping the oldest latency once
+    /// [LATENCY_WINDOW] latencies are kept.
+    pub(crate) fn record(&self, latency: Duration) {
+        let mut latencies = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        if latencies.len() == LATENCY_WINDOW {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+    /// Returns the percentiles of the kept latencies, or `None` if no
+    /// response was recorded yet.
+    pub(crate) fn percentiles(&self) -> Option<LatencyPercentiles> {
+        let mut sorted: Vec<_> = {
+            let latencies = self.0.lock().unwrap_or_else(|err| err.into_inner());
+            latencies.iter().copied().collect()
+        };
+        sorted.sort_unstable();
+        // nearest-rank percentile
+        let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+        (!sorted.is_empty()).then(|| LatencyPercentiles {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            samples: sorted.len(),
+        })
+    }
+}
+
+/// Percentiles of the latencies, from registration to final response, of the
+/// router's most recent responses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LatencyPercentiles {
+    /// median latency
+    pub p50: Duration,
+    /// latency 90% of the responses were faster than or as fast as
+    pub p90: Duration,
+    /// latency 99% of the responses were faster than or as fast as
+    pub p99: Duration,
+    /// number of most recent responses the percentiles are computed from
+    pub samples: usize,
+}
@@ -0,0 +1,611 @@
+//! # NATS Module
+//!
+//! This module provides a [NATS](https://nats.io) adapter for the
+//! [Router], enabled with the `nats` feature.
+//!
+//! ## Overview
+//!
+//! The adapter lets `s2a4c` act as an in-process facade over an existing
+//! messaging fabric, in both directions:
+//!
+//! - [Router::from_nats] creates a [Router] whose workers are remote NATS
+//!   responders. Every request received through an
+//!   [Endpoint](crate::endpoint::Endpoint) is published as a NATS request on
+//!   the given subject and the reply is routed back as the response.
+//! - [Router::tokio_spawn_nats_service] subscribes to a subject and pushes
+//!   every incoming NATS message through the router to the local workers,
+//!   publishing the response to the message's reply subject.
+//!
+//! Failures are signalled the way NATS services do, with a reply carrying
+//! the [ERROR_HEADER] and [ERROR_CODE_HEADER] headers and an empty payload:
+//! the service replies with them when a request can not be decoded or its
+//! [Endpoint](crate::endpoint::Endpoint) fails, and requests to remote
+//! responders replying with them fail, see [Router::from_nats].
+//!
+//! In both directions at most [MAX_PENDING_REQUESTS] requests are in flight
+//! at a time, further requests wait in the router's request channel or in the
+//! NATS subscription respectively.
+//!
+//! Requests and responses are encoded as JSON using
+//! [serde_json](https://docs.rs/serde_json), or with the [Codec] passed to
+//! [Router::from_nats_with_codec] and [Router::tokio_spawn_nats_service_with_codec].
+//! With the `bytes` feature, a `Router<Bytes, Bytes>` can instead pass the
+//! NATS payloads through as they are, see `Router::from_nats_bytes` and
+//! `Router::tokio_spawn_nats_service_bytes`.
+use std::{fmt, sync::Arc, time::Duration};
+
+use crate::channel::{Receiver, Sender};
+use ::bytes::Bytes;
+use async_nats::{Client, HeaderMap, Subject};
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+use crate::{
+    codec::{Codec, JsonCodec},
+    endpoint::EndpointError,
+    router::Router,
+};
+
+/// Name of the header of an error reply describing the error, as used by
+/// NATS services.
+pub const ERROR_HEADER: &str = "Nats-Service-Error";
+
+/// Name of the header of an error reply holding the HTTP-like status code of
+/// the error, as used by NATS services.
+pub const ERROR_CODE_HEADER: &str = "Nats-Service-Error-Code";
+
+/// Maximum number of requests awaiting their NATS reply, or being handled by
+/// the service, at a time.
+pub const MAX_PENDING_REQUESTS: usize = 1024;
+
+/// Conversion of the values of type `T` to and from NATS payloads.
+trait Payload<T>: Clone + Send + Sync + 'static {
+    /// error returned when a conversion fails
+    type Error: fmt::Debug + Send;
+
+    /// Converts `value` into a payload.
+    fn encode_payload(&self, value: T) -> Result<Bytes, Self::Error>;
+
+    /// Converts `payload` into a value.
+    fn decode_payload(&self, payload: Bytes) -> Result<T, Self::Error>;
+}
+
+impl<T: Serialize + DeserializeOwned, C: Codec> Payload<T> for C {
+    type Error = C::Error;
+
+    fn encode_payload(&self, value: T) -> Result<Bytes, Self::Error> {
+        self.encode(&value).map(Bytes::from)
+    }
+    fn decode_payload(&self, payload: Bytes) -> Result<T, Self::Error> {
+        self.decode(&payload)
+    }
+}
+
+/// [Payload] of `Bytes` values, which are the payloads themselves.
+#[cfg(feature = "bytes")]
+#[derive(Debug, Clone, Copy)]
+struct PassThrough;
+
+#[cfg(feature = "bytes")]
+impl Payload<Bytes> for PassThrough {
+    type Error = std::convert::Infallible;
+
+    fn encode_payload(&self, value: Bytes) -> Result<Bytes, Self::Error> {
+        Ok(value)
+    }
+    fn decode_payload(&self, payload: Bytes) -> Result<Bytes, Self::Error> {
+        Ok(payload)
+    }
+}
+
+/// Logs a failure of the adapter as a `tracing` warning with the `tracing`
+/// feature.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn warn(source: &str, err: &dyn fmt::Debug) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(source, error = ?err, "nats error");
+}
+
+/// Returns the HTTP-like status code of an error reply to a request failing
+/// with `err`, matching the status codes of the `axum` and `actix` helpers.
+fn status_code(err: &EndpointError) -> u16 {
+    match err {
+        EndpointError::Timeout(_) => 504,
+        EndpointError::Overloaded
+        | EndpointError::TooManyInFlight
+        | EndpointError::NoWorkers
+        | EndpointError::Paused
+        | EndpointError::RequestSend => 503,
+        EndpointError::Throttled | EndpointError::ConcurrencyLimit => 429,
+        EndpointError::Invalid(_) => 400,
+        EndpointError::ResponseReceive(_) | EndpointError::WorkerPanicked => 500,
+        EndpointError::Rejected(never) => match *never {},
+    }
+}
+
+/// Returns the headers of an error reply with status `code` and
+/// `description`.
+fn error_headers(code: u16, description: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    // header values can not span several lines
+    headers.insert(ERROR_HEADER, description.replace(['\r', '\n'], " "));
+    headers.insert(ERROR_CODE_HEADER, code.to_string());
+    headers
+}
+
+/// Converts the result of a request handled by the NATS service into the
+/// payload of its reply.
+///
+/// # Returns
+///
+/// Returns the encoded response, or the headers of the error reply if the
+/// request failed or its response could not be encoded.
+fn encode_reply<Response, P>(
+    result: Result<Response, EndpointError>,
+    payloads: &P,
+) -> Result<Bytes, HeaderMap>
+where
+    P: Payload<Response>,
+{
+    let response = result.map_err(|err| error_headers(status_code(&err), &err.to_string()))?;
+    payloads
+        .encode_payload(response)
+        .map_err(|err| error_headers(500, &format!("{:?}", err)))
+}
+
+/// Decodes the reply of a remote responder from its `headers` and
+/// `payload`.
+///
+/// # Returns
+///
+/// Returns the response, or the description of the error if the responder
+/// replied with an error, see [ERROR_HEADER], or the payload can not be
+/// decoded.
+fn decode_reply<Response, P>(
+    headers: Option<&HeaderMap>,
+    payload: Bytes,
+    payloads: &P,
+) -> Result<Response, String>
+where
+    P: Payload<Response>,
+{
+    if let Some(error) = headers.and_then(|headers| headers.get(ERROR_HEADER)) {
+        return Err(error.as_str().to_owned());
+    }
+    payloads
+        .decode_payload(payload)
+        .map_err(|err| format!("{:?}", err))
+}
+
+/// Publishes the reply to a request handled by the NATS service, an error
+/// reply with an empty payload if `reply` holds the headers of one.
+async fn publish_reply(client: &Client, subject: Subject, reply: Result<Bytes, HeaderMap>) {
+    let published = match reply {
+        Ok(payload) => client.publish(subject, payload).await,
+        Err(headers) => {
+            client
+                .publish_with_headers(subject, headers, Bytes::new())
+                .await
+        }
+    };
+    if let Err(err) = published {
+        warn("nats service", &err);
+    }
+}
+
+/// Asynchronous private worker function that forwards requests to NATS
+/// responders using request/reply.
+///
+/// # Arguments
+///
+/// - `receiver`: The router's request receiver.
+/// - `sender`: The router's response sender.
+/// - `subject`: The NATS subject requests are published on.
+/// - `client`: The NATS client used for publishing requests.
+/// - `payloads`: Converts requests to and responses from payloads.
+/// - `router`: The router, failing the requests that could not be answered.
+///
+/// # Behavior
+///
+/// Every request is encoded and sent in its own task so that a slow responder
+/// does not block the requests queued behind it, no further request is
+/// received while [MAX_PENDING_REQUESTS] requests await their reply. Requests which can not be
+/// encoded or sent, are answered with an error reply, or whose reply can
+/// not be decoded, are failed through [Router::fail_remote], so their
+/// [Endpoint](crate::endpoint::Endpoint) does not wait for a response that
+/// never comes.
+async fn request_reply_worker<Request, Response, P>(
+    receiver: Receiver<(Uuid, Request)>,
+    sender: Sender<(Uuid, Response)>,
+    subject: String,
+    client: Client,
+    payloads: P,
+    router: Router<Request, Response>,
+) where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+    P: Payload<Request> + Payload<Response>,
+{
+    let pending = Arc::new(Semaphore::new(MAX_PENDING_REQUESTS));
+    loop {
+        // the semaphore is never closed
+        let Ok(permit) = pending.clone().acquire_owned().await else {
+            return;
+        };
+        let Ok((uuid, request)) = receiver.recv().await else {
+            return;
+        };
+        let payload = match payloads.encode_payload(request) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn("nats worker", &err);
+                router.fail_remote(&uuid);
+                continue;
+            }
+        };
+        let sender = sender.clone();
+        let subject = subject.clone();
+        let client = client.clone();
+        let payloads = payloads.clone();
+        let router = router.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            let response = match client.request(subject, payload).await {
+                Ok(message) => decode_reply(message.headers.as_ref(), message.payload, &payloads),
+                Err(err) => Err(err.to_string()),
+            };
+            match response {
+                // the router was shut down if the response can not be sent
+                Ok(response) => {
+                    let _ = sender.send((uuid, response)).await;
+                }
+                Err(err) => {
+                    warn("nats worker", &err);
+                    router.fail_remote(&uuid);
+                }
+            }
+        });
+    }
+}
+
+impl<Request, Response> Router<Request, Response>
+where
+    Request: Serialize + DeserializeOwned + Send + 'static + Clone,
+    Response: Serialize + DeserializeOwned + Send + 'static + Clone,
+{
+    /// Creates a new [Router] with default channel capacities whose workers
+    /// are remote NATS responders listening on `subject`.
+    ///
+    /// # Arguments
+    ///
+    /// - `subject`: The NATS subject requests are published on.
+    /// - `client`: A connected NATS [Client].
+    ///
+    /// # Returns
+    ///
+    /// Returns the new [Router]. The router loops still need to be started
+    /// with [Router::tokio_spawn].
+    ///
+    /// # Behavior
+    ///
+    /// Requests which can not be encoded or sent, which the responder
+    /// answers with an error reply, see [ERROR_HEADER], or whose reply can
+    /// not be decoded, fail with
+    /// [EndpointError::ResponseReceive] and are reported as
+    /// [RouterError::RemoteFailed](crate::router::RouterError::RemoteFailed).
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn from_nats(subject: impl Into<String>, client: Client) -> Self {
+        Self::from_nats_with_codec(subject, client, JsonCodec)
+    }
+
+    /// Creates a new [Router] like [Router::from_nats], encoding requests
+    /// and responses with `codec` instead of JSON.
+    ///
+    /// # Arguments
+    ///
+    /// - `subject`: The NATS subject requests are published on.
+    /// - `client`: A connected NATS [Client].
+    /// - `codec`: The [Codec] the remote responders decode requests and
+    ///   encode responses with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn from_nats_with_codec(
+        subject: impl Into<String>,
+        client: Client,
+        codec: impl Codec,
+    ) -> Self {
+        Self::from_nats_with(subject.into(), client, codec)
+    }
+
+    /// Subscribes to `subject` and handles every incoming NATS message as a
+    /// request to this router, publishing the response to the message's
+    /// reply subject.
+    ///
+    /// # Arguments
+    ///
+    /// - `subject`: The NATS subject to subscribe to.
+    /// - `client`: A connected NATS [Client].
+    /// - `timeout`: An optional [Duration] applied to every request. If
+    ///   `None`, the router's default timeout is applied.
+    ///
+    /// # Returns
+    ///
+    /// Returns the handle of the spawned subscription task, which completes
+    /// if subscribing fails.
+    ///
+    /// # Behavior
+    ///
+    /// Messages which can not be decoded are answered with an error reply
+    /// with status `400`, requests whose
+    /// [Endpoint](crate::endpoint::Endpoint) fails with an error reply with
+    /// the status code of the error, e.g. `504` once they timed out, see
+    /// [ERROR_HEADER] and [ERROR_CODE_HEADER]. Messages without a reply
+    /// subject are dropped.
+    ///
+    /// At most [MAX_PENDING_REQUESTS] messages are handled at a time, no
+    /// further message is taken from the subscription until one of them was
+    /// replied to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn tokio_spawn_nats_service(
+        &self,
+        subject: impl Into<String>,
+        client: Client,
+        timeout: Option<Duration>,
+    ) -> tokio::task::JoinHandle<()> {
+        self.tokio_spawn_nats_service_with_codec(subject, client, timeout, JsonCodec)
+    }
+
+    /// Subscribes to `subject` like [Router::tokio_spawn_nats_service],
+    /// decoding requests and encoding responses with `codec` instead of JSON.
+    ///
+    /// # Arguments
+    ///
+    /// - `subject`: The NATS subject to subscribe to.
+    /// - `client`: A connected NATS [Client].
+    /// - `timeout`: An optional [Duration] applied to every request. If
+    ///   `None`, the router's default timeout is applied.
+    /// - `codec`: The [Codec] the remote requesters encode requests and
+    ///   decode responses with.
+    ///
+    /// # Returns
+    ///
+    /// Returns the handle of the spawned subscription task.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn tokio_spawn_nats_service_with_codec(
+        &self,
+        subject: impl Into<String>,
+        client: Client,
+        timeout: Option<Duration>,
+        codec: impl Codec,
+    ) -> tokio::task::JoinHandle<()> {
+        self.spawn_nats_service_with(subject.into(), client, timeout, codec)
+    }
+}
+
+impl<Request, Response> Router<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    /// Creates a new [Router] whose workers are remote NATS responders, see
+    /// [Router::from_nats_with_codec], converting the payloads with
+    /// `payloads`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime, the worker forwarding
+    /// the requests is spawned with [Router::tokio_spawn_workers].
+    fn from_nats_with<P>(subject: String, client: Client, payloads: P) -> Self
+    where
+        P: Payload<Request> + Payload<Response>,
+    {
+        let router = Self::default();
+        let failing = router.clone();
+        router.tokio_spawn_workers(1, move |receiver, sender| {
+            request_reply_worker(
+                receiver,
+                sender,
+                subject.clone(),
+                client.clone(),
+                payloads.clone(),
+                failing.clone(),
+            )
+        });
+        router
+    }
+
+    /// Subscribes to `subject`, see [Router::tokio_spawn_nats_service_with_codec],
+    /// converting the payloads with `payloads`.
+    fn spawn_nats_service_with<P>(
+        &self,
+        subject: String,
+        client: Client,
+        timeout: Option<Duration>,
+        payloads: P,
+    ) -> tokio::task::JoinHandle<()>
+    where
+        P: Payload<Request> + Payload<Response>,
+    {
+        let router = self.clone();
+        tokio::spawn(async move {
+            let mut subscriber = match client.subscribe(subject).await {
+                Ok(subscriber) => subscriber,
+                Err(err) => {
+                    warn("nats service", &err);
+                    return;
+                }
+            };
+            let pending = Arc::new(Semaphore::new(MAX_PENDING_REQUESTS));
+            loop {
+                // the semaphore is never closed
+                let Ok(permit) = pending.clone().acquire_owned().await else {
+                    return;
+                };
+                let Some(message) = subscriber.next().await else {
+                    return;
+                };
+                let Some(reply) = message.reply else {
+                    warn("nats service", &"message without reply subject");
+                    continue;
+                };
+                let request: Request = match payloads.decode_payload(message.payload) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        let headers = error_headers(400, &format!("{:?}", err));
+                        publish_reply(&client, reply, Err(headers)).await;
+                        continue;
+                    }
+                };
+                let endpoint = router.endpoint(timeout);
+                let client = client.clone();
+                let payloads = payloads.clone();
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    let result = endpoint.handle_request(request).await;
+                    publish_reply(&client, reply, encode_reply(result, &payloads)).await;
+                });
+            }
+        })
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl Router<Bytes, Bytes> {
+    /// Creates a new [Router] like [Router::from_nats], publishing the
+    /// requests as the NATS payloads and responding with the payloads of the
+    /// replies, without encoding or copying them.
+    ///
+    /// # Arguments
+    ///
+    /// - `subject`: The NATS subject requests are published on.
+    /// - `client`: A connected NATS [Client].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn from_nats_bytes(subject: impl Into<String>, client: Client) -> Self {
+        Self::from_nats_with(subject.into(), client, PassThrough)
+    }
+
+    /// Subscribes to `subject` like [Router::tokio_spawn_nats_service],
+    /// handling the payloads of the NATS messages as the requests and
+    /// publishing the responses as the reply payloads, without decoding or
+    /// copying them.
+    ///
+    /// # Arguments
+    ///
+    /// - `subject`: The NATS subject to subscribe to.
+    /// - `client`: A connected NATS [Client].
+    /// - `timeout`: An optional [Duration] applied to every request. If
+    ///   `None`, the router's default timeout is applied.
+    ///
+    /// # Returns
+    ///
+    /// Returns the handle of the spawned subscription task.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn tokio_spawn_nats_service_bytes(
+        &self,
+        subject: impl Into<String>,
+        client: Client,
+        timeout: Option<Duration>,
+    ) -> tokio::task::JoinHandle<()> {
+        self.spawn_nats_service_with(subject.into(), client, timeout, PassThrough)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use ::bytes::Bytes;
+
+    use crate::{
+        codec::JsonCodec,
+        endpoint::EndpointError,
+        router::{Router, RouterError},
+    };
+
+    use super::{decode_reply, encode_reply, Payload, ERROR_CODE_HEADER, ERROR_HEADER};
+
+    #[test]
+    fn test_payloads() {
+        let payload = JsonCodec.encode_payload(vec![1u32, 2]).unwrap();
+        assert_eq!(payload, Bytes::from_static(b"[1,2]"));
+        assert_eq!(
+            decode_reply::<Vec<u32>, _>(None, payload, &JsonCodec),
+            Ok(vec![1, 2])
+        );
+        assert!(decode_reply::<u32, _>(None, Bytes::from_static(b"x"), &JsonCodec).is_err());
+        #[cfg(feature = "bytes")]
+        {
+            let payload = Bytes::from_static(b"raw");
+            assert_eq!(
+                super::PassThrough.encode_payload(payload.clone()).unwrap(),
+                payload
+            );
+            assert_eq!(
+                decode_reply(None, payload.clone(), &super::PassThrough),
+                Ok(payload)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failures() {
+        // failed requests of the service are answered with an error reply
+        let router: Router<u32, u32> = Router::default();
+        router.tokio_spawn();
+        let result = router.endpoint(None).handle_request(1).await;
+        let headers = encode_reply(result, &JsonCodec).unwrap_err();
+        assert_eq!(headers.get(ERROR_CODE_HEADER).unwrap().as_str(), "503");
+        assert_eq!(
+            headers.get(ERROR_HEADER).unwrap().as_str(),
+            EndpointError::<Infallible>::NoWorkers.to_string()
+        );
+        assert_eq!(
+            encode_reply(Ok(7u32), &JsonCodec).unwrap(),
+            Bytes::from_static(b"7")
+        );
+
+        // error replies fail the requests of the router instead of their
+        // endpoints waiting for a response
+        assert_eq!(
+            decode_reply::<u32, _>(Some(&headers), Bytes::new(), &JsonCodec),
+            Err(EndpointError::<Infallible>::NoWorkers.to_string())
+        );
+        let errors = router.errors();
+        let failing = router.clone();
+        router.tokio_spawn_workers(1, move |receiver, _| {
+            let router = failing.clone();
+            async move {
+                while let Ok((uuid, _)) = receiver.recv().await {
+                    router.fail_remote(&uuid);
+                }
+            }
+        });
+        assert!(matches!(
+            router.endpoint(None).handle_request(1).await,
+            Err(EndpointError::ResponseReceive(_))
+        ));
+        assert!(matches!(
+            errors.recv().await,
+            Ok(RouterError::RemoteFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,92 @@
+//! # Pause Module
+//!
+//! This module provides the [PauseMode] enum deciding what happens to the
+//! requests submitted to a paused [Router], see [Router::pause].
+//!
+//! ## Overview
+//!
+//! Pausing a router stops its registration loop from pulling requests off
+//! the registration channel, while the requests registered before keep being
+//! dispatched to the workers and answered. Once [Router::pause] returned, no
+//! further request is pulled off the channel until [Router::resume] is
+//! called, a request the loop received concurrently with the pause is held
+//! back until the router is resumed.
+//!
+//! The endpoints of a paused router either wait for it to be resumed within
+//! the timeout of the request, or reject new requests with [EndpointError::Paused] right
+//! away. Draining or shutting a paused router down releases the endpoints
+//! waiting for it, their requests fail like the requests of a running router
+//! being drained or shut down.
+use tokio::sync::watch;
+
+use crate::channel::{Receiver, RecvError};
+#[cfg(doc)]
+use crate::{endpoint::EndpointError, router::Router};
+
+/// What happens to the requests submitted to a paused [Router], see
+/// [Router::with_pause_mode].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PauseMode {
+    /// requests wait for the router to be resumed, the wait counts towards
+    /// the timeout of the request, failing with [EndpointError::Timeout]
+    /// once it elapsed
+    #[default]
+    Wait,
+    /// requests are rejected with [EndpointError::Paused]
+    Reject,
+}
+
+/// Whether a [Router] pulls requests from its registration channel, shared
+/// by the router, its clones and its endpoints.
+#[derive(Debug)]
+pub(crate) struct Intake {
+    /// `true` while the router is paused
+    paused: watch::Sender<bool>,
+}
+
+impl Intake {
+    /// Creates a new `Intake` of a running router.
+    pub(crate) fn new() -> Self {
+        Self {
+            paused: watch::channel(false).0,
+        }
+    }
+    /// Pauses the intake, returning whether it was running.
+    pub(crate) fn pause(&self) -> bool {
+        !self.paused.send_replace(true)
+    }
+    /// Resumes the intake, returning whether it was paused.
+    pub(crate) fn resume(&self) -> bool {
+        self.paused.send_replace(false)
+    }
+    /// Returns whether the intake is paused.
+    pub(crate) fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+    /// Waits until the intake is running.
+    pub(crate) async fn resumed(&self) {
+        // the sender is borrowed, so the channel can not close while waiting
+        let _ = self.paused.subscribe().wait_for(|paused| !paused).await;
+    }
+    /// Receives the next message of `receiver` while the intake is running.
+    ///
+    /// # Behavior
+    ///
+    /// Nothing is received while the intake is paused. Pausing it cancels a
+    /// pending receive, a message received concurrently with the pause is
+    /// held back and returned once the intake is resumed.
+    pub(crate) async fn recv<T>(&self, receiver: &Receiver<T>) -> Result<T, RecvError> {
+        let mut paused = self.paused.subscribe();
+        loop {
+            let _ = paused.wait_for(|paused| !paused).await;
+            let received = tokio::select! {
+                received = receiver.recv() => Some(received),
+                _ = paused.wait_for(|paused| *paused) => None,
+            };
+            if let Some(received) = received {
+                let _ = paused.wait_for(|paused| !paused).await;
+                return received;
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! # Pipeline Module
+//!
+//! This module provides the [Pipeline] struct chaining several routers into a
+//! multi-stage request-response flow, see
+//! [Router::pipe_to](crate::router::Router::pipe_to).
+//!
+//! ## Overview
+//!
+//! The response of every stage becomes the request of the next stage, e.g.
+//! parse → enrich → persist, without glue workers forwarding responses by
+//! hand. All stages handle a request under the UUID assigned to it when it
+//! entered the pipeline, so the workers of every stage can correlate it, and
+//! the caller awaits the response of the final stage.
+use std::{fmt, future::Future, sync::Arc};
+
+use futures::future::BoxFuture;
+use uuid::Uuid;
+
+use crate::{
+    context::TraceContext,
+    endpoint::{Endpoint, EndpointError},
+    id::IdGenerator,
+    router::Router,
+};
+
+/// Submits a request to all stages up to and including the current one.
+type Stages<Request, Response> = Arc<
+    dyn Fn(Uuid, Request, TraceContext) -> BoxFuture<'static, Result<Response, EndpointError>>
+        + Send
+        + Sync,
+>;
+
+/// A chain of routers, the response of every router being the request of the
+/// next one.
+///
+/// # Type Parameters
+/// - `Request`: the request type of the first stage
+/// - `Response`: the response type of the final stage
+pub struct Pipeline<Request, Response> {
+    /// submits requests to the stages in order
+    stages: Stages<Request, Response>,
+    /// generates the UUIDs of the requests entering the pipeline
+    id_generator: IdGenerator,
+}
+
+// implemented by hand, deriving would require `Request` and `Response` to
+// implement the traits as well.
+impl<Request, Response> Clone for Pipeline<Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            stages: self.stages.clone(),
+            id_generator: self.id_generator.clone(),
+        }
+    }
+}
+
+impl<Request, Response> fmt::Debug for Pipeline<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("id_generator", &self.id_generator)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Request, Response> Pipeline<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    /// Creates a new single stage `Pipeline` submitting requests to `router`.
+    ///
+    /// Requests are submitted through an endpoint of the router created with
+    /// [Router::endpoint], so the router's default timeout applies to the
+    /// stage.
+    pub fn new(router: &Router<Request, Response>) -> Self {
+        let endpoint = router.endpoint(None);
+        Self {
+            stages: Arc::new(move |uuid, request, context| {
+                let endpoint = endpoint.clone();
+                Box::pin(async move { endpoint.handle_request_as(uuid, request, context).await })
+            }),
+            id_generator: router.id_generator(),
+        }
+    }
+    /// Appends a stage to the pipeline, the responses of the current final
+    /// stage become the requests of `next`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the extended pipeline, responding with the responses of `next`.
+    pub fn pipe_to<Next>(self, next: &Router<Response, Next>) -> Pipeline<Request, Next>
+    where
+        Next: Send + 'static + Clone,
+    {
+        let stages = self.stages;
+        let endpoint: Endpoint<Response, Next> = next.endpoint(None);
+        Pipeline {
+            stages: Arc::new(move |uuid, request, context| {
+                let previous = stages(uuid, request, context.clone());
+                let endpoint = endpoint.clone();
+                Box::pin(async move {
+                    let response = previous.await?;
+                    endpoint.handle_request_as(uuid, response, context).await
+                })
+            }),
+            id_generator: self.id_generator,
+        }
+    }
+    /// Submits `request` to the first stage and awaits the response of the
+    /// final stage.
+    ///
+    /// # Returns
+    ///
+    /// Returns the response of the final stage, or the error of the first
+    /// stage that failed.
+    pub async fn handle_request(&self, request: Request) -> Result<Response, EndpointError> {
+        self.handle_request_with_id(request).1.await
+    }
+    /// Handles a request like [Pipeline::handle_request], exposing the UUID
+    /// the request is handled under by all stages.
+    pub fn handle_request_with_id(
+        &self,
+        request: Request,
+    ) -> (
+        Uuid,
+        impl Future<Output = Result<Response, EndpointError>> + Send + 'static,
+    ) {
+        let uuid = self.id_generator.generate();
+        (uuid, (self.stages)(uuid, request, TraceContext::captured()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::router::Router;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_pipeline_preserves_uuid() {
+        let parse: Router<String, u32> = Router::default();
+        parse.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                sender.send((uuid, request.parse().unwrap())).await.unwrap();
+            }
+        });
+        parse.tokio_spawn();
+        let double: Router<u32, (Uuid, u32)> = Router::default();
+        double.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                sender.send((uuid, (uuid, request * 2))).await.unwrap();
+            }
+        });
+        double.tokio_spawn();
+
+        let pipeline = parse.pipe_to(&double);
+        let (uuid, response) = pipeline.handle_request_with_id("21".into());
+        assert_eq!(response.await, Ok((uuid, 42)));
+    }
+}
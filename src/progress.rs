@@ -0,0 +1,204 @@
+//! # Progress Module
+//!
+//! This module provides the [Update] enum letting workers report the progress
+//! of long-running requests before their final response, and the
+//! [ProgressHandle] struct exposing the updates to the caller.
+//!
+//! ## Overview
+//!
+//! A router whose response type is an `Update<Progress, Response>` and which
+//! was created with [Router::with_progress_updates] keeps a request pending
+//! until its worker sends an [Update::Done]. Every [Update::Progress] sent
+//! before is delivered to the [ProgressHandle] returned by
+//! [Endpoint::handle_request_with_progress].
+use crate::channel::{unbounded, Receiver, RecvError};
+use uuid::Uuid;
+
+use crate::{
+    context::TraceContext,
+    endpoint::{Delivery, Endpoint, EndpointError, PendingGuard},
+    router::Router,
+};
+
+/// A message sent by a worker for a request reporting progress.
+///
+/// # Type Parameters
+/// - `Progress`: the type of the progress updates
+/// - `Response`: the type of the final response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Update<Progress, Response> {
+    /// the request progressed, further updates follow
+    Progress(Progress),
+    /// the final response of the request
+    Done(Response),
+}
+
+impl<Progress, Response> Update<Progress, Response> {
+    /// Returns whether the update is the final response of its request.
+    pub fn is_done(&self) -> bool {
+        matches!(self, Update::Done(_))
+    }
+}
+
+impl<Request, Progress, Response> Router<Request, Update<Progress, Response>>
+where
+    Request: Send + 'static + Clone,
+    Progress: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    /// Keeps requests pending until their worker sends an [Update::Done],
+    /// delivering the preceding [Update::Progress] messages to the caller.
+    pub fn with_progress_updates(self) -> Self {
+        self.with_final_responses(Update::is_done)
+    }
+}
+
+/// Receives the progress updates and the final response of a request, see
+/// [Endpoint::handle_request_with_progress].
+#[derive(Debug)]
+pub struct ProgressHandle<'a, Request, Progress, Response> {
+    /// endpoint the request was submitted by
+    endpoint: &'a Endpoint<Request, Update<Progress, Response>>,
+    /// UUID of the request
+    id: Uuid,
+    /// receives the updates of the request
+    receiver: Receiver<Delivery<Update<Progress, Response>>>,
+    /// cancels and deregisters the request if the handle is dropped before
+    /// the final response was received
+    guard: PendingGuard,
+    /// whether the final response or an error was received
+    finished: bool,
+}
+
+impl<Request, Progress, Response> ProgressHandle<'_, Request, Progress, Response>
+where
+    Request: Send + 'static,
+    Progress: Send + 'static,
+    Response: Send + 'static,
+{
+    /// Returns the UUID of the request.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+    /// Receives the next update of the request.
+    ///
+    /// The timeout of the endpoint applies to waiting for every single
+    /// update, so requests reporting progress in time never time out.
+    ///
+    /// # Returns
+    ///
+    /// Returns the next update, or `None` once the final response or an
+    /// error was received.
+    pub async fn next(&mut self) -> Option<Result<Update<Progress, Response>, EndpointError>> {
+        if self.finished {
+            return None;
+        }
+        let update = self
+            .endpoint
+            .receive(&self.receiver, &self.guard, 1, self.endpoint.deadline(None))
+            .await
+            .map(|mut updates| updates.remove(0));
+        self.finished = !matches!(update, Ok(Update::Progress(_)));
+        if self.finished {
+            self.guard.disarm();
+        }
+        Some(update)
+    }
+    /// Skips the remaining progress updates and awaits the final response.
+    pub async fn response(mut self) -> Result<Response, EndpointError> {
+        while let Some(update) = self.next().await {
+            if let Update::Done(response) = update? {
+                return Ok(response);
+            }
+        }
+        Err(EndpointError::ResponseReceive(RecvError))
+    }
+}
+
+impl<Request, Progress, Response> Endpoint<Request, Update<Progress, Response>>
+where
+    Request: Send + 'static,
+    Progress: Send + 'static,
+    Response: Send + 'static,
+{
+    /// Submits `request` and returns a [ProgressHandle] receiving its
+    /// progress updates and final response.
+    ///
+    /// Dropping the handle before the final response was received cancels
+    /// the request.
+    ///
+    /// The router must have been created with
+    /// [Router::with_progress_updates], otherwise the first progress update
+    /// is taken as the final response.
+    pub async fn handle_request_with_progress(
+        &self,
+        request: Request,
+    ) -> Result<ProgressHandle<'_, Request, Progress, Response>, EndpointError> {
+        let id = self.generate_id();
+        let (receiver, guard) = self
+            .register_with(
+                id,
+                request,
+                TraceContext::captured(),
+                1,
+                None,
+                None,
+                self.deadline(None),
+                unbounded(),
+            )
+            .await?;
+        Ok(ProgressHandle {
+            endpoint: self,
+            id,
+            receiver,
+            guard,
+            finished: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Update;
+    use crate::router::Router;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_progress_updates() {
+        let router: Router<u32, Update<u32, String>> = Router::default().with_progress_updates();
+        router.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, steps)) = receiver.recv().await {
+                for step in 1..=steps {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    sender.send((uuid, Update::Progress(step))).await.unwrap();
+                }
+                sender
+                    .send((uuid, Update::Done("done".into())))
+                    .await
+                    .unwrap();
+            }
+        });
+        router.tokio_spawn();
+
+        // the timeout applies to every update, not to the whole request
+        let endpoint = router.endpoint(Duration::from_millis(50));
+        let mut handle = endpoint.handle_request_with_progress(4).await.unwrap();
+        let mut updates = Vec::new();
+        while let Some(update) = handle.next().await {
+            updates.push(update.unwrap());
+        }
+        assert_eq!(
+            updates,
+            [
+                Update::Progress(1),
+                Update::Progress(2),
+                Update::Progress(3),
+                Update::Progress(4),
+                Update::Done("done".to_string()),
+            ]
+        );
+        let handle = endpoint.handle_request_with_progress(2).await.unwrap();
+        assert_eq!(handle.response().await, Ok("done".to_string()));
+        assert_eq!(router.stats().in_flight, 0);
+    }
+}
@@ -0,0 +1,427 @@
+//! # Recorder Module
+//!
+//! This module provides the [Recorder] struct, an opt-in audit log of the
+//! requests handled by the endpoints of a [Router], the [Record] struct
+//! describing a single request, and the [RecordSink] trait for passing the
+//! records on to a [FileSink] or a user callback. Recorded requests can be
+//! re-submitted with [Router::replay] and [Router::replay_range].
+//!
+//! ## Overview
+//!
+//! Once a recorder is set with [Router::with_recorder], every request handled
+//! by an endpoint of the router is recorded along with its UUID, its response,
+//! the time it was submitted at, how long it took and its [Outcome], including
+//! requests rejected before reaching the router. The recorder keeps the most
+//! recent records in an in-memory ring buffer, which replaying looks the
+//! requests up in, and passes every record on to its sinks.
+//!
+//! Requests are recorded by [Endpoint::handle_request] and its variants,
+//! scattered requests are not recorded. Recording reads the system clock,
+//! which is unavailable on `wasm32` targets.
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, LineWriter, Write},
+    ops::RangeBounds,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use uuid::Uuid;
+
+#[cfg(doc)]
+use crate::endpoint::Endpoint;
+use crate::{
+    endpoint::{EndpointError, Timeout},
+    router::Router,
+};
+
+/// How the handling of a recorded request ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    /// the request was answered with a response
+    Responded,
+    /// the request could not be submitted to the router, see
+    /// [EndpointError::RequestSend]
+    RequestSend,
+    /// the response could not be received, see
+    /// [EndpointError::ResponseReceive]
+    ResponseReceive,
+    /// the request timed out, see [EndpointError::Timeout]
+    Timeout,
+    /// the request was rejected by load shedding, see
+    /// [EndpointError::Overloaded]
+    Overloaded,
+    /// the request was rejected by an in-flight limit, see
+    /// [EndpointError::TooManyInFlight]
+    TooManyInFlight,
+    /// the request was rejected by the throttling of its key, see
+    /// [EndpointError::Throttled]
+    Throttled,
+    /// the request was rejected by the concurrency limit of its endpoint, see
+    /// [EndpointError::ConcurrencyLimit]
+    ConcurrencyLimit,
+    /// no workers were attached to the router, see
+    /// [EndpointError::NoWorkers]
+    NoWorkers,
+    /// the router was paused, see [EndpointError::Paused]
+    Paused,
+    /// the request was rejected by the validator of the router, see
+    /// [EndpointError::Invalid]
+    Invalid,
+    /// the worker handling the request panicked, see
+    /// [EndpointError::WorkerPanicked]
+    WorkerPanicked,
+}
+
+impl From<&EndpointError> for Outcome {
+    fn from(err: &EndpointError) -> Self {
+        match err {
+            EndpointError::RequestSend => Outcome::RequestSend,
+            EndpointError::ResponseReceive(_) => Outcome::ResponseReceive,
+            EndpointError::Timeout(_) => Outcome::Timeout,
+            EndpointError::Overloaded => Outcome::Overloaded,
+            EndpointError::TooManyInFlight => Outcome::TooManyInFlight,
+            EndpointError::Throttled => Outcome::Throttled,
+            EndpointError::ConcurrencyLimit => Outcome::ConcurrencyLimit,
+            EndpointError::NoWorkers => Outcome::NoWorkers,
+            EndpointError::Paused => Outcome::Paused,
+            EndpointError::Invalid(_) => Outcome::Invalid,
+            EndpointError::WorkerPanicked => Outcome::WorkerPanicked,
+            EndpointError::Rejected(never) => match *never {},
+        }
+    }
+}
+
+/// A request handled by an endpoint of a [Router], as recorded by its
+/// [Recorder].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record<Request, Response> {
+    /// UUID the request was handled under
+    pub uuid: Uuid,
+    /// the request itself
+    pub request: Request,
+    /// the response, if the request was answered
+    pub response: Option<Response>,
+    /// time the request was submitted at
+    pub submitted_at: SystemTime,
+    /// time from submitting the request until it was answered or failed
+    pub elapsed: Duration,
+    /// timeout of the endpoint the request was submitted by
+    pub timeout: Option<Duration>,
+    /// how the handling of the request ended
+    pub outcome: Outcome,
+}
+
+/// Destination records are passed on to by a [Recorder].
+///
+/// Implemented for closures taking a [Record], and by [FileSink].
+pub trait RecordSink<Request, Response>: Send + Sync {
+    /// Receives the record of a request once it was handled.
+    fn record(&self, record: &Record<Request, Response>);
+}
+
+impl<Request, Response, F> RecordSink<Request, Response> for F
+where
+    F: Fn(&Record<Request, Response>) + Send + Sync,
+{
+    fn record(&self, record: &Record<Request, Response>) {
+        self(record)
+    }
+}
+
+/// A [RecordSink] appending the [Debug](fmt::Debug) representation of every
+/// record to a file, one record per line.
+///
+/// Records are written synchronously under a [std::sync::Mutex] by the task of
+/// the endpoint handling the request, blocking the executor's thread for the
+/// duration of the write. Where that matters, pass the records on to a
+/// dedicated thread with a callback sink instead, e.g. over a channel. Failed
+/// writes are logged as `tracing` warnings with the `tracing` feature.
+#[derive(Debug)]
+pub struct FileSink {
+    /// the file records are appended to
+    file: Mutex<LineWriter<File>>,
+}
+
+impl FileSink {
+    /// Opens the file at `path` for appending records, creating it if it
+    /// doesn't exist.
+    pub fn append(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(LineWriter::new(file)),
+        })
+    }
+}
+
+impl<Request, Response> RecordSink<Request, Response> for FileSink
+where
+    Request: fmt::Debug,
+    Response: fmt::Debug,
+{
+    fn record(&self, record: &Record<Request, Response>) {
+        let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = writeln!(file, "{:?}", record) {
+            warn(&err);
+        }
+    }
+}
+
+/// Logs a failed write of a [FileSink] as a `tracing` warning with the
+/// `tracing` feature.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn warn(err: &dyn fmt::Debug) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(error = ?err, "file sink error");
+}
+
+/// Type erased interface of a [Recorder] used by endpoints.
+pub(crate) trait Recording<Request, Response>: fmt::Debug + Send + Sync {
+    /// Returns a copy of `request` to be recorded once it was handled.
+    fn copy_request(&self, request: &Request) -> Request;
+    /// Records the handling of `request`, ended with `result`.
+    fn record(
+        &self,
+        uuid: Uuid,
+        request: Request,
+        result: &Result<Response, EndpointError>,
+        submitted_at: SystemTime,
+        timeout: Option<Duration>,
+    );
+}
+
+/// An audit log of the requests handled by the endpoints of a [Router],
+/// keeping the most recent records and passing every record on to its sinks.
+///
+/// # Type Parameters
+/// - `Request`: the request type of the router
+/// - `Response`: the response type of the router
+pub struct Recorder<Request, Response> {
+    /// the most recent records, oldest first
+    records: Mutex<VecDeque<Record<Request, Response>>>,
+    /// maximum number of records kept
+    capacity: usize,
+    /// destinations every record is passed on to
+    sinks: Vec<Box<dyn RecordSink<Request, Response>>>,
+}
+
+impl<Request, Response> Recorder<Request, Response>
+where
+    Request: Clone,
+    Response: Clone,
+{
+    /// Creates a new `Recorder` keeping the `capacity` most recent records,
+    /// the oldest record is dropped once it is reached. With a `capacity` of
+    /// zero records are only passed on to the sinks.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            sinks: Vec::new(),
+        }
+    }
+    /// Adds a sink every record is passed on to.
+    pub fn with_sink(mut self, sink: impl RecordSink<Request, Response> + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+    /// Returns the kept records, oldest first.
+    pub fn records(&self) -> Vec<Record<Request, Response>> {
+        self.lock().iter().cloned().collect()
+    }
+    /// Returns the kept record of the request handled under `uuid`, if any.
+    pub fn get(&self, uuid: &Uuid) -> Option<Record<Request, Response>> {
+        self.lock()
+            .iter()
+            .find(|record| record.uuid == *uuid)
+            .cloned()
+    }
+    /// Returns the kept records of the requests submitted within `range`,
+    /// oldest first.
+    pub fn range(&self, range: impl RangeBounds<SystemTime>) -> Vec<Record<Request, Response>> {
+        self.lock()
+            .iter()
+            .filter(|record| range.contains(&record.submitted_at))
+            .cloned()
+            .collect()
+    }
+    /// Locks the kept records, recovering them if a sink panicked.
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Record<Request, Response>>> {
+        self.records.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl<Request, Response> Recording<Request, Response> for Recorder<Request, Response>
+where
+    Request: Clone + Send,
+    Response: Clone + Send,
+{
+    fn copy_request(&self, request: &Request) -> Request {
+        request.clone()
+    }
+    fn record(
+        &self,
+        uuid: Uuid,
+        request: Request,
+        result: &Result<Response, EndpointError>,
+        submitted_at: SystemTime,
+        timeout: Option<Duration>,
+    ) {
+        let record = Record {
+            uuid,
+            request,
+            response: result.as_ref().ok().cloned(),
+            // the system clock may have been set back meanwhile
+            elapsed: submitted_at.elapsed().unwrap_or_default(),
+            submitted_at,
+            timeout,
+            outcome: result
+                .as_ref()
+                .err()
+                .map_or(Outcome::Responded, Outcome::from),
+        };
+        for sink in &self.sinks {
+            sink.record(&record);
+        }
+        if self.capacity > 0 {
+            let mut records = self.lock();
+            if records.len() == self.capacity {
+                records.pop_front();
+            }
+            records.push_back(record);
+        }
+    }
+}
+
+impl<Request, Response> fmt::Debug for Recorder<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("capacity", &self.capacity)
+            .field("sinks", &self.sinks.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Request, Response> Router<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    /// Re-submits the recorded request handled under `uuid`, see
+    /// [Router::with_recorder].
+    ///
+    /// The request is submitted under a new UUID, with the timeout it was
+    /// recorded with, and is recorded again.
+    ///
+    /// # Returns
+    ///
+    /// Returns the outcome of the replayed request, or `None` if the
+    /// router has no recorder or the recorder doesn't keep a record of the
+    /// request.
+    pub async fn replay(&self, uuid: &Uuid) -> Option<Result<Response, EndpointError>> {
+        let record = self.recorder()?.get(uuid)?;
+        Some(self.replay_record(record).await)
+    }
+    /// Re-submits the recorded requests submitted within `range` one after
+    /// another, oldest first, like [Router::replay].
+    ///
+    /// # Returns
+    ///
+    /// Returns the UUIDs the requests were recorded under along with the
+    /// outcomes of their replays.
+    pub async fn replay_range(
+        &self,
+        range: impl RangeBounds<SystemTime>,
+    ) -> Vec<(Uuid, Result<Response, EndpointError>)> {
+        let records = self
+            .recorder()
+            .map_or_else(Vec::new, |recorder| recorder.range(range));
+        let mut replayed = Vec::with_capacity(records.len());
+        for record in records {
+            replayed.push((record.uuid, self.replay_record(record).await));
+        }
+        replayed
+    }
+    /// Re-submits the request of `record` with the timeout it was recorded
+    /// with.
+    async fn replay_record(
+        &self,
+        record: Record<Request, Response>,
+    ) -> Result<Response, EndpointError> {
+        let timeout = record.timeout.map_or(Timeout::None, Timeout::After);
+        self.endpoint(timeout).handle_request(record.request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Outcome, Recorder};
+    use crate::{endpoint::EndpointError, router::Router};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, SystemTime},
+    };
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let sunk = Arc::new(AtomicUsize::new(0));
+        let recorder = Recorder::new(2).with_sink({
+            let sunk = sunk.clone();
+            move |_: &_| {
+                sunk.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        let router: Router<u32, u32> = Router::default().with_recorder(recorder);
+        let handled = Arc::new(AtomicUsize::new(0));
+        router.tokio_spawn_workers(1, |receiver, sender| {
+            let handled = handled.clone();
+            async move {
+                while let Ok((uuid, request)) = receiver.recv().await {
+                    handled.fetch_add(1, Ordering::SeqCst);
+                    if request > 0 {
+                        sender.send((uuid, request * 2)).await.unwrap();
+                    }
+                }
+            }
+        });
+        router.tokio_spawn();
+
+        let start = SystemTime::now();
+        let endpoint = router.endpoint(Duration::from_millis(20));
+        let (uuid, response) = endpoint.handle_request_with_id(1);
+        assert_eq!(response.await, Ok(2));
+        let response = endpoint.handle_request(0).await;
+        assert!(matches!(response, Err(EndpointError::Timeout(_))));
+        let response = endpoint.handle_request(3).await;
+        assert_eq!(response, Ok(6));
+
+        // the ring buffer keeps the most recent records, the sink got all
+        let recorder = router.recorder().unwrap();
+        let records = recorder.records();
+        assert_eq!(sunk.load(Ordering::SeqCst), 3);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].request, 0);
+        assert_eq!(records[0].response, None);
+        assert_eq!(records[0].outcome, Outcome::Timeout);
+        assert_eq!(records[1].response, Some(6));
+        assert_eq!(records[1].outcome, Outcome::Responded);
+        assert_eq!(records[1].timeout, Some(Duration::from_millis(20)));
+        assert!(recorder.get(&uuid).is_none());
+
+        // replayed requests are handled again, and recorded themselves
+        let uuid = records[1].uuid;
+        assert_eq!(router.replay(&uuid).await, Some(Ok(6)));
+        assert_eq!(handled.load(Ordering::SeqCst), 4);
+        assert_eq!(router.replay(&uuid::Uuid::nil()).await, None);
+        let records = recorder.records();
+        assert_eq!(records[0].uuid, uuid);
+        let replayed = router.replay_range(start..).await;
+        assert_eq!(replayed, [(uuid, Ok(6)), (records[1].uuid, Ok(6))]);
+    }
+}
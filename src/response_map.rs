@@ -0,0 +1,186 @@
+//! # Response Map Module
+//!
+//! This module provides the crate-private [PendingMap] trait abstracting the
+//! map the [Router](crate::router::Router) keeps its pending requests in, and
+//! the [Sharded] struct spreading a map over several shards.
+//!
+//! ## Overview
+//!
+//! Every request is inserted into the response map by the registration loop,
+//! read and removed by the response loop, and removed by its endpoint when
+//! nobody waits for the response anymore. Under high throughput these
+//! accesses contend on the same map, so the router splits it into
+//! [RouterBuilder::response_map_shards](crate::builder::RouterBuilder::response_map_shards)
+//! independent maps, picking the shard of a request by the low bits of its
+//! UUID, which are random for version 4 and version 7 UUIDs alike.
+use std::future::Future;
+
+use uuid::Uuid;
+
+/// Default number of shards of the response map.
+pub(crate) const DEFAULT_SHARDS: usize = 16;
+
+/// The map of the router's pending requests, by their UUID.
+///
+/// The methods mirror the ones of [scc::HashMap], the default backend.
+pub(crate) trait PendingMap<V>: Send + Sync {
+    /// Inserts `value` under `uuid`, returning it back if the UUID is
+    /// already in use.
+    fn insert_async(&self, uuid: Uuid, value: V) -> impl Future<Output = Result<(), V>> + Send;
+    /// Calls `reader` with the value under `uuid`, if any.
+    fn read_async<R, F>(&self, uuid: &Uuid, reader: F) -> impl Future<Output = Option<R>> + Send
+    where
+        R: Send,
+        F: FnOnce(&Uuid, &V) -> R + Send;
+    /// Calls `reader` with the value under `uuid`, if any, blocking the
+    /// thread on contention.
+    fn read<R>(&self, uuid: &Uuid, reader: impl FnOnce(&Uuid, &V) -> R) -> Option<R>;
+    /// Removes the value under `uuid`, if any.
+    fn remove_async(&self, uuid: &Uuid) -> impl Future<Output = Option<(Uuid, V)>> + Send;
+    /// Removes the value under `uuid`, if any, blocking the thread on
+    /// contention.
+    fn remove(&self, uuid: &Uuid) -> Option<(Uuid, V)>;
+    /// Keeps only the values for which `keep` returns `true`.
+    fn retain(&self, keep: impl FnMut(&Uuid, &mut V) -> bool);
+    /// Returns the number of values in the map.
+    fn len(&self) -> usize;
+    /// Returns whether the map holds no values.
+    fn is_empty(&self) -> bool;
+}
+
+impl<V> PendingMap<V> for scc::HashMap<Uuid, V>
+where
+    V: Send + Sync,
+{
+    async fn insert_async(&self, uuid: Uuid, value: V) -> Result<(), V> {
+        scc::HashMap::insert_async(self, uuid, value)
+            .await
+            .map_err(|(_, value)| value)
+    }
+    async fn read_async<R, F>(&self, uuid: &Uuid, reader: F) -> Option<R>
+    where
+        R: Send,
+        F: FnOnce(&Uuid, &V) -> R + Send,
+    {
+        scc::HashMap::read_async(self, uuid, reader).await
+    }
+    fn read<R>(&self, uuid: &Uuid, reader: impl FnOnce(&Uuid, &V) -> R) -> Option<R> {
+        scc::HashMap::read(self, uuid, reader)
+    }
+    async fn remove_async(&self, uuid: &Uuid) -> Option<(Uuid, V)> {
+        scc::HashMap::remove_async(self, uuid).await
+    }
+    fn remove(&self, uuid: &Uuid) -> Option<(Uuid, V)> {
+        scc::HashMap::remove(self, uuid)
+    }
+    fn retain(&self, keep: impl FnMut(&Uuid, &mut V) -> bool) {
+        scc::HashMap::retain(self, keep);
+    }
+    fn len(&self) -> usize {
+        scc::HashMap::len(self)
+    }
+    fn is_empty(&self) -> bool {
+        scc::HashMap::is_empty(self)
+    }
+}
+
+/// A [PendingMap] split into independent shards, the shard of a UUID is
+/// picked by its low bits.
+#[derive(Debug)]
+pub(crate) struct Sharded<M> {
+    /// the shards, at least one
+    shards: Box<[M]>,
+}
+
+impl<M> Sharded<M>
+where
+    M: Default,
+{
+    /// Creates a new `Sharded` map with `shards` empty shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    pub(crate) fn new(shards: usize) -> Self {
+        assert!(shards > 0, "the response map needs at least one shard");
+        Self {
+            shards: (0..shards).map(|_| M::default()).collect(),
+        }
+    }
+}
+
+impl<M> Sharded<M> {
+    /// Returns the shard holding the value under `uuid`.
+    fn shard(&self, uuid: &Uuid) -> &M {
+        // truncating keeps the random low bits of the UUID
+        &self.shards[uuid.as_u64_pair().1 as usize % self.shards.len()]
+    }
+}
+
+impl<V, M> PendingMap<V> for Sharded<M>
+where
+    V: Send + Sync,
+    M: PendingMap<V>,
+{
+    async fn insert_async(&self, uuid: Uuid, value: V) -> Result<(), V> {
+        self.shard(&uuid).insert_async(uuid, value).await
+    }
+    async fn read_async<R, F>(&self, uuid: &Uuid, reader: F) -> Option<R>
+    where
+        R: Send,
+        F: FnOnce(&Uuid, &V) -> R + Send,
+    {
+        self.shard(uuid).read_async(uuid, reader).await
+    }
+    fn read<R>(&self, uuid: &Uuid, reader: impl FnOnce(&Uuid, &V) -> R) -> Option<R> {
+        self.shard(uuid).read(uuid, reader)
+    }
+    async fn remove_async(&self, uuid: &Uuid) -> Option<(Uuid, V)> {
+        self.shard(uuid).remove_async(uuid).await
+    }
+    fn remove(&self, uuid: &Uuid) -> Option<(Uuid, V)> {
+        self.shard(uuid).remove(uuid)
+    }
+    fn retain(&self, mut keep: impl FnMut(&Uuid, &mut V) -> bool) {
+        for shard in &self.shards {
+            shard.retain(&mut keep);
+        }
+    }
+    fn len(&self) -> usize {
+        self.shards.iter().map(PendingMap::len).sum()
+    }
+    fn is_empty(&self) -> bool {
+        self.shards.iter().all(PendingMap::is_empty)
+    }
+}
+
+/// The response map of the router, sharded [scc::HashMap]s.
+pub(crate) type ResponseMap<V> = Sharded<scc::HashMap<Uuid, V>>;
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{PendingMap, ResponseMap};
+
+    #[tokio::test]
+    async fn test_sharded_map() {
+        let map: ResponseMap<usize> = ResponseMap::new(4);
+        let uuids: Vec<Uuid> = (0..64).map(|_| Uuid::new_v4()).collect();
+        for (value, uuid) in uuids.iter().enumerate() {
+            assert_eq!(map.insert_async(*uuid, value).await, Ok(()));
+        }
+        assert_eq!(map.insert_async(uuids[0], 0).await, Err(0));
+        assert_eq!(map.len(), 64);
+        // UUIDs spread over all shards
+        assert!(map.shards.iter().all(|shard| !shard.is_empty()));
+
+        assert_eq!(map.read_async(&uuids[7], |_, value| *value).await, Some(7));
+        assert_eq!(map.remove_async(&uuids[7]).await, Some((uuids[7], 7)));
+        assert_eq!(map.read(&uuids[7], |_, value| *value), None);
+        map.retain(|_, value| *value % 2 == 0);
+        assert_eq!(map.len(), 32);
+        map.retain(|_, _| false);
+        assert!(map.is_empty());
+    }
+}
@@ -15,13 +15,57 @@
 //!
 //! Also provided is a default implementation for easy instantiation with
 //! pre-configured channel capacities.
-use std::{future::Future, sync::Arc, time::Duration};
+use std::{
+    fmt,
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
+};
 
-use async_channel::{bounded, unbounded, Receiver, Sender};
-use scc::HashMap;
+use crate::channel::{bounded, unbounded, Receiver, Sender};
+use futures::{future::BoxFuture, StreamExt};
+use thiserror::Error;
+use tokio_util::{sync::CancellationToken, task::AbortOnDropHandle, time::DelayQueue};
 use uuid::Uuid;
 
-use crate::endpoint::Endpoint;
+#[cfg(feature = "rayon")]
+use crate::worker::RayonWorker;
+use crate::{
+    adapt::MappedEndpoint,
+    builder::RouterBuilder,
+    cache::{Cache, CacheKey, ResponseCache},
+    context::TraceContext,
+    deadline::DeadlineQueue,
+    deferred::{deferred_worker_loop, ResponseHandle},
+    dispatch::{
+        DispatchEngine, DispatchStrategy, LeastLoaded, Queued, RequestQueues, SchedulingQueue,
+        Sticky, WorkerChannels,
+    },
+    endpoint::{
+        Delivery, Endpoint, Fallback, InFlightLimit, InFlightSlot, LoadShedding, Registration,
+        RegistrationKind, RejectReason, Tenancy, Timeout, Validator, WorkerPanicked,
+    },
+    health::{HealthProbes, HealthReport},
+    hooks::{RouterHooks, SlowRequest},
+    id::IdGenerator,
+    metrics::{MetricsSnapshot, RouterMetrics},
+    pause::{Intake, PauseMode},
+    pipeline::Pipeline,
+    recorder::Recorder,
+    response_map::{PendingMap, ResponseMap},
+    spawn::{tokio_spawn_named, Spawn},
+    stats::{DrainReport, RouterStats},
+    tenant::{TenantLimits, TenantQueues, DEFAULT_TENANT},
+    throttle::KeyThrottle,
+    timed::DispatchedAt,
+    worker::{
+        concurrent_worker_loop, worker_loop, BlockingWorker, FnWorker, Worker, WorkerAttachment,
+    },
+};
 
 #[derive(Debug, Clone)]
 /// The `Router` struct is responsible for routing requests and responses
@@ -33,13 +77,13 @@ use crate::endpoint::Endpoint;
 /// - `Response`: any type that implements [Send] + [Clone] + 'static
 pub struct Router<Request, Response> {
     /// used by Endpoints to send incoming requests to the router for processing
-    registration_sender: Sender<(Request, Sender<Response>)>,
+    registration_sender: Sender<Registration<Request, Response>>,
     /// used by the router's registration loop to receiving new requests and
     /// their corresponding response senders
-    registration_receiver: Receiver<(Request, Sender<Response>)>,
-    /// used by the registration loop to sending requests along with their unique
+    registration_receiver: Receiver<Registration<Request, Response>>,
+    /// used by the registration loop to send requests along with their unique
     /// identifiers to workers
-    request_sender: Sender<(Uuid, Request)>,
+    queues: RequestQueues<Request>,
     /// used by the router's response loop to receive responses along with their
     /// unique identifiers
     request_receiver: Receiver<(Uuid, Request)>,
@@ -48,8 +92,183 @@ pub struct Router<Request, Response> {
     /// used by the router's response loop to receive responses along with their
     /// unique identifiers
     response_receiver: Receiver<(Uuid, Response)>,
-    /// maps unique request IDs to their corresponding response senders
-    response_map: Arc<HashMap<Uuid, Sender<Response>>>,
+    /// maps unique request IDs to their corresponding pending requests
+    response_map: Arc<ResponseMap<Pending<Response>>>,
+    /// timeout used by endpoints created without an explicit one
+    default_timeout: Option<Duration>,
+    /// used by endpoints to generate the unique request IDs
+    id_generator: IdGenerator,
+    /// counters maintained by the router loops
+    metrics: Arc<RouterMetrics>,
+    /// whether the counters are exposed through [Router::metrics]
+    metrics_enabled: bool,
+    /// stops the router loops once cancelled
+    shutdown_token: CancellationToken,
+    /// number of running workers spawned by the router
+    workers: Arc<AtomicUsize>,
+    /// optional cache answering requests without dispatching them to workers
+    cache: Option<Arc<dyn Cache<Request, Response>>>,
+    /// carries health check pings to workers and back
+    health: HealthProbes,
+    /// number of queued requests at which endpoints reject new requests
+    high_watermark: Option<usize>,
+    /// limit of the number of in-flight requests enforced by endpoints
+    in_flight_limit: Option<InFlightLimit>,
+    /// per-key throttling applied by the endpoints, see
+    /// [Router::with_key_throttling]
+    throttle: Option<KeyThrottle<Request>>,
+    /// responds to requests that timed out, see [Router::with_fallback]
+    fallback: Option<Fallback<Request, Response>>,
+    /// validates requests before they are registered, see
+    /// [Router::with_validator]
+    validator: Option<Validator<Request>>,
+    /// whether the registration loop pulls requests, see [Router::pause]
+    intake: Arc<Intake>,
+    /// what happens to the requests submitted while the router is paused
+    pause_mode: PauseMode,
+    /// spawns the router's tasks
+    spawner: Arc<dyn Spawn>,
+    /// prefix of the names of the tasks spawned on the tokio runtime
+    task_name_prefix: Arc<str>,
+    /// number of workers spawned on the tokio runtime so far, numbering the
+    /// names of their tasks
+    worker_index: Arc<AtomicUsize>,
+    /// tells final responses from progress updates, if workers send
+    /// progress updates
+    is_final: Option<IsFinal<Response>>,
+    /// callbacks invoked at every step of a request's lifecycle
+    hooks: RouterHooks<Request, Response>,
+    /// time from registration to response after which requests are reported
+    /// as slow
+    slow_request_threshold: Option<Duration>,
+    /// optional audit log of the requests handled by the router's endpoints
+    recorder: Option<Arc<Recorder<Request, Response>>>,
+    /// when the router loops were first started
+    started_at: Arc<OnceLock<Instant>>,
+    /// reports the failures of the router loops, see [Router::errors]
+    errors: ErrorReporter,
+    /// used by the registration loop to send requests scheduled for later to
+    /// the delay loop
+    delay_sender: Sender<Delayed<Request>>,
+    /// used by the delay loop to receive requests scheduled for later
+    delay_receiver: Receiver<Delayed<Request>>,
+}
+
+/// A failure of the router loops, observed through [Router::errors].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum RouterError {
+    /// a request was dropped as its UUID is already used by an in-flight
+    /// request
+    #[error("request {0} dropped, its uuid is already in use")]
+    DuplicateId(Uuid),
+    /// a request could not be dispatched as the workers' request channel is
+    /// closed
+    #[error("dispatching request {0} failed, the request channel is closed")]
+    DispatchFailed(Uuid),
+    /// a response was dropped as its endpoint is no longer waiting for it
+    #[error("response to request {0} dropped, its endpoint is gone")]
+    ResponseDropped(Uuid),
+    /// a response was dropped as no request with its UUID is in flight
+    #[error("response to unknown request {0} dropped")]
+    UnknownResponse(Uuid),
+    /// the worker handling a request panicked
+    #[error("the worker handling request {0} panicked")]
+    WorkerPanicked(Uuid),
+    /// the remote responder of a request failed, or its reply could not be
+    /// decoded
+    #[error("the remote responder of request {0} failed")]
+    RemoteFailed(Uuid),
+}
+
+/// Capacity of the channel the receivers returned by [Router::errors]
+/// receive from.
+const ERROR_CHANNEL_CAPACITY: usize = 100;
+
+/// Logs the failures of the router loops with the `tracing` feature and sends
+/// them to the receivers returned by [Router::errors], nothing is written to
+/// stdout.
+#[derive(Debug, Clone)]
+struct ErrorReporter {
+    /// sends the reported failures
+    sender: Sender<RouterError>,
+    /// cloned for every call to [Router::errors]
+    receiver: Receiver<RouterError>,
+    /// whether [Router::errors] was called, failures are only sent once it
+    /// was, so none pile up in the channel while nobody observes them
+    observed: Arc<AtomicBool>,
+}
+
+impl ErrorReporter {
+    fn new() -> Self {
+        let (sender, receiver) = bounded(ERROR_CHANNEL_CAPACITY);
+        Self {
+            sender,
+            receiver,
+            observed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+    /// Reports `err`, raised by the router loop `source`, as a `tracing`
+    /// warning with the `tracing` feature, and to the receivers returned by
+    /// [Router::errors]. The failure is not sent if their channel is full.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn report(&self, source: &str, err: RouterError) {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(source, error = %err, "router error");
+        if self.observed.load(Ordering::SeqCst) {
+            let _ = self.sender.try_send(err);
+        }
+    }
+    /// Returns a receiver of the failures reported from now on.
+    fn subscribe(&self) -> Receiver<RouterError> {
+        self.observed.store(true, Ordering::SeqCst);
+        self.receiver.clone()
+    }
+}
+
+/// Tells whether a response is the final response of its request.
+struct IsFinal<Response>(Arc<dyn Fn(&Response) -> bool + Send + Sync>);
+
+// implemented by hand, deriving would require `Response` to implement the
+// traits as well.
+impl<Response> Clone for IsFinal<Response> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<Response> fmt::Debug for IsFinal<Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IsFinal").finish_non_exhaustive()
+    }
+}
+
+/// Interval at which [Router::drain] checks whether the router is empty.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A registered request waiting for its response.
+#[derive(Debug)]
+struct Pending<Response> {
+    /// delivers the response to the waiting [Endpoint]
+    sender: Sender<Delivery<Response>>,
+    /// context submitted with the request
+    context: TraceContext,
+    /// cancelled once nobody waits for the response anymore
+    cancellation: CancellationToken,
+    /// key the response is cached under, if the router has a cache
+    cache_key: Option<CacheKey>,
+    /// number of responses still expected, more than one for scattered
+    /// requests
+    remaining: AtomicUsize,
+    /// when the request was registered, if the router measures latencies
+    registered_at: Option<Instant>,
+    /// number of requests waiting for a worker when the request was
+    /// registered
+    queue_depth: usize,
+    /// timeout of the endpoint the request was submitted by
+    timeout: Option<Duration>,
+    /// counts the request towards the in-flight limits of the router and of
+    /// its tenant until it is removed from the response map
+    _slots: Vec<InFlightSlot>,
 }
 
 /// Asynchronous private function that continuously listens for incoming
@@ -58,44 +277,141 @@ pub struct Router<Request, Response> {
 /// # Arguments
 ///
 /// - `response_receiver`: A receiver channel that receives tuples of UUIDs and
-///     responses.
-/// - `response_map`: Router's `HashMap` that maps UUIDs to their corresponding
-///     response senders.
+///   responses.
+/// - `response_map`: Router's [ResponseMap] that maps UUIDs to their corresponding
+///   response senders.
+/// - `metrics`: Router's metrics counters.
+/// - `cache`: Router's optional response cache.
+/// - `health`: Router's health check probes.
+/// - `is_final`: Router's optional function telling final responses from
+///   progress updates.
+/// - `hooks`: Router's lifecycle callbacks.
+/// - `slow_request_threshold`: Router's optional slow request threshold.
+/// - `errors`: Router's [ErrorReporter].
 ///
 /// # Type Parameters
 ///
 /// - `Response`: The type of the response. It must implement [Send], [Clone],
-///     and `'static`,
+///   and `'static`,
 ///
 /// # Behavior
 ///
 /// The function runs in an infinite loop, awaiting responses from the
 /// `response_receiver`. When a response is received, it attempts to find the
 /// corresponding sender in the `response_map` using the UUID. If a sender is
-/// found, it sends the response to the sender. If sending the response fails,
-/// it logs the error.
-async fn response_loop<Response>(
+/// found, it sends the response to the sender. Once all expected responses
+/// were sent, it removes the sender from the `response_map` after the outcome
+/// is counted, caching the response if the router has a cache. If sending the
+/// response fails, or no sender is found, it reports the failure as a
+/// [RouterError].
+/// Answers to health check pings are delivered to their health check.
+/// Progress updates are delivered without counting towards the expected
+/// responses. Responses arriving after their endpoint gave up are counted as
+/// late. The `on_response`, `on_late_response` and `on_orphan_response` hooks
+/// are called accordingly, `on_timeout` is called by the endpoints once their
+/// deadline passed. Requests whose last response arrives later than
+/// the `slow_request_threshold` after their registration are reported, see
+/// [report_slow_request].
+#[allow(clippy::too_many_arguments)]
+async fn response_loop<Request, Response>(
     response_receiver: Receiver<(Uuid, Response)>,
-    response_map: Arc<HashMap<Uuid, Sender<Response>>>,
+    response_map: Arc<ResponseMap<Pending<Response>>>,
+    metrics: Arc<RouterMetrics>,
+    cache: Option<Arc<dyn Cache<Request, Response>>>,
+    health: HealthProbes,
+    is_final: Option<IsFinal<Response>>,
+    hooks: RouterHooks<Request, Response>,
+    slow_request_threshold: Option<Duration>,
+    errors: ErrorReporter,
 ) where
     Response: Send + 'static + Clone,
 {
-    while let Ok((uuid, response)) = response_receiver.recv().await {
-        match response_map.remove_async(&uuid).await {
-            Some((_, sender)) => match sender.send(response).await {
-                //TODO: Handle error via logging and tracing
-                Ok(_) => {
-                    println!("Success from resp loop")
+    let answers = health.answers();
+    loop {
+        let (uuid, response) = tokio::select! {
+            received = response_receiver.recv() => match received {
+                Ok(received) => received,
+                Err(_) => break,
+            },
+            Ok(uuid) = answers.recv() => {
+                health.deliver(uuid);
+                continue;
+            }
+        };
+        let is_final = is_final
+            .as_ref()
+            .is_none_or(|is_final| (is_final.0)(&response));
+        // the response channel of a pending request has room for all its
+        // responses, so sending can only fail if the endpoint is gone.
+        let delivery = response_map
+            .read_async(&uuid, |_, pending| {
+                let last = is_final && pending.remaining.fetch_sub(1, Ordering::SeqCst) == 1;
+                let cancelled = pending.cancellation.is_cancelled();
+                let cached = pending.cache_key.is_some().then(|| response.clone());
+                let elapsed = pending
+                    .registered_at
+                    .map(|registered_at| registered_at.elapsed());
+                match (elapsed, cancelled) {
+                    (Some(elapsed), false) => hooks.response(uuid, &response, elapsed),
+                    (elapsed, true) => {
+                        RouterMetrics::increment(&metrics.late);
+                        if let Some(elapsed) = elapsed {
+                            // time passed since the endpoint's deadline, or
+                            // since the registration without a deadline
+                            let lateness = pending
+                                .timeout
+                                .map_or(elapsed, |timeout| elapsed.saturating_sub(timeout));
+                            hooks.late_response(uuid, &response, lateness);
+                        }
+                    }
+                    (None, false) => {}
                 }
-                Err(err) => {
-                    println!("Error from resp loop : {:?}", err)
+                if let (true, false, Some(latencies), Some(elapsed)) =
+                    (last, cancelled, &metrics.latencies, elapsed)
+                {
+                    latencies.record(elapsed);
                 }
-            },
+                let slow = elapsed
+                    .filter(|elapsed| last && slow_request_threshold.is_some_and(|t| *elapsed > t))
+                    .map(|elapsed| SlowRequest {
+                        uuid,
+                        elapsed,
+                        queue_depth: pending.queue_depth,
+                    });
+                (last, cached, slow, pending.sender.try_send(Ok(response)))
+            })
+            .await;
+        match delivery {
+            Some((false, _, _, sent)) => {
+                if sent.is_err() {
+                    hooks.orphan_response(uuid);
+                    errors.report("resp loop", RouterError::ResponseDropped(uuid));
+                }
+            }
+            Some((true, cached, slow, sent)) => {
+                if let Some(slow) = slow {
+                    report_slow_request(&slow, &hooks);
+                }
+                match sent {
+                    Ok(_) => RouterMetrics::increment(&metrics.responded),
+                    Err(_) => {
+                        RouterMetrics::increment(&metrics.orphaned);
+                        hooks.orphan_response(uuid);
+                        errors.report("resp loop", RouterError::ResponseDropped(uuid));
+                    }
+                }
+                let removed = response_map.remove_async(&uuid).await;
+                if let (Some(cache), Some((_, pending)), Some(response)) = (&cache, removed, cached)
+                {
+                    if let Some(key) = pending.cache_key {
+                        cache.insert(key, response);
+                    }
+                }
+            }
             None => {
-                println!(
-                    "Error from resp loop : No sender found for uuid: {:?}",
-                    uuid
-                );
+                RouterMetrics::increment(&metrics.orphaned);
+                hooks.orphan_response(uuid);
+                errors.report("resp loop", RouterError::UnknownResponse(uuid));
             }
         }
     }
@@ -106,62 +422,394 @@ async fn response_loop<Response>(
 ///
 /// # Arguments
 ///
-/// - `registration_receiver`: A receiver channel that receives tuples of
-///     requests and their corresponding response senders.
-/// - `response_map`: router's `HashMap` that maps UUIDs to their corresponding
-///     response senders.
-/// - `request_sender`: A sender channel that sends tuples of UUIDs and
-///     requests.
+/// - `registration_receiver`: A receiver channel that receives registrations
+///   of requests along with their response senders and contexts.
+/// - `response_map`: router's [ResponseMap] that maps UUIDs to their corresponding
+///   pending requests.
+/// - `queues`: Router's request queues, holding the shared request channel
+///   and the optional per-worker request channels, deadline queue and tenant
+///   queues.
+/// - `metrics`: Router's metrics counters.
+/// - `cache`: Router's optional response cache.
+/// - `spawner`: Router's [Spawn] implementation, spawning the tasks sending
+///   requests to the workers.
+/// - `hooks`: Router's lifecycle callbacks.
+/// - `slow_request_threshold`: Router's optional slow request threshold.
+/// - `errors`: Router's [ErrorReporter].
+/// - `delay_sender`: Sends the requests scheduled for later to the
+///   [delay_loop].
+/// - `health`: Router's health check probes.
 ///
 /// # Type Parameters
 ///
 /// - `Request`: The type of the request. It must implement [Send], [Clone],
-///     and `'static`,
+///   and `'static`,
 /// - `Response`: The type of the response. It must implement [Send], [Clone],
-///     and `'static`,
+///   and `'static`,
 ///
 /// # Behavior
 ///
 /// The function runs in an infinite loop, awaiting registration requests from
-/// the `registration_receiver`. When a request is received, it generates a new
-/// UUID, maps the UUID to the response sender in the `response_map`, and sends
-/// the UUID and request to the `request_sender`. If inserting into the
-/// `response_map` fails (e.g., if the key already exists), it handles the error
-/// appropriately.
+/// the `registration_receiver`. When a request is received, it maps the UUID
+/// assigned by its endpoint to the response sender and context in the
+/// `response_map`, and sends the UUID and request to the shared request
+/// channel. If
+/// inserting into the `response_map` fails because the UUID is already in
+/// use, the request is dropped, reported as [RouterError::DuplicateId], and
+/// its endpoint fails with
+/// [EndpointError::ResponseReceive](crate::endpoint::EndpointError::ResponseReceive).
+/// Requests that cannot be sent to the workers are reported as
+/// [RouterError::DispatchFailed]. Requests with a
+/// fresh response in the `cache` are answered immediately instead. With
+/// per-worker channels, the request is sent to the channel of the worker
+/// picked by the router's dispatch strategy. The `on_registered` and
+/// `on_dispatched` hooks are called once the request was registered, and once
+/// every copy was sent to the workers. With a deadline queue, the request is
+/// pushed into the queue along with its deadline. With tenant queues, the
+/// request is pushed into the queue of its tenant, requests submitted without
+/// a tenant into the queue of the default tenant `""`. Requests scheduled for
+/// later are registered right away, but sent to the [delay_loop] dispatching
+/// them at their scheduled time. The queues and the `delay_sender` are closed
+/// once the loop ends. The registration time and queue depth of requests are
+/// recorded if the `on_response` or `on_late_response` hook, or the
+/// `slow_request_threshold` is set. No request is received while the
+/// `intake` is paused. Health check pings are dispatched to the workers in the
+/// order they were received in, see [HealthProbes::dispatch].
+#[allow(clippy::too_many_arguments)]
 async fn registration_loop<Request, Response>(
-    registration_receiver: Receiver<(Request, Sender<Response>)>,
-    response_map: Arc<HashMap<Uuid, Sender<Response>>>,
-    request_sender: Sender<(Uuid, Request)>,
+    registration_receiver: Receiver<Registration<Request, Response>>,
+    intake: Arc<Intake>,
+    response_map: Arc<ResponseMap<Pending<Response>>>,
+    queues: RequestQueues<Request>,
+    metrics: Arc<RouterMetrics>,
+    cache: Option<Arc<dyn Cache<Request, Response>>>,
+    spawner: Arc<dyn Spawn>,
+    hooks: RouterHooks<Request, Response>,
+    slow_request_threshold: Option<Duration>,
+    errors: ErrorReporter,
+    delay_sender: Sender<Delayed<Request>>,
+    health: HealthProbes,
 ) where
     Request: Send + 'static + Clone,
     Response: Send + 'static + Clone,
 {
-    while let Ok((request, response_sink)) = registration_receiver.recv().await {
+    let measures_latency =
+        hooks.measures_latency() || slow_request_threshold.is_some() || metrics.latencies.is_some();
+    while let Ok(Registration(kind)) = intake.recv(&registration_receiver).await {
+        let registration = match kind {
+            RegistrationKind::Request(registration) => registration,
+            RegistrationKind::Ping(uuid) => {
+                health.dispatch(uuid);
+                continue;
+            }
+        };
+        let request = registration.request;
+        let scheduled = registration
+            .not_before
+            .filter(|not_before| *not_before > Instant::now());
+        // scattered requests gather several responses, they bypass the cache
+        let cache_key = match cache.as_ref().filter(|_| registration.copies == 1) {
+            Some(cache) => {
+                let key = cache.key(&request);
+                if let Some(response) = cache.get(&key) {
+                    RouterMetrics::increment(&metrics.cache_hits);
+                    let _ = registration.response_sender.try_send(Ok(response));
+                    continue;
+                }
+                Some(key)
+            }
+            None => None,
+        };
+        let pending = Pending {
+            sender: registration.response_sender,
+            context: registration.context,
+            cancellation: registration.cancellation.clone(),
+            cache_key,
+            remaining: AtomicUsize::new(registration.copies),
+            // latencies of scheduled requests are measured from dispatch
+            registered_at: measures_latency.then(|| scheduled.unwrap_or_else(Instant::now)),
+            queue_depth: if measures_latency { queues.len() } else { 0 },
+            timeout: registration.timeout,
+            _slots: registration.slots,
+        };
         // insert can fail if key already exists, unlikly but handled.
-        let mut uuid = Uuid::new_v4();
-        while response_map
-            .insert_async(uuid, response_sink.clone())
-            .await
-            //.await
-            .is_err()
-        {
-            uuid = Uuid::new_v4();
+        let uuid = registration.id;
+        if response_map.insert_async(uuid, pending).await.is_err() {
+            errors.report("reg loop", RouterError::DuplicateId(uuid));
+            continue;
+        }
+        // the endpoint may have been dropped, and failed to deregister the
+        // request, before it was inserted
+        if registration.cancellation.is_cancelled() {
+            response_map.remove_async(&uuid).await;
+            continue;
+        }
+        RouterMetrics::increment(&metrics.registered);
+        hooks.registered(uuid, &request);
+        let dispatch = Dispatch {
+            uuid,
+            request,
+            copies: registration.copies,
+            cancellation: registration.cancellation,
+            tenant: registration.tenant,
+            timeout: registration.timeout,
+            dispatched_at: registration.dispatched_at,
+        };
+        match scheduled {
+            // the channel is unbounded and only closed once the loop ends
+            Some(scheduled) => {
+                let _ = delay_sender.try_send((scheduled, dispatch));
+            }
+            None => dispatch.dispatch(&queues, &spawner, &hooks, &errors),
+        }
+    }
+    delay_sender.close();
+    if let Some(queue) = &queues.deadline_queue {
+        queue.close();
+    }
+    if let Some(tenant_queues) = &queues.tenant_queues {
+        tenant_queues.close();
+    }
+}
+
+/// A registered request ready to be dispatched to the workers.
+struct Dispatch<Request> {
+    /// identifier of the request
+    uuid: Uuid,
+    /// the request itself
+    request: Request,
+    /// number of copies dispatched, more than one for scattered requests
+    copies: usize,
+    /// cancelled once nobody waits for the response anymore
+    cancellation: CancellationToken,
+    /// tenant the request was submitted for, if any
+    tenant: Option<Arc<str>>,
+    /// timeout of the endpoint the request was submitted by
+    timeout: Option<Duration>,
+    /// stamped once the request is dispatched, if its endpoint times it
+    dispatched_at: Option<DispatchedAt>,
+}
+
+/// A request scheduled for later, along with the time it is dispatched at.
+type Delayed<Request> = (Instant, Dispatch<Request>);
+
+impl<Request> Dispatch<Request>
+where
+    Request: Send + 'static + Clone,
+{
+    /// Dispatches the request to the workers, see [registration_loop]: pushes
+    /// its copies into the deadline queue or the queue of its tenant if the
+    /// router has one, or sends them to the request channel picked by the
+    /// `queues` in a task spawned with `spawner` otherwise.
+    fn dispatch<Response>(
+        self,
+        queues: &RequestQueues<Request>,
+        spawner: &Arc<dyn Spawn>,
+        hooks: &RouterHooks<Request, Response>,
+        errors: &ErrorReporter,
+    ) where
+        Response: Send + 'static,
+    {
+        let Dispatch {
+            uuid,
+            request,
+            copies,
+            cancellation,
+            tenant,
+            timeout,
+            dispatched_at,
+        } = self;
+        if let Some(queue) = &queues.deadline_queue {
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
+            for request in std::iter::repeat_n(request, copies) {
+                let cancellation = cancellation.clone();
+                let dispatched_at = dispatched_at.clone();
+                queue.push(
+                    deadline,
+                    Queued {
+                        uuid,
+                        request,
+                        cancellation,
+                        dispatched_at,
+                    },
+                );
+            }
+            return;
+        }
+        if let Some(tenant_queues) = &queues.tenant_queues {
+            let tenant = tenant.unwrap_or_else(|| Arc::from(DEFAULT_TENANT));
+            for request in std::iter::repeat_n(request, copies) {
+                let cancellation = cancellation.clone();
+                let dispatched_at = dispatched_at.clone();
+                tenant_queues.push(
+                    tenant.clone(),
+                    Queued {
+                        uuid,
+                        request,
+                        cancellation,
+                        dispatched_at,
+                    },
+                );
+            }
+            return;
         }
-        let request_sender = request_sender.clone();
-        tokio::spawn(async move {
-            //TODO: Handle error via logging and tracing
-            match request_sender.send((uuid, request)).await {
-                Ok(_) => {
-                    println!("Success from reg loop")
+        let request_sender = queues.sender(&request).clone();
+        let worker_channels = queues.worker_channels.clone();
+        let hooks = hooks.clone();
+        let errors = errors.clone();
+        spawner.spawn(Box::pin(async move {
+            // clones the request for all but the last copy
+            for request in std::iter::repeat_n(request, copies) {
+                match request_sender.send((uuid, request)).await {
+                    Ok(_) => {
+                        if let Some(channels) = &worker_channels {
+                            channels.dispatched();
+                        }
+                        if let Some(dispatched_at) = &dispatched_at {
+                            dispatched_at.stamp();
+                        }
+                        hooks.dispatched(uuid);
+                    }
+                    Err(_) => {
+                        errors.report("reg loop", RouterError::DispatchFailed(uuid));
+                        break;
+                    }
+                };
+            }
+        }));
+    }
+}
+
+/// Asynchronous private function holding the requests scheduled for later in
+/// a timer wheel and dispatching them at their scheduled time.
+///
+/// # Arguments
+///
+/// - `delay_receiver`: Receives the requests scheduled for later from the
+///   registration loop.
+/// - `queues`: Router's request queues the requests are dispatched to.
+/// - `response_map`: Router's [ResponseMap] that maps UUIDs to their corresponding
+///   pending requests.
+/// - `spawner`: Router's [Spawn] implementation, spawning the tasks sending
+///   requests to the workers.
+/// - `hooks`: Router's lifecycle callbacks.
+/// - `errors`: Router's [ErrorReporter].
+///
+/// # Behavior
+///
+/// Requests are held in a [DelayQueue] and dispatched like the
+/// registration loop dispatches requests once their time came. Requests
+/// cancelled while held are not dispatched, they are removed from the
+/// `response_map`, their timeout was counted by their endpoint already. The
+/// function returns once the
+/// `delay_receiver` is closed and all held requests were dispatched.
+async fn delay_loop<Request, Response>(
+    delay_receiver: Receiver<Delayed<Request>>,
+    queues: RequestQueues<Request>,
+    response_map: Arc<ResponseMap<Pending<Response>>>,
+    spawner: Arc<dyn Spawn>,
+    hooks: RouterHooks<Request, Response>,
+    errors: ErrorReporter,
+) where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    let mut wheel = DelayQueue::new();
+    let mut open = true;
+    while open || !wheel.is_empty() {
+        tokio::select! {
+            received = delay_receiver.recv(), if open => match received {
+                Ok((scheduled, dispatch)) => {
+                    wheel.insert_at(dispatch, scheduled.into());
                 }
-                Err(err) => {
-                    println!("Error from reg loop : {:?}", err)
+                Err(_) => open = false,
+            },
+            Some(expired) = wheel.next() => {
+                let dispatch = expired.into_inner();
+                if dispatch.cancellation.is_cancelled() {
+                    response_map.remove_async(&dispatch.uuid).await;
+                    continue;
                 }
-            };
-        });
+                dispatch.dispatch(&queues, &spawner, &hooks, &errors);
+            }
+        }
     }
 }
 
+/// Asynchronous private function that continuously dispatches the requests
+/// of one of the router's scheduling queues to the workers, in the order of
+/// the queue: soonest deadline first for the deadline queue, one request of
+/// each tenant in turn for the tenant queues.
+///
+/// # Arguments
+///
+/// - `queue`: Router's deadline queue or tenant queues.
+/// - `queues`: Router's request queues, requests are sent to their shared
+///   request channel, or to their per-worker request channels if present.
+/// - `response_map`: Router's [ResponseMap] that maps UUIDs to their corresponding
+///   pending requests.
+/// - `hooks`: Router's lifecycle callbacks.
+/// - `errors`: Router's [ErrorReporter].
+///
+/// # Behavior
+///
+/// The function pops the next request of the queue and waits until the
+/// request channel has room for it, so requests registered meanwhile are
+/// still scheduled along with the queued ones. Requests cancelled while
+/// queued are not dispatched, they are removed from the `response_map`, their
+/// timeout was counted by their endpoint already. The function returns once the queue is closed and
+/// empty, or the request channel is closed, reporting the request it failed
+/// to send as [RouterError::DispatchFailed].
+async fn queue_loop<Request, Response>(
+    queue: Arc<impl SchedulingQueue<Request>>,
+    queues: RequestQueues<Request>,
+    response_map: Arc<ResponseMap<Pending<Response>>>,
+    hooks: RouterHooks<Request, Response>,
+    errors: ErrorReporter,
+) where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    while let Some(queued) = queue.pop().await {
+        let uuid = queued.uuid;
+        if queued.cancellation.is_cancelled() {
+            response_map.remove_async(&uuid).await;
+            continue;
+        }
+        let request_sender = queues.sender(&queued.request);
+        match request_sender.send((uuid, queued.request)).await {
+            Ok(_) => {
+                queues.dispatched();
+                if let Some(dispatched_at) = &queued.dispatched_at {
+                    dispatched_at.stamp();
+                }
+                hooks.dispatched(uuid);
+            }
+            Err(_) => {
+                errors.report("queue loop", RouterError::DispatchFailed(uuid));
+                break;
+            }
+        }
+    }
+}
+
+/// Private function reporting a request whose response took longer than the
+/// router's slow request threshold, as a `tracing` warning with the `tracing`
+/// feature, and to the `on_slow_request` hook.
+fn report_slow_request<Request, Response>(
+    slow: &SlowRequest,
+    hooks: &RouterHooks<Request, Response>,
+) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        uuid = %slow.uuid,
+        elapsed = ?slow.elapsed,
+        queue_depth = slow.queue_depth,
+        "slow request"
+    );
+    hooks.slow_request(slow);
+}
+
 impl<Request, Response> Default for Router<Request, Response>
 where
     Request: Send + 'static + Clone,
@@ -183,11 +831,11 @@ where
     /// # Arguments
     ///
     /// - `registration_channel_size`: An optional size for the registration
-    ///     channel. If `None`, an unbounded channel is created.
+    ///   channel. If `None`, an unbounded channel is created.
     /// - `request_channel_size`: An optional size for the request channel. If
-    ///     `None`, an unbounded channel is created.
+    ///   `None`, an unbounded channel is created.
     /// - `response_channel_size`: An optional size for the response channel. If
-    ///     `None`, an unbounded channel is created.
+    ///   `None`, an unbounded channel is created.
     ///
     /// # Returns
     ///
@@ -198,49 +846,764 @@ where
         request_channel_size: Option<usize>,
         response_channel_size: Option<usize>,
     ) -> Self {
-        let (registration_sender, registration_receiver) = match registration_channel_size {
+        RouterBuilder::new()
+            .registration_channel_size(registration_channel_size)
+            .request_channel_size(request_channel_size)
+            .response_channel_size(response_channel_size)
+            .build()
+    }
+    /// Places a [ResponseCache] in front of the worker dispatch of the router.
+    ///
+    /// Requests with a fresh cached response are answered without being
+    /// dispatched to the workers, the responses of all other requests are
+    /// cached once delivered.
+    pub fn with_response_cache<Key>(mut self, cache: ResponseCache<Key, Request, Response>) -> Self
+    where
+        Key: Hash + Eq + Send + Sync + 'static,
+    {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+    /// Dispatches requests to the workers with `strategy` instead of letting
+    /// all workers share a single request channel.
+    ///
+    /// # Arguments
+    ///
+    /// - `workers`: The number of workers requests are dispatched to.
+    /// - `strategy`: The [DispatchStrategy] picking the worker of every
+    ///   request.
+    ///
+    /// # Behavior
+    ///
+    /// Every worker gets its own request channel, with the capacity of the
+    /// router's request channel. Spawned workers are attached to the channels
+    /// in turn, so exactly `workers` workers should be spawned, the workers
+    /// spawned beyond that share the channel of an earlier worker.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `workers` is zero.
+    pub fn with_dispatch_strategy(
+        mut self,
+        workers: usize,
+        strategy: impl DispatchStrategy<Request> + 'static,
+    ) -> Self {
+        self.queues.worker_channels = Some(Arc::new(WorkerChannels::new(
+            workers,
+            self.queues.request_sender.capacity(),
+            strategy,
+        )));
+        self
+    }
+    /// Enables sticky routing: every request is dispatched to the worker its
+    /// key hashes to, so all requests with the same key hit the same worker.
+    ///
+    /// Shorthand for [Router::with_dispatch_strategy] with the [Sticky]
+    /// strategy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `workers` is zero.
+    pub fn with_sticky_routing<Key>(
+        self,
+        workers: usize,
+        key_fn: impl Fn(&Request) -> Key + Send + Sync + 'static,
+    ) -> Self
+    where
+        Key: Hash,
+    {
+        self.with_dispatch_strategy(workers, Sticky::new(key_fn))
+    }
+    /// Gives every worker its own request channel like
+    /// [Router::with_dispatch_strategy], sending the requests to the channels
+    /// in turn, and lets idle workers steal the requests queued for busy
+    /// ones.
+    ///
+    /// # Behavior
+    ///
+    /// Every attached worker receives its requests through an inbox holding a
+    /// single request, filled by a feeder task spawned with the router's
+    /// [Spawn] implementation. The feeder takes the requests from the
+    /// worker's own channel, or once its own is empty, from the channel with
+    /// the most queued requests or the inbox of a busy worker. A busy worker
+    /// thereby holds at most one request which can not be stolen. Unlike
+    /// with the shared request channel, a dispatched request only wakes a
+    /// single idle feeder.
+    /// Exactly `workers` workers should be spawned, see
+    /// [Router::with_dispatch_strategy].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `workers` is zero.
+    pub fn with_work_stealing(mut self, workers: usize) -> Self {
+        self.queues.worker_channels = Some(Arc::new(WorkerChannels::work_stealing(
+            workers,
+            self.queues.request_sender.capacity(),
+        )));
+        self
+    }
+    /// Sets the timeout applied by endpoints created with [Timeout::Default],
+    /// see [Router::endpoint].
+    pub fn with_default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+        self
+    }
+    /// Keeps requests pending until `is_final` returns `true` for one of
+    /// their responses, delivering the preceding responses as progress
+    /// updates.
+    pub(crate) fn with_final_responses(
+        mut self,
+        is_final: impl Fn(&Response) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.is_final = Some(IsFinal(Arc::new(is_final)));
+        self
+    }
+    /// Sets the [RouterHooks] called at every step of a request's lifecycle,
+    /// replacing any hooks set before.
+    pub fn with_hooks(mut self, hooks: RouterHooks<Request, Response>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+    /// Records every request handled by the router's endpoints with
+    /// `recorder`, see [Router::replay].
+    ///
+    /// Only endpoints created after setting the recorder record requests.
+    pub fn with_recorder(mut self, recorder: Recorder<Request, Response>) -> Self {
+        self.recorder = Some(Arc::new(recorder));
+        self
+    }
+    /// Returns the [Recorder] set with [Router::with_recorder], if any.
+    pub fn recorder(&self) -> Option<&Recorder<Request, Response>> {
+        self.recorder.as_deref()
+    }
+    /// Enables earliest-deadline-first scheduling: requests are dispatched
+    /// to the workers in the order of their deadlines instead of in the
+    /// order they were registered in.
+    ///
+    /// # Behavior
+    ///
+    /// The deadline of a request is the time it was registered at plus the
+    /// timeout of its [Endpoint], requests without a timeout are dispatched
+    /// after all requests with one. Registered requests wait in a deadline
+    /// ordered queue, and the router's request channel is replaced with a
+    /// channel holding a single request, so a request is only handed over
+    /// once a worker is about to take it. Requests cancelled while queued are
+    /// never dispatched.
+    ///
+    /// Workers must be spawned after enabling the scheduling, as they receive
+    /// requests from the replaced channel. Replaces the fair scheduling
+    /// across tenants enabled with [Router::with_tenancy].
+    pub fn with_edf_scheduling(mut self) -> Self {
+        let (request_sender, request_receiver) = bounded(1);
+        self.queues.request_sender = request_sender;
+        self.request_receiver = request_receiver;
+        self.queues.deadline_queue = Some(Arc::new(DeadlineQueue::new()));
+        self.queues.tenant_queues = None;
+        self
+    }
+    /// Enables multi-tenancy: requests are queued per tenant and dispatched
+    /// to the workers taking one request of each tenant in turn, and every
+    /// tenant is bound by `limits`.
+    ///
+    /// # Behavior
+    ///
+    /// Endpoints created with [Router::tenant_endpoint] submit requests for
+    /// their tenant, endpoints created otherwise for the default tenant `""`,
+    /// which is not bound by `limits`. Registered requests wait in the queue
+    /// of their tenant, and the router's request channel is replaced with a
+    /// channel holding a single request, so a tenant flooding the router
+    /// only delays the other tenants' requests by the requests already handed
+    /// over. Requests cancelled while queued are never dispatched.
+    ///
+    /// Workers must be spawned after enabling tenancy, as they receive
+    /// requests from the replaced channel. Replaces the earliest-deadline-first
+    /// scheduling enabled with [Router::with_edf_scheduling].
+    pub fn with_tenancy(mut self, limits: TenantLimits) -> Self {
+        let (request_sender, request_receiver) = bounded(1);
+        self.queues.request_sender = request_sender;
+        self.request_receiver = request_receiver;
+        self.queues.deadline_queue = None;
+        self.queues.tenant_queues = Some(Arc::new(TenantQueues::new(limits)));
+        self
+    }
+    /// Throttles the requests of the router by key, e.g. to protect a
+    /// downstream service from a single customer: at most the limits of
+    /// `throttle` are dispatched concurrently and per second for the same
+    /// key.
+    ///
+    /// # Behavior
+    ///
+    /// The endpoints apply the limits before registering a request, and
+    /// requests exceeding them are rejected with
+    /// [EndpointError::Throttled](crate::endpoint::EndpointError::Throttled)
+    /// or buffered per key, see [KeyThrottle::on_excess]. All endpoints share
+    /// the limits, but only endpoints created after enabling throttling apply
+    /// them.
+    pub fn with_key_throttling(mut self, throttle: KeyThrottle<Request>) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+    /// Sets what happens to the requests submitted while the router is
+    /// paused, see [Router::pause]. Requests wait for the router to be
+    /// resumed by default.
+    ///
+    /// Only endpoints created after setting the mode apply it.
+    pub fn with_pause_mode(mut self, mode: PauseMode) -> Self {
+        self.pause_mode = mode;
+        self
+    }
+    /// Responds with the response of `fallback` to requests that timed out,
+    /// instead of failing with
+    /// [EndpointError::Timeout](crate::endpoint::EndpointError::Timeout),
+    /// e.g. to serve a default or stale response.
+    ///
+    /// Only endpoints created after setting the fallback fall back, see
+    /// [Endpoint::with_fallback] for the details.
+    pub fn with_fallback(
+        mut self,
+        fallback: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.fallback = Some(Fallback::new(fallback));
+        self
+    }
+    /// Responds with the response `fallback` resolves to to requests that
+    /// timed out, like [Router::with_fallback].
+    pub fn with_async_fallback<F>(
+        mut self,
+        fallback: impl Fn(Request) -> F + Send + Sync + 'static,
+    ) -> Self
+    where
+        F: Future<Output = Response> + Send + 'static,
+    {
+        self.fallback = Some(Fallback::new_async(fallback));
+        self
+    }
+    /// Validates every request with `validator` before it is registered.
+    ///
+    /// # Behavior
+    ///
+    /// The endpoints run the validator before applying any other limit, a
+    /// rejected request fails with
+    /// [EndpointError::Invalid](crate::endpoint::EndpointError::Invalid)
+    /// holding the [RejectReason] right away, without taking up queue
+    /// capacity, an in-flight slot or a worker. Only endpoints created after
+    /// setting the validator validate requests.
+    pub fn with_validator(
+        mut self,
+        validator: impl Fn(&Request) -> Result<(), RejectReason> + Send + Sync + 'static,
+    ) -> Self {
+        self.validator = Some(Validator::new(validator));
+        self
+    }
+    /// Validates every request with the future returned by `validator`,
+    /// like [Router::with_validator].
+    ///
+    /// The returned future may not borrow the request, `validator` copies
+    /// the parts of the request the future validates.
+    pub fn with_async_validator<F>(
+        mut self,
+        validator: impl Fn(&Request) -> F + Send + Sync + 'static,
+    ) -> Self
+    where
+        F: Future<Output = Result<(), RejectReason>> + Send + 'static,
+    {
+        self.validator = Some(Validator::new_async(validator));
+        self
+    }
+    /// Returns a new [RouterBuilder] for configuring a `Router`.
+    pub fn builder() -> RouterBuilder {
+        RouterBuilder::new()
+    }
+    /// Creates a new instance of the `Router` struct from the configuration of
+    /// a [RouterBuilder].
+    pub(crate) fn from_builder(builder: RouterBuilder) -> Self {
+        let (dispatch_engine, workers) = (builder.dispatch_engine, builder.workers);
+        let (registration_sender, registration_receiver) = match builder.registration_channel_size {
             Some(b) => bounded(b),
             None => unbounded(),
         };
-        let (request_sender, request_receiver) = match request_channel_size {
+        let (request_sender, request_receiver) = match builder.request_channel_size {
             Some(b) => bounded(b),
             None => unbounded(),
         };
-        let (response_sender, response_receiver) = match response_channel_size {
+        let (response_sender, response_receiver) = match builder.response_channel_size {
             Some(b) => bounded(b),
             None => unbounded(),
         };
-        let response_map = Arc::new(HashMap::new());
-        Self {
+        let response_map = Arc::new(ResponseMap::new(builder.response_map_shards));
+        let (delay_sender, delay_receiver) = unbounded();
+        let router = Self {
             registration_sender,
             registration_receiver,
-            request_sender,
+            queues: RequestQueues {
+                request_sender,
+                worker_channels: None,
+                deadline_queue: None,
+                tenant_queues: None,
+            },
             request_receiver,
             response_sender,
             response_receiver,
             response_map,
+            default_timeout: builder.default_timeout,
+            id_generator: builder.id_generator,
+            metrics: Arc::new(RouterMetrics::new(builder.metrics)),
+            metrics_enabled: builder.metrics,
+            shutdown_token: builder.shutdown_token,
+            workers: Arc::new(AtomicUsize::new(0)),
+            cache: None,
+            health: HealthProbes::new(),
+            high_watermark: builder.high_watermark,
+            in_flight_limit: builder.max_in_flight.map(InFlightLimit::new),
+            throttle: None,
+            fallback: None,
+            validator: None,
+            intake: Arc::new(Intake::new()),
+            pause_mode: PauseMode::default(),
+            spawner: builder.spawner,
+            task_name_prefix: builder.task_name_prefix,
+            worker_index: Arc::new(AtomicUsize::new(0)),
+            is_final: None,
+            hooks: RouterHooks::default(),
+            slow_request_threshold: builder.slow_request_threshold,
+            recorder: None,
+            started_at: Arc::new(OnceLock::new()),
+            errors: ErrorReporter::new(),
+            delay_sender,
+            delay_receiver,
+        };
+        match dispatch_engine {
+            DispatchEngine::Shared => router,
+            DispatchEngine::LeastLoaded => {
+                router.with_dispatch_strategy(workers, LeastLoaded::new())
+            }
+            DispatchEngine::WorkStealing => router.with_work_stealing(workers),
         }
     }
     /// Creates a new [Endpoint] instance using the router's registration sender
-    /// and an optional timeout.
+    /// and a [Timeout].
     ///
     /// # Arguments
     ///
-    /// - `timeout`: An optional [Duration] specifying the timeout for the
-    ///   [Endpoint]. If `None`, no timeout is applied.
+    /// - `timeout`: The [Timeout] of the [Endpoint], or anything converting
+    ///   into one. [Timeout::Default], or `None`, applies the router's default
+    ///   timeout, if one was configured, otherwise no timeout is applied.
+    ///   [Timeout::None] explicitly opts out of any timeout.
     ///
     /// # Returns
     ///
     /// Returns a new instance of the [Endpoint] struct configured with the
-    /// router's registration sender and the specified timeout.
-    pub fn endpoint(&self, timeout: Option<Duration>) -> Endpoint<Request, Response> {
-        Endpoint::new(self.registration_sender.clone(), timeout)
+    /// router's registration sender, the specified timeout and the router's
+    /// load shedding policy and in-flight limit. Its requests fail with
+    /// [EndpointError::NoWorkers](crate::endpoint::EndpointError::NoWorkers)
+    /// right away while no workers are attached to the router.
+    pub fn endpoint(&self, timeout: impl Into<Timeout>) -> Endpoint<Request, Response> {
+        let mut endpoint = Endpoint::new(
+            self.registration_sender.clone(),
+            timeout.into().resolve(self.default_timeout),
+        )
+        .with_id_generator(self.id_generator.clone())
+        .with_worker_count(self.workers.clone())
+        .with_intake(self.intake.clone(), self.pause_mode)
+        .with_deregistration({
+            let response_map = self.response_map.clone();
+            Arc::new(move |uuid| {
+                response_map.remove(uuid);
+            })
+        })
+        .with_timeout_reporting({
+            let metrics = self.metrics.clone();
+            let hooks = self.hooks.clone();
+            Arc::new(move |uuid| {
+                RouterMetrics::increment(&metrics.timed_out);
+                hooks.timeout(*uuid);
+            })
+        });
+        if let Some(in_flight_limit) = &self.in_flight_limit {
+            endpoint = endpoint.with_in_flight_limit(in_flight_limit.clone());
+        }
+        if let Some(throttle) = &self.throttle {
+            endpoint = endpoint.with_throttle(throttle.clone());
+        }
+        if let Some(fallback) = &self.fallback {
+            endpoint = endpoint.with_fallback_of(fallback.clone());
+        }
+        if let Some(validator) = &self.validator {
+            endpoint = endpoint.with_validator(validator.clone());
+        }
+        if let Some(recorder) = &self.recorder {
+            endpoint = endpoint.with_recording(recorder.clone());
+        }
+        match self.high_watermark {
+            Some(high_watermark) => endpoint.with_load_shedding(LoadShedding {
+                high_watermark,
+                queues: self.queues.clone(),
+            }),
+            None => endpoint,
+        }
+    }
+    /// Creates a new [Endpoint] like [Router::endpoint], submitting requests
+    /// for `tenant`, see [Router::with_tenancy].
+    ///
+    /// The endpoint rejects new requests with
+    /// [EndpointError::Overloaded](crate::endpoint::EndpointError::Overloaded)
+    /// once the tenant's queue is full, and with
+    /// [EndpointError::TooManyInFlight](crate::endpoint::EndpointError::TooManyInFlight)
+    /// once the tenant's in-flight limit, shared by all endpoints of the
+    /// tenant, was reached.
+    ///
+    /// # Panics
+    ///
+    /// Panics if tenancy was not enabled with [Router::with_tenancy].
+    pub fn tenant_endpoint(
+        &self,
+        tenant: impl Into<Arc<str>>,
+        timeout: impl Into<Timeout>,
+    ) -> Endpoint<Request, Response> {
+        let queues = self
+            .queues
+            .tenant_queues
+            .clone()
+            .expect("tenancy must be enabled with Router::with_tenancy");
+        let tenant = tenant.into();
+        let mut endpoint = self.endpoint(timeout);
+        if let Some(in_flight_limit) = queues.in_flight_limit(&tenant) {
+            endpoint = endpoint.with_in_flight_limit(in_flight_limit);
+        }
+        endpoint.with_tenancy(Tenancy { tenant, queues })
+    }
+    /// Creates a new [MappedEndpoint] like [Router::endpoint], exposing the
+    /// router to callers with their own request and response types.
+    ///
+    /// # Returns
+    ///
+    /// Returns a [MappedEndpoint] converting the requests of the callers
+    /// into `Request` and the responses of the workers into `NewResponse`.
+    pub fn adapt<NewRequest, NewResponse>(
+        &self,
+        timeout: impl Into<Timeout>,
+    ) -> MappedEndpoint<NewRequest, NewResponse>
+    where
+        NewRequest: Into<Request> + Send + 'static,
+        Response: Into<NewResponse>,
+        NewResponse: Send + 'static,
+    {
+        self.endpoint(timeout)
+            .map_request(Into::into)
+            .map_response(Into::into)
+    }
+    /// Creates a new [Endpoint] like [Router::endpoint], wrapped in an [Arc] so
+    /// it can be stored once (e.g. in actix `Data` or axum `State`) and
+    /// cheaply shared between handlers.
+    pub fn shared_endpoint(&self, timeout: impl Into<Timeout>) -> Arc<Endpoint<Request, Response>> {
+        Arc::new(self.endpoint(timeout))
     }
+    /// Chains the router with `next` into a [Pipeline], the responses of this
+    /// router become the requests of `next`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a [Pipeline] submitting requests to this router and responding
+    /// with the responses of `next`. Both routers handle a request under the
+    /// same UUID, and apply their default timeout, if any.
+    pub fn pipe_to<Next>(&self, next: &Router<Response, Next>) -> Pipeline<Request, Next>
+    where
+        Next: Send + 'static + Clone,
+    {
+        Pipeline::new(self).pipe_to(next)
+    }
+    /// Returns the generator of the router's request identifiers.
+    pub(crate) fn id_generator(&self) -> IdGenerator {
+        self.id_generator.clone()
+    }
+    /// Returns the current values of the router's metrics counters, or `None`
+    /// if metrics are disabled.
+    pub fn metrics(&self) -> Option<MetricsSnapshot> {
+        self.metrics_enabled.then(|| self.metrics.snapshot())
+    }
+    /// Returns a receiver of the failures of the router loops: dropped
+    /// requests and responses, and requests that could not be dispatched or
+    /// whose worker panicked.
+    ///
+    /// Failures are only sent once this method was called, and dropped while
+    /// the receivers fall behind by more than a hundred failures. All
+    /// returned receivers share a single channel, so every failure is
+    /// received by one of them. The channel is closed once the router is
+    /// shut down.
+    pub fn errors(&self) -> Receiver<RouterError> {
+        self.errors.subscribe()
+    }
+    /// Returns the current lengths of the router's channels, the number of
+    /// in-flight requests, the number of running workers and the uptime of
+    /// the router, along with its metrics counters and latency percentiles if
+    /// metrics are enabled.
+    pub fn stats(&self) -> RouterStats {
+        RouterStats {
+            registration_queue_len: self.registration_sender.len(),
+            request_queue_len: self.request_queue_len(),
+            response_queue_len: self.response_sender.len(),
+            in_flight: self.response_map.len(),
+            workers: self.workers.load(Ordering::SeqCst),
+            uptime: self.started_at.get().map(Instant::elapsed),
+            counts: self.metrics(),
+            latency: self
+                .metrics
+                .latencies
+                .as_ref()
+                .filter(|_| self.metrics_enabled)
+                .and_then(|latencies| latencies.percentiles()),
+        }
+    }
+    /// Sends a synthetic ping through the router and waits up to `timeout`
+    /// for a worker to answer it.
+    ///
+    /// The ping is submitted to the registration channel like a request and
+    /// dispatched to the workers by the registration loop, so a paused or
+    /// blocked registration loop, or workers not getting through their backlog
+    /// within `timeout`, report the router unhealthy, see the
+    /// [health](crate::health) module. Only workers managed by the router,
+    /// spawned with [Router::spawn_worker_instances], answer pings. Raw workers
+    /// spawned with [Router::tokio_spawn_workers] only contribute to the
+    /// worker count.
+    ///
+    /// # Returns
+    ///
+    /// Returns a [HealthReport] telling whether a worker answered, the round
+    /// trip latency of the ping and the number of running workers.
+    pub async fn health_check(&self, timeout: Duration) -> HealthReport {
+        let latency = self
+            .health
+            .ping(timeout, |uuid| async move {
+                let ping = Registration(RegistrationKind::Ping(uuid));
+                self.registration_sender.send(ping).await.is_ok()
+            })
+            .await;
+        HealthReport {
+            answered: latency.is_some(),
+            latency,
+            workers: self.workers.load(Ordering::SeqCst),
+        }
+    }
+    /// Returns the health check probes answered by managed workers.
+    pub(crate) fn health_probes(&self) -> &HealthProbes {
+        &self.health
+    }
+    /// Returns the [TraceContext] submitted with the in-flight request
+    /// identified by `uuid`, or `None` if no such request is pending.
+    pub fn trace_context(&self, uuid: &Uuid) -> Option<TraceContext> {
+        self.response_map
+            .read(uuid, |_, pending| pending.context.clone())
+    }
+    /// Returns the [CancellationToken] of the in-flight request identified by
+    /// `uuid`, or `None` if no such request is pending.
+    ///
+    /// The token is cancelled when the [Endpoint] waiting for the response
+    /// times out, workers can check or select on it to abandon requests
+    /// nobody is waiting on anymore.
+    pub fn cancellation_token(&self, uuid: &Uuid) -> Option<CancellationToken> {
+        self.response_map
+            .read(uuid, |_, pending| pending.cancellation.clone())
+    }
+    /// Removes the in-flight request identified by `uuid` without delivering
+    /// a response, used by workers abandoning a cancelled request.
+    pub(crate) fn deregister(&self, uuid: &Uuid) {
+        self.response_map.remove(uuid);
+    }
+    /// Removes the in-flight request identified by `uuid`, failing it with
+    /// [EndpointError::WorkerPanicked](crate::endpoint::EndpointError::WorkerPanicked),
+    /// used by managed workers whose handler panicked.
+    pub(crate) fn fail_panicked(&self, uuid: &Uuid) {
+        if let Some((_, pending)) = self.response_map.remove(uuid) {
+            // the response channel has room for all the missing responses
+            let _ = pending.sender.try_send(Err(WorkerPanicked));
+            self.errors
+                .report("worker", RouterError::WorkerPanicked(*uuid));
+        }
+    }
+    /// Removes the in-flight request identified by `uuid` without delivering
+    /// a response, failing it with
+    /// [EndpointError::ResponseReceive](crate::endpoint::EndpointError::ResponseReceive),
+    /// used by adapters whose remote responder failed.
+    #[cfg(any(feature = "nats", feature = "kafka"))]
+    pub(crate) fn fail_remote(&self, uuid: &Uuid) {
+        // dropping the response sender closes the channel of the endpoint
+        if self.response_map.remove(uuid).is_some() {
+            self.errors
+                .report("remote", RouterError::RemoteFailed(*uuid));
+        }
+    }
+    /// Stops accepting new requests and waits for the in-flight requests to
+    /// complete, or for the `deadline` to pass.
+    ///
+    /// # Arguments
+    ///
+    /// - `deadline`: The maximum [Duration] to wait for in-flight requests.
+    ///
+    /// # Returns
+    ///
+    /// Returns a [DrainReport] counting the requests completed, timed out and
+    /// abandoned while draining.
+    ///
+    /// # Behavior
+    ///
+    /// The registration channel is closed, so new requests fail with
+    /// [EndpointError::RequestSend](crate::endpoint::EndpointError::RequestSend),
+    /// while the router loops keep processing the already submitted requests.
+    /// Once the registration and request channels and the response map are
+    /// empty, or the deadline passed, the remaining requests are abandoned:
+    /// they are removed from the router and their endpoints fail with
+    /// [EndpointError::ResponseReceive](crate::endpoint::EndpointError::ResponseReceive).
+    pub async fn drain(&self, deadline: Duration) -> DrainReport {
+        let deadline = tokio::time::Instant::now() + deadline;
+        let start = self.metrics.snapshot();
+        self.registration_sender.close();
+        // the requests held back while paused are processed as well
+        self.intake.resume();
+        while tokio::time::Instant::now() < deadline
+            && !(self.registration_sender.is_empty()
+                && self.request_queue_len() == 0
+                && self.response_map.is_empty())
+        {
+            tokio::time::sleep_until(
+                deadline.min(tokio::time::Instant::now() + DRAIN_POLL_INTERVAL),
+            )
+            .await;
+        }
+        let mut report = DrainReport::default();
+        while let Ok(Registration(kind)) = self.registration_receiver.try_recv() {
+            if let RegistrationKind::Request(_) = kind {
+                report.abandoned += 1;
+            }
+        }
+        // requests whose endpoint timed out were counted at their deadline
+        self.response_map.retain(|_, pending| {
+            if !pending.cancellation.is_cancelled() {
+                report.abandoned += 1;
+            }
+            false
+        });
+        let end = self.metrics.snapshot();
+        report.completed = (end.responded - start.responded) as usize;
+        report.timed_out += (end.timed_out - start.timed_out) as usize;
+        report
+    }
+    /// Returns the number of requests waiting for a worker.
+    fn request_queue_len(&self) -> usize {
+        self.queues.len()
+    }
+    /// Pauses the router, e.g. for a maintenance window or to hand its
+    /// traffic over to another router: the registration loop stops pulling
+    /// requests off the registration channel, while the requests registered
+    /// before are still dispatched and answered.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the router was running, `false` if it was already
+    /// paused.
+    ///
+    /// # Behavior
+    ///
+    /// Once paused, no further request is registered until
+    /// [Router::resume] is called, and the endpoints of the router wait for
+    /// it to be resumed within their timeout, or reject new requests with
+    /// [EndpointError::Paused](crate::endpoint::EndpointError::Paused), see
+    /// [Router::with_pause_mode]. Pausing is shared by all clones of the
+    /// router. Draining or shutting the router down resumes it.
+    pub fn pause(&self) -> bool {
+        self.intake.pause()
+    }
+    /// Resumes a router paused with [Router::pause], registering the requests
+    /// submitted meanwhile.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the router was paused, `false` if it was running.
+    pub fn resume(&self) -> bool {
+        self.intake.resume()
+    }
+    /// Returns whether the router is paused, see [Router::pause].
+    pub fn is_paused(&self) -> bool {
+        self.intake.is_paused()
+    }
+    /// Returns the token stopping the router loops once cancelled.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown_token.clone()
+    }
+    /// Stops the router loops and closes the registration channel, further
+    /// requests fail with
+    /// [EndpointError::RequestSend](crate::endpoint::EndpointError::RequestSend),
+    /// and the channel of the receivers returned by [Router::errors].
+    pub fn shutdown(&self) {
+        self.shutdown_token.cancel();
+    }
+    /// Spawns the router loops with the router's [Spawn] implementation, see
+    /// [RouterBuilder::spawner].
+    ///
+    /// The timers of the router, e.g. the timeouts of its endpoints and the
+    /// requests scheduled for later, still require a tokio runtime, see the
+    /// [spawn](crate::spawn) module.
+    pub fn spawn(&self) {
+        let temp = self.clone();
+        self.spawner
+            .spawn(Box::pin(async move { temp.run().await }));
+    }
+    /// Spawns `num_workers` workers running `worker_fn` with the router's
+    /// [Spawn] implementation, like [Router::tokio_spawn_workers].
+    pub fn spawn_workers<F>(
+        &self,
+        num_workers: usize,
+        worker_fn: impl Fn(Receiver<(Uuid, Request)>, Sender<(Uuid, Response)>) -> F,
+    ) where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        for worker in self.worker_futures(num_workers, worker_fn) {
+            self.spawner.spawn(Box::pin(worker));
+        }
+    }
+    /// Spawns the router loops on the tokio runtime, each loop in a task of
+    /// its own, named like `s2a4c-registration-loop`, see
+    /// [RouterBuilder::task_name_prefix].
+    ///
+    /// # Returns
+    ///
+    /// Returns the handle of the task supervising the loops, named like
+    /// `s2a4c-router`. Aborting it aborts the loops, and a panic of a loop is
+    /// propagated to it.
     pub fn tokio_spawn(&self) -> tokio::task::JoinHandle<()> {
+        self.tokio_spawn_until(self.shutdown_token.clone())
+    }
+    /// Spawns the router loops like [Router::tokio_spawn], stopping them and
+    /// closing the registration channel once `stop` is cancelled.
+    pub(crate) fn tokio_spawn_until(&self, stop: CancellationToken) -> tokio::task::JoinHandle<()> {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.started_at.get_or_init(Instant::now);
+        let loops = self.loops().map(|(name, router_loop)| {
+            AbortOnDropHandle::new(tokio_spawn_named(&self.task_name(name), router_loop))
+        });
         let temp = self.clone();
-        tokio::spawn(async move { temp.run().await })
+        tokio_spawn_named(&self.task_name("router"), async move {
+            // the loops are aborted once stopped, or the supervising task is
+            // aborted
+            tokio::select! {
+                _ = stop.cancelled() => temp.close(),
+                joined = futures::future::try_join_all(loops) => {
+                    if let Err(err) = joined {
+                        if err.is_panic() {
+                            std::panic::resume_unwind(err.into_panic());
+                        }
+                    }
+                }
+            }
+        })
     }
-
+    /// Spawns `num_workers` workers running `worker_fn` on the tokio runtime,
+    /// in tasks named like `s2a4c-worker-3`, numbered in the order the
+    /// router's workers were spawned in, see
+    /// [RouterBuilder::task_name_prefix].
+    ///
+    /// # Returns
+    ///
+    /// Returns the handles of the spawned worker tasks.
     pub fn tokio_spawn_workers<F>(
         &self,
         num_workers: usize,
@@ -249,25 +1612,370 @@ where
     where
         F: Future<Output = ()> + Send + 'static,
     {
-        let mut handles = Vec::new();
-        for _ in 0..num_workers {
-            handles.push(tokio::spawn(worker_fn(
-                self.request_receiver.clone(),
-                self.response_sender.clone(),
-            )));
-        }
-        handles
+        self.worker_futures(num_workers, worker_fn)
+            .into_iter()
+            .map(|worker| {
+                let index = self.worker_index.fetch_add(1, Ordering::SeqCst);
+                tokio_spawn_named(&self.task_name(&format!("worker-{index}")), worker)
+            })
+            .collect()
     }
-    pub async fn run(&self) {
-        let response_loop = tokio::spawn(response_loop(
+    /// Creates the futures of `num_workers` workers running `worker_fn`,
+    /// counted in the router's worker count until they complete or are
+    /// dropped.
+    pub(crate) fn worker_futures<F>(
+        &self,
+        num_workers: usize,
+        worker_fn: impl Fn(Receiver<(Uuid, Request)>, Sender<(Uuid, Response)>) -> F,
+    ) -> Vec<impl Future<Output = ()> + Send + 'static>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        (0..num_workers)
+            .map(|_| {
+                let attachment = self.attach_worker();
+                let (receiver, sender) = attachment.channels();
+                let worker = worker_fn(receiver, sender);
+                async move {
+                    let _attachment = attachment;
+                    worker.await
+                }
+            })
+            .collect()
+    }
+    /// Attaches a worker to the router, for running it on an executor other
+    /// than tokio, on a dedicated thread or in another crate.
+    ///
+    /// # Behavior
+    ///
+    /// The worker receives the requests from the router's request channel,
+    /// or from the next worker channel if the router has a
+    /// [DispatchStrategy], like a worker spawned with
+    /// [Router::tokio_spawn_workers]. With work stealing, its requests are
+    /// fed to it by a task spawned with the router's [Spawn] implementation,
+    /// see [Router::with_work_stealing]. It is counted in the router's worker
+    /// count until the returned [WorkerAttachment] is dropped, so endpoints
+    /// don't fail with
+    /// [EndpointError::NoWorkers](crate::endpoint::EndpointError::NoWorkers)
+    /// in the meantime.
+    ///
+    /// # Returns
+    ///
+    /// Returns the [WorkerAttachment] holding the channels of the worker.
+    pub fn attach_worker(&self) -> WorkerAttachment<Request, Response> {
+        let receiver = match &self.queues.worker_channels {
+            Some(channels) => {
+                let (receiver, feeder) = channels.attach();
+                if let Some(feeder) = feeder {
+                    self.spawner.spawn(feeder);
+                }
+                receiver
+            }
+            None => self.request_receiver.clone(),
+        };
+        WorkerAttachment::new(receiver, self.response_sender.clone(), self.workers.clone())
+    }
+
+    /// Spawns `num_workers` tokio tasks, each driving a [Worker] instance
+    /// created by `factory`.
+    ///
+    /// # Arguments
+    ///
+    /// - `num_workers`: The number of worker instances to spawn.
+    /// - `factory`: A function creating a new [Worker] instance, called once
+    ///   per spawned worker.
+    ///
+    /// # Returns
+    ///
+    /// Returns the handles of the spawned worker tasks.
+    pub fn spawn_worker_instances<W>(
+        &self,
+        num_workers: usize,
+        factory: impl Fn() -> W,
+    ) -> Vec<tokio::task::JoinHandle<()>>
+    where
+        W: Worker<Request, Response> + Send + Sync + 'static,
+    {
+        self.tokio_spawn_workers(num_workers, |receiver, sender| {
+            worker_loop(factory(), self.clone(), receiver, sender)
+        })
+    }
+    /// Spawns `num_workers` workers handling requests with the asynchronous
+    /// `handler`, one request at a time per worker.
+    ///
+    /// The router owns the receive/send loop and echoes every request's UUID
+    /// along with its response, like for [Router::spawn_worker_instances], so
+    /// a worker reduces to a closure:
+    ///
+    /// ```rust
+    /// # use s2a4c::router::Router;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let router: Router<u32, u32> = Router::default();
+    /// router.spawn_workers_fn(2, |request| async move { request * 2 });
+    /// # }
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// - `num_workers`: The number of workers to spawn.
+    /// - `handler`: A function mapping a request to a future resolving to its
+    ///   response.
+    ///
+    /// # Returns
+    ///
+    /// Returns the handles of the spawned worker tasks.
+    pub fn spawn_workers_fn<F, Fut>(
+        &self,
+        num_workers: usize,
+        handler: F,
+    ) -> Vec<tokio::task::JoinHandle<()>>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.spawn_worker_instances(num_workers, || FnWorker(handler.clone()))
+    }
+    /// Spawns `num_workers` workers running the synchronous, blocking
+    /// `handler` on tokio's blocking thread pool.
+    ///
+    /// Every worker hands one request at a time to
+    /// [tokio::task::spawn_blocking] and awaits it, so at most `num_workers`
+    /// requests are handled concurrently and blocking handlers (FFI, blocking
+    /// database drivers) never stall the async runtime.
+    ///
+    /// # Arguments
+    ///
+    /// - `num_workers`: The number of requests handled concurrently.
+    /// - `handler`: A function mapping a request to its response.
+    ///
+    /// # Returns
+    ///
+    /// Returns the handles of the spawned worker tasks.
+    pub fn spawn_blocking_workers<F>(
+        &self,
+        num_workers: usize,
+        handler: F,
+    ) -> Vec<tokio::task::JoinHandle<()>>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.spawn_worker_instances(num_workers, || BlockingWorker(handler.clone()))
+    }
+    /// Spawns one worker per thread of `pool`, running the synchronous,
+    /// CPU-bound `handler` on the rayon thread pool.
+    ///
+    /// Every worker hands one request at a time to the pool and awaits its
+    /// response, so the async runtime never runs the handler. At most one job
+    /// per thread of the pool is queued by the router, including the jobs of
+    /// requests abandoned while running, further requests wait in the
+    /// router's request channel.
+    ///
+    /// # Arguments
+    ///
+    /// - `pool`: The rayon thread pool the handler runs on, possibly shared
+    ///   with other work.
+    /// - `handler`: A function mapping a request to its response.
+    ///
+    /// # Returns
+    ///
+    /// Returns the handles of the spawned worker tasks.
+    #[cfg(feature = "rayon")]
+    pub fn spawn_rayon_workers<F>(
+        &self,
+        pool: Arc<rayon::ThreadPool>,
+        handler: F,
+    ) -> Vec<tokio::task::JoinHandle<()>>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        let num_workers = pool.current_num_threads();
+        let jobs = Arc::new(tokio::sync::Semaphore::new(num_workers));
+        let handler = Arc::new(handler);
+        self.spawn_worker_instances(num_workers, || RayonWorker {
+            pool: pool.clone(),
+            jobs: jobs.clone(),
+            handler: handler.clone(),
+        })
+    }
+    /// Spawns `num_workers` workers, each handling up to
+    /// `per_worker_concurrency` requests concurrently with the asynchronous
+    /// `handler`.
+    ///
+    /// A worker spawned with [Router::tokio_spawn_workers] awaiting I/O
+    /// handles the requests it receives one after another. These workers
+    /// instead hand every request to its own task, and only receive the next
+    /// request while fewer than `per_worker_concurrency` of their tasks are
+    /// running.
+    ///
+    /// # Arguments
+    ///
+    /// - `num_workers`: The number of workers to spawn.
+    /// - `per_worker_concurrency`: The maximum number of requests handled
+    ///   concurrently by every worker.
+    /// - `handler`: A function mapping a request to a future resolving to its
+    ///   response.
+    ///
+    /// # Returns
+    ///
+    /// Returns the handles of the spawned worker tasks, aborting a worker
+    /// aborts the requests it is handling.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `per_worker_concurrency` is zero.
+    pub fn spawn_workers_concurrent<F, Fut>(
+        &self,
+        num_workers: usize,
+        per_worker_concurrency: usize,
+        handler: F,
+    ) -> Vec<tokio::task::JoinHandle<()>>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        assert!(
+            per_worker_concurrency > 0,
+            "workers must handle at least one request at a time"
+        );
+        let handler = Arc::new(handler);
+        self.tokio_spawn_workers(num_workers, |receiver, sender| {
+            concurrent_worker_loop(
+                handler.clone(),
+                per_worker_concurrency,
+                self.clone(),
+                receiver,
+                sender,
+            )
+        })
+    }
+    /// Spawns `num_workers` workers handing every request to the
+    /// asynchronous `handler` along with the [ResponseHandle] of the request,
+    /// for requests answered later from outside the worker loop.
+    ///
+    /// The handler may respond right away, or park the handle, e.g. until an
+    /// external service called back through a webhook, and respond from any
+    /// other task. Workers receive the next request as soon as the handler
+    /// returned. Requests whose handles are all dropped without responding
+    /// are abandoned, see [ResponseHandle].
+    ///
+    /// # Arguments
+    ///
+    /// - `num_workers`: The number of workers to spawn.
+    /// - `handler`: A function taking a request along with its
+    ///   [ResponseHandle].
+    ///
+    /// # Returns
+    ///
+    /// Returns the handles of the spawned worker tasks.
+    pub fn spawn_deferred_workers<F, Fut>(
+        &self,
+        num_workers: usize,
+        handler: F,
+    ) -> Vec<tokio::task::JoinHandle<()>>
+    where
+        F: Fn(Request, ResponseHandle<Response>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.tokio_spawn_workers(num_workers, |receiver, sender| {
+            deferred_worker_loop(handler.clone(), self.clone(), receiver, sender)
+        })
+    }
+    /// Creates the router loops, along with the names of their tasks.
+    fn loops(&self) -> [(&'static str, BoxFuture<'static, ()>); 4] {
+        let response_loop = response_loop(
             self.response_receiver.clone(),
             self.response_map.clone(),
-        ));
-        let registration_loop = tokio::spawn(registration_loop(
+            self.metrics.clone(),
+            self.cache.clone(),
+            self.health.clone(),
+            self.is_final.clone(),
+            self.hooks.clone(),
+            self.slow_request_threshold,
+            self.errors.clone(),
+        );
+        let registration_loop = registration_loop(
             self.registration_receiver.clone(),
+            self.intake.clone(),
+            self.response_map.clone(),
+            self.queues.clone(),
+            self.metrics.clone(),
+            self.cache.clone(),
+            self.spawner.clone(),
+            self.hooks.clone(),
+            self.slow_request_threshold,
+            self.errors.clone(),
+            self.delay_sender.clone(),
+            self.health.clone(),
+        );
+        let delay_loop = delay_loop(
+            self.delay_receiver.clone(),
+            self.queues.clone(),
             self.response_map.clone(),
-            self.request_sender.clone(),
-        ));
-        let _ = tokio::join!(response_loop, registration_loop);
+            self.spawner.clone(),
+            self.hooks.clone(),
+            self.errors.clone(),
+        );
+        let router = self.clone();
+        let queue_loop = async move {
+            if let Some(queue) = &router.queues.deadline_queue {
+                queue_loop(
+                    queue.clone(),
+                    router.queues.clone(),
+                    router.response_map.clone(),
+                    router.hooks.clone(),
+                    router.errors.clone(),
+                )
+                .await
+            }
+            if let Some(tenant_queues) = &router.queues.tenant_queues {
+                queue_loop(
+                    tenant_queues.clone(),
+                    router.queues.clone(),
+                    router.response_map.clone(),
+                    router.hooks.clone(),
+                    router.errors.clone(),
+                )
+                .await
+            }
+        };
+        [
+            ("response-loop", Box::pin(response_loop)),
+            ("registration-loop", Box::pin(registration_loop)),
+            ("delay-loop", Box::pin(delay_loop)),
+            ("queue-loop", Box::pin(queue_loop)),
+        ]
+    }
+    /// Closes the registration channel and the error channel, once the
+    /// router was shut down.
+    fn close(&self) {
+        self.registration_receiver.close();
+        self.errors.sender.close();
+        // endpoints waiting for a paused router fail to send their requests
+        self.intake.resume();
+    }
+    /// Returns the name of the task `name`, prefixed with the router's task
+    /// name prefix, see [RouterBuilder::task_name_prefix].
+    pub(crate) fn task_name(&self, name: &str) -> String {
+        format!("{}-{}", self.task_name_prefix, name)
+    }
+    /// Runs the router loops until all of them complete, or until the
+    /// router is shut down.
+    ///
+    /// The loops are driven by the returned future itself, so it can be
+    /// awaited on any executor.
+    pub async fn run(&self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.started_at.get_or_init(Instant::now);
+        let loops = futures::future::join_all(self.loops().map(|(_, router_loop)| router_loop));
+        // the loops are dropped, and thereby stopped, once the router is
+        // shut down
+        tokio::select! {
+            _ = self.shutdown_token.cancelled() => self.close(),
+            _ = loops => {}
+        }
     }
 }
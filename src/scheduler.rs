@@ -0,0 +1,579 @@
+//! # Scheduler Module
+//!
+//! This module provides the [Scheduler] struct pushing recurring [Job]s
+//! through a [Router](crate::router::Router), and the [Cron] struct parsing
+//! the cron expressions jobs can be scheduled with.
+//!
+//! ## Overview
+//!
+//! A [Job] generates a request with a closure on every tick of its
+//! [Schedule], either at a fixed period or at the times matched by a
+//! [Cron] expression, and submits it through the [Endpoint] of its
+//! [Scheduler]. The responses, or the [EndpointError]s, can be observed with
+//! [Job::on_response]. Every scheduled job runs as a tokio task controlled by
+//! its [ScheduleHandle], which pauses, resumes and cancels it.
+//!
+//! Requests are submitted in their own task, so a slow response does not
+//! delay the following ticks. Ticks missed while the task was busy are
+//! skipped, as are the ticks passing while a job is paused.
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use thiserror::Error;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+
+use crate::endpoint::{Endpoint, EndpointError};
+
+/// Minutes in a day.
+const MINUTES_PER_DAY: u64 = 24 * 60;
+
+/// Number of days [Cron::next_after] searches for a matching time, enough
+/// for every combination of day of the month, month and day of the week to
+/// come around.
+const CRON_SEARCH_DAYS: u64 = 5 * 366;
+
+/// Error returned when parsing an invalid [Cron] expression.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("invalid cron expression {expression:?}: {reason}")]
+pub struct CronError {
+    /// the invalid expression
+    expression: String,
+    /// what is invalid about the expression
+    reason: &'static str,
+}
+
+/// A cron expression matching the times a [Job] is run at, in UTC.
+///
+/// The expression consists of five whitespace separated fields: minute
+/// (0-59), hour (0-23), day of the month (1-31), month (1-12) and day of
+/// the week (0-7, 0 and 7 being Sunday). Every field is `*` or a comma
+/// separated list of values `5`, ranges `1-5` and steps `*/15`, `0-30/10` or
+/// `5/20`. Like in crontab, a time matches if the day of the month or the day
+/// of the week matches, in case both are restricted.
+///
+/// # Examples
+///
+/// ```
+/// use s2a4c::scheduler::Cron;
+///
+/// // at 9:00 and 17:30 on weekdays
+/// let cron: Cron = "0,30 9,17 * * 1-5".parse().unwrap();
+/// assert!("60 * * * *".parse::<Cron>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cron {
+    /// bit set of the matching minutes
+    minutes: u64,
+    /// bit set of the matching hours
+    hours: u64,
+    /// bit set of the matching days of the month
+    days: u64,
+    /// bit set of the matching months
+    months: u64,
+    /// bit set of the matching days of the week, Sunday being 0
+    weekdays: u64,
+    /// whether the day of the month field is restricted, not starting with `*`
+    days_restricted: bool,
+    /// whether the day of the week field is restricted, not starting with `*`
+    weekdays_restricted: bool,
+}
+
+impl Cron {
+    /// Parses a cron expression, see [Cron].
+    pub fn parse(expression: &str) -> Result<Self, CronError> {
+        let error = |reason| CronError {
+            expression: expression.to_string(),
+            reason,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(error("expected 5 fields"));
+        };
+        let weekday_bits = parse_field(weekdays, 0, 7).map_err(error)?;
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59).map_err(error)?,
+            hours: parse_field(hours, 0, 23).map_err(error)?,
+            days: parse_field(days, 1, 31).map_err(error)?,
+            months: parse_field(months, 1, 12).map_err(error)?,
+            // 7 is Sunday as well
+            weekdays: (weekday_bits | weekday_bits >> 7) & 0x7f,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        })
+    }
+    /// Returns the first time matched by the expression strictly after
+    /// `time`, or `None` if there is none, e.g. for February 30th.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let seconds = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let next_minute = seconds / 60 + 1;
+        let first_day = next_minute / MINUTES_PER_DAY;
+        let mut first_minute = next_minute % MINUTES_PER_DAY;
+        for day in first_day..first_day + CRON_SEARCH_DAYS {
+            if self.matches_day(day) {
+                let minute = (first_minute..MINUTES_PER_DAY).find(|minute| {
+                    has_bit(self.hours, minute / 60) && has_bit(self.minutes, minute % 60)
+                });
+                if let Some(minute) = minute {
+                    let minutes = day * MINUTES_PER_DAY + minute;
+                    return Some(UNIX_EPOCH + Duration::from_secs(minutes * 60));
+                }
+            }
+            first_minute = 0;
+        }
+        None
+    }
+    /// Returns whether the day `days` days after the epoch matches the day of
+    /// the month, month and day of the week fields.
+    fn matches_day(&self, days: u64) -> bool {
+        let (month, day) = month_and_day(days);
+        // the epoch was a Thursday
+        let weekday = (days + 4) % 7;
+        let day_matches = has_bit(self.days, day);
+        let weekday_matches = has_bit(self.weekdays, weekday);
+        has_bit(self.months, month)
+            && match (self.days_restricted, self.weekdays_restricted) {
+                (true, true) => day_matches || weekday_matches,
+                (true, false) => day_matches,
+                (false, true) => weekday_matches,
+                (false, false) => true,
+            }
+    }
+}
+
+impl FromStr for Cron {
+    type Err = CronError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        Self::parse(expression)
+    }
+}
+
+/// Parses a field of a cron expression holding values from `min` to `max`.
+///
+/// # Returns
+///
+/// Returns the bit set of the values matched by the field.
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, &'static str> {
+    let value = |value: &str| {
+        value
+            .parse::<u64>()
+            .ok()
+            .filter(|value| (min..=max).contains(value))
+            .ok_or("value out of range")
+    };
+    let mut bits = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<usize>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err("invalid step"),
+            },
+            None => (item, None),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            // a single value with a step starts a range
+            None if step.is_some() => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if first > last {
+            return Err("invalid range");
+        }
+        for value in (first..=last).step_by(step.unwrap_or(1)) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// Returns whether `value` is in the bit set `bits`.
+fn has_bit(bits: u64, value: u64) -> bool {
+    bits & (1 << value) != 0
+}
+
+/// Returns the month and the day of the month of the day `days` days after
+/// the epoch, in the proleptic Gregorian calendar.
+fn month_and_day(days: u64) -> (u64, u64) {
+    // shifts the epoch to 0000-03-01, so leap days end a year
+    let days = days + 719_468;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    (month, day)
+}
+
+/// When a [Job] is run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// every time the period passed, starting one period after scheduling
+    Every(Duration),
+    /// at the times matched by a cron expression
+    Cron(Cron),
+}
+
+impl From<Duration> for Schedule {
+    fn from(period: Duration) -> Self {
+        Schedule::Every(period)
+    }
+}
+
+impl From<Cron> for Schedule {
+    fn from(cron: Cron) -> Self {
+        Schedule::Cron(cron)
+    }
+}
+
+/// Generates the request of a tick.
+type MakeRequest<Request> = Arc<dyn Fn() -> Request + Send + Sync>;
+/// Callback receiving the outcome of a scheduled request.
+type ResponseHook<Response> = Arc<dyn Fn(Result<Response, EndpointError>) + Send + Sync>;
+
+/// A recurring job, generating a request on every tick of its [Schedule].
+///
+/// # Type Parameters
+/// - `Request`: the request type of the router
+/// - `Response`: the response type of the router
+pub struct Job<Request, Response> {
+    /// when the job is run
+    schedule: Schedule,
+    /// generates the request of every tick
+    make_request: MakeRequest<Request>,
+    /// called with the outcome of every request
+    on_response: Option<ResponseHook<Response>>,
+}
+
+impl<Request, Response> Job<Request, Response> {
+    /// Creates a new `Job` generating a request with `make_request` on every
+    /// tick of `schedule`.
+    pub fn new(
+        schedule: impl Into<Schedule>,
+        make_request: impl Fn() -> Request + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            schedule: schedule.into(),
+            make_request: Arc::new(make_request),
+            on_response: None,
+        }
+    }
+    /// Creates a new `Job` generating a request every `period`, see
+    /// [Schedule::Every].
+    pub fn every(
+        period: Duration,
+        make_request: impl Fn() -> Request + Send + Sync + 'static,
+    ) -> Self {
+        Self::new(period, make_request)
+    }
+    /// Creates a new `Job` generating a request at the times matched by
+    /// `cron`, see [Schedule::Cron].
+    pub fn cron(cron: Cron, make_request: impl Fn() -> Request + Send + Sync + 'static) -> Self {
+        Self::new(cron, make_request)
+    }
+    /// Sets the callback called with the response, or the error, of every
+    /// request submitted by the job.
+    pub fn on_response(
+        mut self,
+        hook: impl Fn(Result<Response, EndpointError>) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_response = Some(Arc::new(hook));
+        self
+    }
+}
+
+impl<Request, Response> fmt::Debug for Job<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Job")
+            .field("schedule", &self.schedule)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Pauses, resumes and cancels a scheduled [Job].
+#[derive(Debug, Clone)]
+pub struct ScheduleHandle {
+    /// whether ticks are skipped
+    paused: Arc<AtomicBool>,
+    /// stops the job once cancelled
+    cancellation: CancellationToken,
+}
+
+impl ScheduleHandle {
+    /// Skips the ticks of the job until it is resumed. Requests already
+    /// submitted are still handled.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+    /// Resumes the job paused with [ScheduleHandle::pause], starting with its
+    /// next tick.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+    /// Returns whether the job is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+    /// Stops the job for good. Requests already submitted are still handled.
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+    /// Returns whether the job was cancelled, with [ScheduleHandle::cancel]
+    /// or [Scheduler::shutdown], or stopped as its cron expression matches no
+    /// further time.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+}
+
+/// Waits for the ticks of a [Schedule].
+enum Ticker {
+    /// ticks of [Schedule::Every]
+    Every(Interval),
+    /// ticks of [Schedule::Cron], along with the time of the last tick
+    Cron(Cron, SystemTime),
+}
+
+impl Ticker {
+    fn new(schedule: Schedule) -> Self {
+        match schedule {
+            Schedule::Every(period) => {
+                let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                Ticker::Every(interval)
+            }
+            Schedule::Cron(cron) => Ticker::Cron(cron, SystemTime::now()),
+        }
+    }
+    /// Waits for the next tick, returning `false` if there is none.
+    async fn tick(&mut self) -> bool {
+        match self {
+            Ticker::Every(interval) => {
+                interval.tick().await;
+                true
+            }
+            Ticker::Cron(cron, last) => {
+                // the clock may lag behind the time slept until
+                let now = SystemTime::now().max(*last);
+                let Some(next) = cron.next_after(now) else {
+                    return false;
+                };
+                tokio::time::sleep(next.duration_since(now).unwrap_or_default()).await;
+                *last = next;
+                true
+            }
+        }
+    }
+}
+
+/// Runs recurring [Job]s, submitting their requests through an [Endpoint].
+///
+/// # Type Parameters
+/// - `Request`: the request type of the router
+/// - `Response`: the response type of the router
+pub struct Scheduler<Request, Response> {
+    /// submits the requests of the jobs
+    endpoint: Endpoint<Request, Response>,
+    /// parent of the cancellation tokens of all scheduled jobs
+    shutdown: CancellationToken,
+}
+
+impl<Request, Response> Scheduler<Request, Response>
+where
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    /// Creates a new `Scheduler` submitting the requests of its jobs through
+    /// `endpoint`, whose timeout applies to every request.
+    pub fn new(endpoint: Endpoint<Request, Response>) -> Self {
+        Self {
+            endpoint,
+            shutdown: CancellationToken::new(),
+        }
+    }
+    /// Schedules `job`, running it in a tokio task until it is cancelled.
+    ///
+    /// # Returns
+    ///
+    /// Returns the [ScheduleHandle] pausing, resuming and cancelling the job.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime, or if the period of a
+    /// [Schedule::Every] job is zero.
+    pub fn schedule(&self, job: Job<Request, Response>) -> ScheduleHandle {
+        let handle = ScheduleHandle {
+            paused: Arc::new(AtomicBool::new(false)),
+            cancellation: self.shutdown.child_token(),
+        };
+        let mut ticker = Ticker::new(job.schedule);
+        let endpoint = self.endpoint.clone();
+        let control = handle.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = control.cancellation.cancelled() => break,
+                    ticked = ticker.tick() => if !ticked {
+                        control.cancel();
+                        break;
+                    },
+                }
+                if control.is_paused() {
+                    continue;
+                }
+                let request = (job.make_request)();
+                let endpoint = endpoint.clone();
+                let on_response = job.on_response.clone();
+                tokio::spawn(async move {
+                    let result = endpoint.handle_request(request).await;
+                    if let Some(on_response) = on_response {
+                        on_response(result);
+                    }
+                });
+            }
+        });
+        handle
+    }
+    /// Schedules a job generating a request with `make_request` every
+    /// `period`, see [Job::every].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime, or if `period` is zero.
+    pub fn every(
+        &self,
+        period: Duration,
+        make_request: impl Fn() -> Request + Send + Sync + 'static,
+    ) -> ScheduleHandle {
+        self.schedule(Job::every(period, make_request))
+    }
+    /// Schedules a job generating a request with `make_request` at the
+    /// times matched by the cron `expression`, see [Cron].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn cron(
+        &self,
+        expression: &str,
+        make_request: impl Fn() -> Request + Send + Sync + 'static,
+    ) -> Result<ScheduleHandle, CronError> {
+        let cron = Cron::parse(expression)?;
+        Ok(self.schedule(Job::cron(cron, make_request)))
+    }
+    /// Cancels all jobs scheduled by the scheduler, see
+    /// [ScheduleHandle::cancel].
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+}
+
+impl<Request, Response> fmt::Debug for Scheduler<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("shutdown", &self.shutdown.is_cancelled())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cron, Scheduler};
+    use crate::router::Router;
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    #[test]
+    fn test_cron() {
+        let next = |expression: &str, seconds| {
+            let time = UNIX_EPOCH + Duration::from_secs(seconds);
+            let next = Cron::parse(expression).unwrap().next_after(time).unwrap();
+            next.duration_since(UNIX_EPOCH).unwrap().as_secs()
+        };
+        // 2024-02-29 12:34:56, a Thursday
+        let leap_day = 1_709_210_096;
+        assert_eq!(next("*/15 * * * *", leap_day), 1_709_210_700);
+        // the following Monday, 2024-03-04 09:00
+        assert_eq!(next("0 9 * * 1", leap_day), 1_709_542_800);
+        // the day of the month or the day of the week, Sunday 2024-03-03
+        assert_eq!(next("0 9 15 * 7", leap_day), 1_709_456_400);
+        // from 2024-12-31 23:59:30 to 2025-01-01 00:00
+        assert_eq!(next("0 0 1 1 *", 1_735_689_570), 1_735_689_600);
+        assert_eq!(
+            Cron::parse("0 0 30 2 *")
+                .unwrap()
+                .next_after(SystemTime::now()),
+            None
+        );
+
+        for invalid in [
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(Cron::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scheduler() {
+        let router: Router<u32, u32> = Router::default();
+        router.tokio_spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                sender.send((uuid, request * 10)).await.unwrap();
+            }
+        });
+        router.tokio_spawn();
+        let scheduler = Scheduler::new(router.endpoint(None));
+
+        let responses = Arc::new(Mutex::new(Vec::new()));
+        let log = responses.clone();
+        let ticks = Arc::new(Mutex::new(0));
+        let handle = scheduler.schedule(
+            super::Job::every(Duration::from_millis(20), move || {
+                let mut ticks = ticks.lock().unwrap();
+                *ticks += 1;
+                *ticks
+            })
+            .on_response(move |response| log.lock().unwrap().push(response.unwrap())),
+        );
+        let sleep = |millis| tokio::time::sleep(Duration::from_millis(millis));
+        sleep(70).await;
+        assert!(responses.lock().unwrap().starts_with(&[10, 20]));
+
+        // no requests are submitted while paused
+        handle.pause();
+        sleep(10).await;
+        let paused = responses.lock().unwrap().len();
+        sleep(60).await;
+        assert_eq!(responses.lock().unwrap().len(), paused);
+        handle.resume();
+        sleep(50).await;
+        assert!(responses.lock().unwrap().len() > paused);
+
+        scheduler.shutdown();
+        assert!(handle.is_cancelled());
+        sleep(10).await;
+        let cancelled = responses.lock().unwrap().len();
+        sleep(50).await;
+        assert_eq!(responses.lock().unwrap().len(), cancelled);
+    }
+}
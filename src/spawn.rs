@@ -0,0 +1,142 @@
+//! # Spawn Module
+//!
+//! This module provides the [Spawn] trait decoupling the tasks of the
+//! [Router](crate::router::Router) from the tokio runtime, and its default
+//! [TokioSpawn] implementation.
+//!
+//! ## Overview
+//!
+//! The router spawns its loops, its workers and short lived tasks dispatching
+//! requests to the workers. By default these tasks are spawned on the tokio
+//! runtime, a different executor is used by passing a [Spawn] implementation
+//! to [RouterBuilder::spawner](crate::builder::RouterBuilder::spawner) and
+//! spawning the router with [Router::spawn](crate::router::Router::spawn) and
+//! [Router::spawn_workers](crate::router::Router::spawn_workers). Any
+//! `Fn(BoxFuture<'static, ()>)` closure implements [Spawn].
+//!
+//! Only spawning is decoupled, the timers of the router still use tokio's
+//! timer: the timeouts of endpoints, requests scheduled for later, coalescing
+//! windows and [Router::drain](crate::router::Router::drain) panic with "there
+//! is no reactor running" unless polled within the context of a tokio
+//! runtime. On an executor without a tokio runtime, use endpoints without a
+//! timeout and don't schedule requests, or enter a tokio runtime on the
+//! threads of the executor, e.g. with
+//! [Handle::enter](tokio::runtime::Handle::enter).
+//!
+//! The tasks spawned on the tokio runtime by
+//! [Router::tokio_spawn](crate::router::Router::tokio_spawn) and
+//! [Router::tokio_spawn_workers](crate::router::Router::tokio_spawn_workers)
+//! are named when compiled with `--cfg tokio_unstable` and the `tracing`
+//! feature, so tokio-console shows them by name.
+use std::{fmt, future::Future};
+
+use futures::future::BoxFuture;
+
+/// Spawns the tasks of a [Router](crate::router::Router) on an executor.
+///
+/// The router's timers require a tokio runtime regardless of the executor,
+/// see the [module documentation](self).
+pub trait Spawn: Send + Sync {
+    /// Spawns `future` as a new task, running it to completion in the
+    /// background.
+    fn spawn(&self, future: BoxFuture<'static, ()>);
+}
+
+impl<F> Spawn for F
+where
+    F: Fn(BoxFuture<'static, ()>) + Send + Sync,
+{
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        self(future)
+    }
+}
+
+impl fmt::Debug for dyn Spawn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spawn").finish_non_exhaustive()
+    }
+}
+
+/// Spawns tasks on the tokio runtime with [tokio::spawn].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioSpawn;
+
+impl Spawn for TokioSpawn {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        tokio::spawn(future);
+    }
+}
+
+/// Spawns `future` on the tokio runtime like [tokio::spawn], as a task named
+/// `name` when compiled with `--cfg tokio_unstable` and the `tracing` feature.
+pub(crate) fn tokio_spawn_named<F>(name: &str, future: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "tracing"))]
+    return tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("failed to spawn a task");
+    #[cfg(not(all(tokio_unstable, feature = "tracing")))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{builder::RouterBuilder, router::Router};
+    use futures::future::BoxFuture;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[tokio::test]
+    async fn test_custom_spawner() {
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let counter = spawned.clone();
+        let router: Router<String, String> = RouterBuilder::new()
+            .spawner(move |future: BoxFuture<'static, ()>| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(future);
+            })
+            .build();
+        router.spawn_workers(2, |receiver, sender| async move {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                sender.send((uuid, request)).await.unwrap();
+            }
+        });
+        router.spawn();
+        assert_eq!(spawned.load(Ordering::SeqCst), 3);
+
+        let response = router.endpoint(None).handle_request("ping".into()).await;
+        assert_eq!(response, Ok("ping".to_string()));
+        // the request was dispatched to the workers by a spawned task
+        assert_eq!(spawned.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_spawner_without_tokio_runtime() {
+        // every task runs on a thread of its own, without a tokio runtime
+        let router: Router<String, String> = RouterBuilder::new()
+            .spawner(|future: BoxFuture<'static, ()>| {
+                std::thread::spawn(move || futures::executor::block_on(future));
+            })
+            .build();
+        router.spawn_workers(1, |receiver, sender| async move {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                sender.send((uuid, request)).await.unwrap();
+            }
+        });
+        router.spawn();
+
+        let endpoint = router.endpoint(None);
+        let response = futures::executor::block_on(endpoint.handle_request("ping".into()));
+        assert_eq!(response, Ok("ping".to_string()));
+        router.shutdown();
+    }
+}
@@ -0,0 +1,50 @@
+//! # Stats Module
+//!
+//! This module provides the [RouterStats] struct returned by
+//! [Router::stats](crate::router::Router::stats), exposing the router's
+//! internal state for health endpoints and autoscaling decisions, and the
+//! [DrainReport] struct returned by
+//! [Router::drain](crate::router::Router::drain).
+//!
+//! With the `serde` feature [RouterStats] implements `serde::Serialize`, so
+//! it can be served as is, e.g. as JSON from a debug endpoint.
+use std::time::Duration;
+
+use crate::metrics::{LatencyPercentiles, MetricsSnapshot};
+
+/// Point in time view of the router's queues and pending requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RouterStats {
+    /// number of requests waiting in the registration channel
+    pub registration_queue_len: usize,
+    /// number of requests waiting in the request channel for a worker
+    pub request_queue_len: usize,
+    /// number of responses waiting in the response channel
+    pub response_queue_len: usize,
+    /// number of registered requests still waiting for their response
+    pub in_flight: usize,
+    /// number of running workers spawned by the router
+    pub workers: usize,
+    /// time since the router loops were started, `None` before they were
+    /// started and on `wasm32` targets
+    pub uptime: Option<Duration>,
+    /// values of the router's metrics counters, `None` if metrics are
+    /// disabled
+    pub counts: Option<MetricsSnapshot>,
+    /// percentiles of the latencies of the most recent responses, `None` if
+    /// metrics are disabled or no response was delivered yet
+    pub latency: Option<LatencyPercentiles>,
+}
+
+/// Outcome of the requests in flight while draining a router.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DrainReport {
+    /// number of requests whose response was delivered while draining
+    pub completed: usize,
+    /// number of requests whose endpoint timed out while draining
+    pub timed_out: usize,
+    /// number of requests still pending at the deadline
+    pub abandoned: usize,
+}
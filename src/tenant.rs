@@ -0,0 +1,280 @@
+//! # Tenant Module
+//!
+//! This module provides the [TenantLimits] struct bounding the share of a
+//! [Router] each tenant may use, and the crate-private [TenantQueues] struct
+//! holding one request queue per tenant, see [Router::with_tenancy].
+//!
+//! ## Overview
+//!
+//! Several tenants sharing a router compete for its workers. Without tenancy a
+//! single tenant flooding the router delays the requests of every other
+//! tenant, as requests are dispatched in the order they were registered in.
+//! With tenancy every endpoint is created for a tenant key, see
+//! [Router::tenant_endpoint], and its requests wait in the queue of that
+//! tenant. A dedicated loop dispatches the queued requests to the workers
+//! taking one request of each tenant in turn, so every tenant with queued
+//! requests gets an equal share of the workers. [TenantLimits] optionally
+//! bound the number of requests a tenant may have queued and in flight.
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::sync::Notify;
+
+use crate::{
+    dispatch::{Queued, SchedulingQueue},
+    endpoint::InFlightLimit,
+};
+
+#[cfg(doc)]
+use crate::router::Router;
+
+/// Tenant key requests of endpoints created without one are queued under.
+pub(crate) const DEFAULT_TENANT: &str = "";
+
+/// Limits applied to every tenant of a [Router], see [Router::with_tenancy].
+///
+/// Both limits are unset by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TenantLimits {
+    /// maximum number of requests of a tenant waiting for a worker
+    pub(crate) max_queued: Option<usize>,
+    /// maximum number of in-flight requests of a tenant
+    pub(crate) max_in_flight: Option<usize>,
+}
+
+impl TenantLimits {
+    /// Creates new `TenantLimits` without any limit set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the maximum number of requests of a tenant waiting for a worker.
+    /// Once reached, the tenant's endpoints reject new requests with
+    /// [EndpointError::Overloaded](crate::endpoint::EndpointError::Overloaded).
+    pub fn max_queued(mut self, max_queued: usize) -> Self {
+        self.max_queued = Some(max_queued);
+        self
+    }
+    /// Sets the maximum number of in-flight requests of a tenant, registered
+    /// with the router and still waiting for their response. Once reached,
+    /// the tenant's endpoints reject new requests with
+    /// [EndpointError::TooManyInFlight](crate::endpoint::EndpointError::TooManyInFlight).
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = Some(max_in_flight);
+        self
+    }
+}
+
+/// The queues of all tenants and the order they are dispatched from in.
+struct TenantState<Request> {
+    /// the waiting requests of every tenant with queued requests
+    queues: HashMap<Arc<str>, VecDeque<Queued<Request>>>,
+    /// tenants with queued requests, in the order they are dispatched from
+    ready: VecDeque<Arc<str>>,
+}
+
+/// Requests waiting for a worker, queued per tenant and dispatched taking one
+/// request of each tenant in turn.
+pub(crate) struct TenantQueues<Request> {
+    /// limits applied to every tenant
+    limits: TenantLimits,
+    /// the waiting requests
+    state: Mutex<TenantState<Request>>,
+    /// in-flight limits of the tenants, created along with a tenant's first
+    /// endpoint
+    in_flight_limits: Mutex<HashMap<Arc<str>, InFlightLimit>>,
+    /// whether no further requests are pushed
+    closed: AtomicBool,
+    /// wakes the loop popping requests once a request was pushed
+    notify: Notify,
+}
+
+impl<Request> TenantQueues<Request> {
+    /// Creates new, empty `TenantQueues` applying `limits` to every tenant.
+    pub(crate) fn new(limits: TenantLimits) -> Self {
+        Self {
+            limits,
+            state: Mutex::new(TenantState {
+                queues: HashMap::new(),
+                ready: VecDeque::new(),
+            }),
+            in_flight_limits: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+            notify: Notify::new(),
+        }
+    }
+    /// Pushes `queued` to the back of the queue of `tenant`.
+    pub(crate) fn push(&self, tenant: Arc<str>, queued: Queued<Request>) {
+        let mut state = self.state.lock().unwrap();
+        let TenantState { queues, ready } = &mut *state;
+        queues
+            .entry(tenant.clone())
+            .or_insert_with(|| {
+                ready.push_back(tenant);
+                VecDeque::new()
+            })
+            .push_back(queued);
+        drop(state);
+        self.notify.notify_one();
+    }
+    /// Returns whether the queue of `tenant` reached the tenant's
+    /// [TenantLimits::max_queued].
+    pub(crate) fn is_full(&self, tenant: &str) -> bool {
+        self.limits.max_queued.is_some_and(|max_queued| {
+            let state = self.state.lock().unwrap();
+            state.queues.get(tenant).map_or(0, VecDeque::len) >= max_queued
+        })
+    }
+    /// Returns the in-flight limit shared by the endpoints of `tenant`, if
+    /// [TenantLimits::max_in_flight] is set.
+    pub(crate) fn in_flight_limit(&self, tenant: &Arc<str>) -> Option<InFlightLimit> {
+        let max_in_flight = self.limits.max_in_flight?;
+        let mut in_flight_limits = self.in_flight_limits.lock().unwrap();
+        let limit = in_flight_limits
+            .entry(tenant.clone())
+            .or_insert_with(|| InFlightLimit::new(max_in_flight));
+        Some(limit.clone())
+    }
+    /// Returns the number of waiting requests of all tenants.
+    pub(crate) fn len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.queues.values().map(VecDeque::len).sum()
+    }
+    /// Removes the request at the front of the next tenant's queue, moving
+    /// the tenant to the back of the order if it has further requests.
+    fn try_pop(&self) -> Option<Queued<Request>> {
+        let mut state = self.state.lock().unwrap();
+        let tenant = state.ready.pop_front()?;
+        let queue = state.queues.get_mut(&tenant)?;
+        let queued = queue.pop_front();
+        if queue.is_empty() {
+            state.queues.remove(&tenant);
+        } else {
+            state.ready.push_back(tenant);
+        }
+        queued
+    }
+}
+
+impl<Request: Send> SchedulingQueue<Request> for TenantQueues<Request> {
+    /// Waits for a request and removes it from the queue of the tenant whose
+    /// turn it is.
+    async fn pop(&self) -> Option<Queued<Request>> {
+        loop {
+            if let Some(queued) = self.try_pop() {
+                return Some(queued);
+            }
+            if self.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+            self.notify.notified().await;
+        }
+    }
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+}
+
+impl<Request> fmt::Debug for TenantQueues<Request> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TenantQueues")
+            .field("limits", &self.limits)
+            .field("len", &self.len())
+            .field("closed", &self.closed.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{endpoint::EndpointError, router::Router, tenant::TenantLimits};
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn test_fair_scheduling() {
+        let router: Router<u64, u64> = Router::default().with_tenancy(TenantLimits::new());
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let log = handled.clone();
+        router.tokio_spawn_workers(1, move |receiver, sender| {
+            let log = log.clone();
+            async move {
+                while let Ok((uuid, request)) = receiver.recv().await {
+                    log.lock().unwrap().push(request);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    sender.send((uuid, request)).await.unwrap();
+                }
+            }
+        });
+        router.tokio_spawn();
+
+        let flooding = router.tenant_endpoint("flooding", None);
+        let other = router.tenant_endpoint("other", None);
+        let sleep = |millis| tokio::time::sleep(Duration::from_millis(millis));
+        let responses = tokio::join!(
+            futures::future::join_all((1..=6).map(|request| flooding.handle_request(request))),
+            async {
+                sleep(10).await;
+                tokio::join!(other.handle_request(11), other.handle_request(12))
+            },
+        );
+        assert_eq!(responses.0, (1..=6).map(Ok).collect::<Vec<_>>());
+        assert_eq!(responses.1, (Ok(11), Ok(12)));
+        // 1 is handled, 2 handed over to the request channel and 3 held by the
+        // dispatching loop before the other tenant's requests are queued
+        assert_eq!(*handled.lock().unwrap(), [1, 2, 3, 4, 11, 5, 12, 6]);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_limits() {
+        let limits = TenantLimits::new().max_queued(1).max_in_flight(4);
+        let router: Router<u32, u32> = Router::default().with_tenancy(limits);
+        router.tokio_spawn_workers(1, |_, _| std::future::pending());
+        router.tokio_spawn();
+
+        // the worker never receives, 1 is handed over to the request channel
+        // and 2 held by the dispatching loop, 3 fills the queue of the tenant
+        let a = router.tenant_endpoint("a", Duration::from_millis(200));
+        let waiting = tokio::spawn({
+            let a = a.clone();
+            async move {
+                futures::future::join_all((1..=3).map(|request| a.handle_request(request))).await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(a.handle_request(4).await, Err(EndpointError::Overloaded));
+
+        // other tenants are neither queued behind nor limited by the tenant
+        let b = router.tenant_endpoint("b", Duration::from_millis(10));
+        let response = b.handle_request(5).await;
+        assert!(matches!(response, Err(EndpointError::Timeout(_))));
+        let responses = waiting.await.unwrap();
+        assert!(responses
+            .iter()
+            .all(|response| matches!(response, Err(EndpointError::Timeout(_)))));
+
+        // the in-flight quota counts the requests of all endpoints of a tenant
+        let limits = TenantLimits::new().max_in_flight(1);
+        let router: Router<u32, u32> = Router::default().with_tenancy(limits);
+        router.tokio_spawn_workers(1, |_, _| std::future::pending());
+        router.tokio_spawn();
+        let a = router.tenant_endpoint("a", Duration::from_millis(50));
+        let also_a = router.tenant_endpoint("a", None);
+        let b = router.tenant_endpoint("b", Duration::from_millis(10));
+        let (first, (second, other)) = tokio::join!(a.handle_request(1), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            (also_a.handle_request(2).await, b.handle_request(3).await)
+        });
+        assert!(matches!(first, Err(EndpointError::Timeout(_))));
+        assert_eq!(second, Err(EndpointError::TooManyInFlight));
+        assert!(matches!(other, Err(EndpointError::Timeout(_))));
+    }
+}
@@ -0,0 +1,379 @@
+//! # Testing Module
+//!
+//! This module, enabled by the `testing` feature, provides the [MockRouter]
+//! struct for unit-testing code that submits requests through an
+//! [Endpoint], with the responses scripted by [Reply] instead of handled by
+//! real workers.
+//!
+//! ## Overview
+//!
+//! A [MockRouter] runs a real [Router] along with a single scripted worker.
+//! Every request dispatched to the worker is matched against the rules added
+//! with [MockRouter::on] and [MockRouter::on_request], in the order they were
+//! added, and answered with the [Reply] of the first matching rule, or with
+//! the fallback set with [MockRouter::otherwise]. Replies can respond, never
+//! respond so the endpoint times out, fail the request as if its worker
+//! panicked, and wait for a delay first.
+//!
+//! Delays and endpoint timeouts only rely on tokio's timer, so tests run
+//! deterministically with the clock paused, e.g. with
+//! `#[tokio::test(start_paused = true)]`. The `testing` feature enables
+//! tokio's `test-util` feature for this.
+//!
+//! ```rust
+//! # use s2a4c::{endpoint::EndpointError, testing::{MockRouter, Reply}};
+//! # use std::time::Duration;
+//! # #[tokio::main]
+//! # async fn main() {
+//! let mock: MockRouter<u32, u32> = MockRouter::new();
+//! mock.on_request(1, Reply::respond(2))
+//!     .on(|request| *request > 10, Reply::respond_with(|request| request * 2));
+//! let endpoint = mock.endpoint(Duration::from_millis(10));
+//! assert_eq!(endpoint.handle_request(1).await, Ok(2));
+//! assert_eq!(endpoint.handle_request(11).await, Ok(22));
+//! // unmatched requests are never answered
+//! assert!(matches!(endpoint.handle_request(5).await, Err(EndpointError::Timeout(_))));
+//! mock.assert_dispatched(3);
+//! # }
+//! ```
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use uuid::Uuid;
+
+use crate::{
+    channel::Sender,
+    endpoint::{Endpoint, Timeout},
+    handle::RouterHandle,
+    router::Router,
+};
+
+/// Decides whether a rule of a [MockRouter] applies to a request.
+type Matcher<Request> = Box<dyn Fn(&Request) -> bool + Send + Sync>;
+
+/// A rule of a [MockRouter], replying to the requests it matches.
+type Rule<Request, Response> = (Matcher<Request>, Reply<Request, Response>);
+
+/// Produces the response of a [Reply::respond_with] reply.
+type Respond<Request, Response> = Arc<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// What a [Reply] does with a request.
+enum Action<Request, Response> {
+    /// answers the request with the response produced for it
+    Respond(Respond<Request, Response>),
+    /// never answers the request
+    Never,
+    /// fails the request as if its worker panicked
+    Panic,
+}
+
+/// The scripted answer of a [MockRouter] to a request.
+pub struct Reply<Request, Response> {
+    /// what is done with the request
+    action: Action<Request, Response>,
+    /// time waited before acting, if any
+    delay: Option<Duration>,
+}
+
+impl<Request, Response> Reply<Request, Response> {
+    /// Answers every request with a clone of `response`.
+    pub fn respond(response: Response) -> Self
+    where
+        Response: Clone + Send + Sync + 'static,
+    {
+        Self::respond_with(move |_| response.clone())
+    }
+    /// Answers every request with the response `respond` produces for it.
+    pub fn respond_with(respond: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        Self {
+            action: Action::Respond(Arc::new(respond)),
+            delay: None,
+        }
+    }
+    /// Never answers requests, so their endpoint times out.
+    pub fn never() -> Self {
+        Self {
+            action: Action::Never,
+            delay: None,
+        }
+    }
+    /// Fails requests with
+    /// [EndpointError::WorkerPanicked](crate::endpoint::EndpointError::WorkerPanicked),
+    /// as if their worker panicked.
+    pub fn panic() -> Self {
+        Self {
+            action: Action::Panic,
+            delay: None,
+        }
+    }
+    /// Waits for `delay` before acting on a request.
+    pub fn after(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+// implemented by hand, deriving would require `Request` and `Response` to
+// implement the traits as well.
+impl<Request, Response> Clone for Reply<Request, Response> {
+    fn clone(&self) -> Self {
+        let action = match &self.action {
+            Action::Respond(respond) => Action::Respond(respond.clone()),
+            Action::Never => Action::Never,
+            Action::Panic => Action::Panic,
+        };
+        Self {
+            action,
+            delay: self.delay,
+        }
+    }
+}
+
+impl<Request, Response> fmt::Debug for Reply<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.action {
+            Action::Respond(_) => "Respond",
+            Action::Never => "Never",
+            Action::Panic => "Panic",
+        };
+        f.debug_struct("Reply")
+            .field("action", &action)
+            .field("delay", &self.delay)
+            .finish()
+    }
+}
+
+/// The rules of a [MockRouter] and the requests dispatched to it.
+struct Script<Request, Response> {
+    /// rules matched against requests in the order they were added
+    rules: Mutex<Vec<Rule<Request, Response>>>,
+    /// reply to requests matching no rule
+    fallback: Mutex<Reply<Request, Response>>,
+    /// requests dispatched to the scripted worker, in dispatch order
+    dispatched: Mutex<Vec<Request>>,
+}
+
+impl<Request, Response> Script<Request, Response> {
+    /// Records `request` as dispatched and returns its reply.
+    fn reply(&self, request: &Request) -> Reply<Request, Response>
+    where
+        Request: Clone,
+    {
+        lock(&self.dispatched).push(request.clone());
+        let rules = lock(&self.rules);
+        match rules.iter().find(|(matcher, _)| matcher(request)) {
+            Some((_, reply)) => reply.clone(),
+            None => lock(&self.fallback).clone(),
+        }
+    }
+}
+
+/// Locks `mutex`, recovering its data if a test panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// A [Router] whose requests are answered by a scripted worker, for
+/// unit-testing code taking an [Endpoint].
+///
+/// Dropping the mock shuts the router down, see [RouterHandle].
+///
+/// # Type Parameters
+/// - `Request`: the request type of the router
+/// - `Response`: the response type of the router
+pub struct MockRouter<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    /// owns the router and its tasks
+    handle: RouterHandle<Request, Response>,
+    /// the rules and dispatched requests
+    script: Arc<Script<Request, Response>>,
+}
+
+impl<Request, Response> MockRouter<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    /// Creates a new `MockRouter` on a [Router::default].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn new() -> Self {
+        Self::from_router(Router::default())
+    }
+    /// Creates a new `MockRouter` on `router`, e.g. to apply its default
+    /// timeout or hooks. Its loops and the scripted worker are spawned right
+    /// away.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn from_router(router: Router<Request, Response>) -> Self {
+        let script = Arc::new(Script {
+            rules: Mutex::new(Vec::new()),
+            fallback: Mutex::new(Reply::never()),
+            dispatched: Mutex::new(Vec::new()),
+        });
+        let mut handle = router.tokio_spawn_handle();
+        let worker_script = script.clone();
+        handle.spawn_workers(1, move |receiver, sender| {
+            let script = worker_script.clone();
+            let router = router.clone();
+            async move {
+                while let Ok((uuid, request)) = receiver.recv().await {
+                    let reply = script.reply(&request);
+                    // every request is answered by its own task, so delays
+                    // don't hold up the following requests
+                    let (sender, router) = (sender.clone(), router.clone());
+                    tokio::spawn(async move {
+                        act(reply, uuid, request, &router, &sender).await;
+                    });
+                }
+            }
+        });
+        Self { handle, script }
+    }
+    /// Answers requests for which `matcher` returns `true` with `reply`,
+    /// unless a rule added before matches them as well.
+    pub fn on(
+        &self,
+        matcher: impl Fn(&Request) -> bool + Send + Sync + 'static,
+        reply: Reply<Request, Response>,
+    ) -> &Self {
+        lock(&self.script.rules).push((Box::new(matcher), reply));
+        self
+    }
+    /// Answers requests equal to `request` with `reply`, like
+    /// [MockRouter::on].
+    pub fn on_request(&self, request: Request, reply: Reply<Request, Response>) -> &Self
+    where
+        Request: PartialEq + Sync,
+    {
+        self.on(move |candidate| *candidate == request, reply)
+    }
+    /// Answers requests matching no rule with `reply`, instead of never
+    /// answering them.
+    pub fn otherwise(&self, reply: Reply<Request, Response>) -> &Self {
+        *lock(&self.script.fallback) = reply;
+        self
+    }
+    /// Creates a new [Endpoint] of the mocked router, see [Router::endpoint].
+    pub fn endpoint(&self, timeout: impl Into<Timeout>) -> Endpoint<Request, Response> {
+        self.router().endpoint(timeout)
+    }
+    /// Returns the mocked router.
+    pub fn router(&self) -> &Router<Request, Response> {
+        self.handle.router()
+    }
+    /// Returns the requests dispatched to the scripted worker so far, in
+    /// dispatch order.
+    pub fn dispatched(&self) -> Vec<Request> {
+        lock(&self.script.dispatched).clone()
+    }
+    /// Asserts that `expected` requests were dispatched to the scripted
+    /// worker so far.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a different number of requests was dispatched.
+    #[track_caller]
+    pub fn assert_dispatched(&self, expected: usize) {
+        let dispatched = lock(&self.script.dispatched).len();
+        assert_eq!(
+            dispatched, expected,
+            "expected {expected} dispatched requests, got {dispatched}"
+        );
+    }
+}
+
+/// Acts on `request`, dispatched under `uuid`, as scripted by `reply`.
+async fn act<Request, Response>(
+    reply: Reply<Request, Response>,
+    uuid: Uuid,
+    request: Request,
+    router: &Router<Request, Response>,
+    sender: &Sender<(Uuid, Response)>,
+) where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    if let Some(delay) = reply.delay {
+        tokio::time::sleep(delay).await;
+    }
+    match reply.action {
+        Action::Respond(respond) => {
+            let _ = sender.send((uuid, respond(&request))).await;
+        }
+        Action::Never => {}
+        Action::Panic => router.fail_panicked(&uuid),
+    }
+}
+
+impl<Request, Response> Default for MockRouter<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Request, Response> fmt::Debug for MockRouter<Request, Response>
+where
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockRouter")
+            .field("rules", &lock(&self.script.rules).len())
+            .field("dispatched", &lock(&self.script.dispatched).len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MockRouter, Reply};
+    use crate::endpoint::EndpointError;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_mock_router() {
+        let mock: MockRouter<u32, u32> = MockRouter::new();
+        mock.on_request(1, Reply::respond(10).after(Duration::from_secs(30)))
+            .on_request(2, Reply::panic())
+            .on(
+                |request| *request < 5,
+                Reply::respond_with(|request| request * 2),
+            )
+            .otherwise(Reply::respond(0));
+        let endpoint = mock.endpoint(Duration::from_secs(60));
+        let slow = mock.endpoint(Duration::from_secs(10));
+
+        // the paused clock advances to the delay, but not to the timeout
+        let started = tokio::time::Instant::now();
+        assert_eq!(endpoint.handle_request(1).await, Ok(10));
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+        let response = slow.handle_request(1).await;
+        assert!(matches!(response, Err(EndpointError::Timeout(_))));
+        assert_eq!(started.elapsed(), Duration::from_secs(40));
+
+        assert_eq!(
+            endpoint.handle_request(2).await,
+            Err(EndpointError::WorkerPanicked)
+        );
+        // earlier rules take precedence
+        assert_eq!(endpoint.handle_request(3).await, Ok(6));
+        assert_eq!(endpoint.handle_request(7).await, Ok(0));
+        assert_eq!(mock.dispatched(), [1, 1, 2, 3, 7]);
+        mock.assert_dispatched(5);
+    }
+}
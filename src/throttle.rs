@@ -0,0 +1,326 @@
+//! # Throttle Module
+//!
+//! This module provides the [KeyThrottle] struct limiting the requests of a
+//! [Router] per request key, and the [Excess] enum deciding what happens to
+//! the requests exceeding the limits, see [Router::with_key_throttling].
+//!
+//! ## Overview
+//!
+//! Requests sharing a key, e.g. the id of the customer they are made for,
+//! often end up at the same downstream service. A [KeyThrottle] extracts the
+//! key of every request and bounds, per key, the number of requests in flight
+//! and the number of requests dispatched per second, so a single customer can
+//! not overwhelm the service on behalf of all others.
+//!
+//! Like the in-flight limits of the router, the limits are enforced by the
+//! endpoints before a request is registered with the router. A request holds
+//! its concurrency slot until the call submitting it returned, whether its
+//! response was received, it timed out or it failed, or until the future of
+//! the call was dropped, so a key whose workers never answer is not throttled
+//! for good. The rate limit is a token bucket per key, holding up to one
+//! second's worth of requests. Requests exceeding the limits of their key are
+//! either rejected with [EndpointError::Throttled] right away, or wait in a
+//! bounded buffer of their key until the limits admit them.
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+
+use crate::endpoint::{timeout, TimerInstant as Instant};
+
+#[cfg(doc)]
+use crate::{endpoint::EndpointError, router::Router};
+
+/// Number of tracked keys from which on idle keys are swept.
+const MIN_SWEEP_AT: usize = 1024;
+
+/// What happens to the requests exceeding the limits of their key, see
+/// [KeyThrottle::on_excess].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Excess {
+    /// requests exceeding the limits are rejected with
+    /// [EndpointError::Throttled]
+    #[default]
+    Reject,
+    /// requests exceeding the limits wait until the limits admit them, up to
+    /// the given number of requests per key, further requests are rejected
+    /// with [EndpointError::Throttled]
+    Queue(usize),
+}
+
+/// Throttling of the requests of a [Router] by request key, see
+/// [Router::with_key_throttling].
+///
+/// No limit is set by default, and excess requests are rejected.
+pub struct KeyThrottle<Request> {
+    /// maps requests to the hash of their key
+    key_fn: Arc<dyn Fn(&Request) -> u64 + Send + Sync>,
+    /// maximum number of in-flight requests per key
+    max_concurrent: Option<usize>,
+    /// maximum number of requests dispatched per second and key
+    max_per_second: Option<u32>,
+    /// what happens to the requests exceeding the limits
+    excess: Excess,
+    /// the state of the keys, shared by all endpoints
+    keys: Arc<Mutex<Keys>>,
+}
+
+/// The state of all keys with in-flight or waiting requests, or with an
+/// emptied token bucket.
+#[derive(Debug)]
+struct Keys {
+    /// the state of every tracked key, by the hash of the key
+    states: HashMap<u64, KeyState>,
+    /// number of tracked keys from which on idle keys are swept
+    sweep_at: usize,
+}
+
+/// The throttling state of a single key.
+#[derive(Debug)]
+struct KeyState {
+    /// number of requests holding a [ThrottleSlot]
+    in_flight: usize,
+    /// number of requests waiting for the limits to admit them
+    waiting: usize,
+    /// number of requests the token bucket admits before refilling
+    tokens: f64,
+    /// the time the token bucket was last refilled at
+    refilled_at: Instant,
+    /// wakes a waiting request once a slot was released
+    released: Arc<Notify>,
+}
+
+impl KeyState {
+    /// Refills the token bucket with the tokens accrued since the last refill.
+    fn refill(&mut self, now: Instant, max_per_second: Option<u32>) {
+        if let Some(rate) = max_per_second {
+            let elapsed = now.saturating_duration_since(self.refilled_at);
+            self.tokens =
+                (self.tokens + elapsed.as_secs_f64() * f64::from(rate)).min(f64::from(rate));
+        }
+        self.refilled_at = now;
+    }
+    /// Returns whether the key has no in-flight or waiting requests and a
+    /// full token bucket, so tracking it can stop.
+    fn is_idle(&mut self, now: Instant, max_per_second: Option<u32>) -> bool {
+        self.refill(now, max_per_second);
+        self.in_flight == 0
+            && self.waiting == 0
+            && max_per_second.is_none_or(|rate| self.tokens >= f64::from(rate))
+    }
+}
+
+impl<Request> KeyThrottle<Request> {
+    /// Creates a new `KeyThrottle` throttling requests by the key returned by
+    /// `key_fn`.
+    pub fn new<Key>(key_fn: impl Fn(&Request) -> Key + Send + Sync + 'static) -> Self
+    where
+        Key: Hash,
+    {
+        Self {
+            key_fn: Arc::new(move |request| {
+                let mut hasher = DefaultHasher::new();
+                key_fn(request).hash(&mut hasher);
+                hasher.finish()
+            }),
+            max_concurrent: None,
+            max_per_second: None,
+            excess: Excess::default(),
+            keys: Arc::new(Mutex::new(Keys {
+                states: HashMap::new(),
+                sweep_at: MIN_SWEEP_AT,
+            })),
+        }
+    }
+    /// Sets the maximum number of in-flight requests per key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_concurrent` is zero.
+    pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+        assert!(max_concurrent > 0, "max_concurrent must be positive");
+        self.max_concurrent = Some(max_concurrent);
+        self
+    }
+    /// Sets the maximum number of requests dispatched per second and key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_per_second` is zero.
+    pub fn max_per_second(mut self, max_per_second: u32) -> Self {
+        assert!(max_per_second > 0, "max_per_second must be positive");
+        self.max_per_second = Some(max_per_second);
+        self
+    }
+    /// Sets what happens to the requests exceeding the limits of their key.
+    ///
+    /// The wait of queued requests counts towards the timeout of their
+    /// endpoint, they fail with [EndpointError::Timeout] once it elapsed
+    /// before the limits admitted them.
+    pub fn on_excess(mut self, excess: Excess) -> Self {
+        self.excess = excess;
+        self
+    }
+    /// Returns the hash of the key of `request`.
+    pub(crate) fn key(&self, request: &Request) -> u64 {
+        (self.key_fn)(request)
+    }
+    /// Waits until the limits of `key`, as returned by [KeyThrottle::key],
+    /// admit a request.
+    ///
+    /// # Returns
+    ///
+    /// Returns the [ThrottleSlot] of the admitted request, or `None` if the
+    /// request is rejected.
+    pub(crate) async fn acquire(&self, key: u64) -> Option<ThrottleSlot> {
+        let mut waiter: Option<Waiter> = None;
+        loop {
+            let (released, next_token) = {
+                let mut keys = self.keys.lock().unwrap();
+                let now = Instant::now();
+                if keys.states.len() >= keys.sweep_at {
+                    keys.states
+                        .retain(|_, state| !state.is_idle(now, self.max_per_second));
+                    keys.sweep_at = MIN_SWEEP_AT.max(2 * keys.states.len());
+                }
+                let state = keys.states.entry(key).or_insert_with(|| KeyState {
+                    in_flight: 0,
+                    waiting: 0,
+                    tokens: self.max_per_second.map_or(0.0, f64::from),
+                    refilled_at: now,
+                    released: Arc::new(Notify::new()),
+                });
+                state.refill(now, self.max_per_second);
+                let has_slot = self.max_concurrent.is_none_or(|max| state.in_flight < max);
+                let next_token = self
+                    .max_per_second
+                    .filter(|_| state.tokens < 1.0)
+                    .map(|rate| Duration::from_secs_f64((1.0 - state.tokens) / f64::from(rate)));
+                // new requests queue up behind the waiting ones
+                let is_next = waiter.is_some() || state.waiting == 0;
+                if has_slot && next_token.is_none() && is_next {
+                    state.in_flight += 1;
+                    if self.max_per_second.is_some() {
+                        state.tokens -= 1.0;
+                    }
+                    if let Some(mut waiter) = waiter.take() {
+                        waiter.armed = false;
+                        state.waiting -= 1;
+                        // passes the wakeup on in case further requests can
+                        // be admitted
+                        if state.waiting > 0 {
+                            state.released.notify_one();
+                        }
+                    }
+                    return Some(ThrottleSlot {
+                        keys: self.keys.clone(),
+                        key,
+                    });
+                }
+                if waiter.is_none() {
+                    let capacity = match self.excess {
+                        Excess::Reject => 0,
+                        Excess::Queue(capacity) => capacity,
+                    };
+                    if state.waiting >= capacity {
+                        return None;
+                    }
+                    state.waiting += 1;
+                    waiter = Some(Waiter {
+                        keys: self.keys.clone(),
+                        key,
+                        armed: true,
+                    });
+                }
+                (state.released.clone(), next_token)
+            };
+            match next_token {
+                Some(next_token) => {
+                    let _ = timeout(next_token, released.notified()).await;
+                }
+                None => released.notified().await,
+            }
+        }
+    }
+}
+
+// implemented by hand, deriving would require `Request` to implement the
+// trait as well.
+impl<Request> Clone for KeyThrottle<Request> {
+    fn clone(&self) -> Self {
+        Self {
+            key_fn: self.key_fn.clone(),
+            max_concurrent: self.max_concurrent,
+            max_per_second: self.max_per_second,
+            excess: self.excess,
+            keys: self.keys.clone(),
+        }
+    }
+}
+
+impl<Request> fmt::Debug for KeyThrottle<Request> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyThrottle")
+            .field("max_concurrent", &self.max_concurrent)
+            .field("max_per_second", &self.max_per_second)
+            .field("excess", &self.excess)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Counts a request waiting in the buffer of its key until admitted, or until
+/// dropped because the request timed out or was dropped.
+struct Waiter {
+    /// the state of the keys
+    keys: Arc<Mutex<Keys>>,
+    /// hash of the key of the request
+    key: u64,
+    /// whether the request still waits
+    armed: bool,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let mut keys = self.keys.lock().unwrap();
+        if let Some(state) = keys.states.get_mut(&self.key) {
+            state.waiting -= 1;
+            // the dropped request may have consumed the wakeup of a slot
+            state.released.notify_one();
+        }
+    }
+}
+
+/// Counts a request towards the concurrency limit of its key until dropped
+/// along with the guard of the endpoint waiting for its response.
+pub(crate) struct ThrottleSlot {
+    /// the state of the keys
+    keys: Arc<Mutex<Keys>>,
+    /// hash of the key of the request
+    key: u64,
+}
+
+impl fmt::Debug for ThrottleSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThrottleSlot")
+            .field("key", &self.key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for ThrottleSlot {
+    fn drop(&mut self) {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some(state) = keys.states.get_mut(&self.key) {
+            state.in_flight -= 1;
+            state.released.notify_one();
+        }
+    }
+}
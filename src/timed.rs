@@ -0,0 +1,93 @@
+//! # Timed Module
+//!
+//! This module provides the [Timed] struct returned by
+//! [Endpoint::handle_request_timed](crate::endpoint::Endpoint::handle_request_timed),
+//! annotating a response with the time its request spent in the router.
+//!
+//! ## Overview
+//!
+//! The latency of a request splits into the time it waited to be dispatched
+//! to the workers, e.g. in the registration channel, in the scheduling
+//! queues or for the limits of the router to admit it, and the time from its
+//! dispatch until its response was received. Only the router knows when a
+//! request was dispatched, so it stamps the dispatch time of timed requests
+//! for their endpoint to split the latency.
+use std::{
+    ops::Deref,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// A response along with the time its request spent in the router.
+///
+/// # Type Parameters
+/// - `Response`: the type of the response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timed<Response> {
+    /// the response itself
+    pub response: Response,
+    /// time from the submission of the request until it was dispatched to
+    /// the workers
+    pub queue_wait: Duration,
+    /// time from the dispatch of the request until its response was
+    /// received
+    pub processing: Duration,
+    /// time from the submission of the request until its response was
+    /// received, the sum of `queue_wait` and `processing`
+    pub total: Duration,
+}
+
+impl<Response> Timed<Response> {
+    /// Annotates `response` with the time passed since `submitted_at`, split
+    /// at `dispatched_at`.
+    ///
+    /// Responses of requests that were never dispatched, e.g. because they
+    /// were answered from the router's cache, count their whole latency as
+    /// queue wait.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn new(
+        response: Response,
+        submitted_at: Instant,
+        dispatched_at: Option<Instant>,
+    ) -> Self {
+        let total = submitted_at.elapsed();
+        let queue_wait = dispatched_at.map_or(total, |dispatched_at| {
+            dispatched_at.saturating_duration_since(submitted_at)
+        });
+        Self {
+            response,
+            queue_wait,
+            processing: total.saturating_sub(queue_wait),
+            total,
+        }
+    }
+    /// Returns the response, discarding its timings.
+    pub fn into_inner(self) -> Response {
+        self.response
+    }
+}
+
+impl<Response> Deref for Timed<Response> {
+    type Target = Response;
+
+    fn deref(&self) -> &Response {
+        &self.response
+    }
+}
+
+/// The time a timed request was first dispatched to the workers at, stamped
+/// by the router and shared with the request's endpoint.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DispatchedAt(Arc<OnceLock<Instant>>);
+
+impl DispatchedAt {
+    /// Stamps the current time, unless the request was already dispatched.
+    pub(crate) fn stamp(&self) {
+        let _ = self.0.set(Instant::now());
+    }
+    /// Returns the time the request was first dispatched at, if it was.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn get(&self) -> Option<Instant> {
+        self.0.get().copied()
+    }
+}
@@ -0,0 +1,313 @@
+//! # WebSocket Module
+//!
+//! This module provides a WebSocket transport for the [Router], enabled with
+//! the `websocket` feature.
+//!
+//! ## Overview
+//!
+//! The transport lets remote clients (browsers, other services) act as
+//! [Endpoint](crate::endpoint::Endpoint)s. It accepts WebSocket connections,
+//! decodes every text or binary message as a [RequestFrame], pushes the
+//! request through the [Router] and writes a [ResponseFrame] back on the same
+//! connection, in a message of the same kind.
+//!
+//! Requests on a single connection are handled concurrently, so responses
+//! may arrive out of order. Every frame carries the `id` chosen by the client
+//! which is echoed in the matching response for correlation. At most
+//! [MAX_PENDING_RESPONSES] requests of a connection are handled or waiting to
+//! be written at a time, further messages are not read until a response was
+//! written, so a slow client can not make the responses pile up.
+//!
+//! Frames are encoded as JSON using [serde_json](https://docs.rs/serde_json),
+//! or with the [Codec] passed to [Router::tokio_spawn_websocket_with_codec].
+use std::{fmt, sync::Arc, time::Duration};
+
+use crate::channel::bounded;
+use futures::{SinkExt, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+use uuid::Uuid;
+
+use crate::{
+    codec::{Codec, JsonCodec},
+    router::Router,
+};
+
+/// Maximum number of requests of a connection handled or waiting for their
+/// response to be written at a time.
+pub const MAX_PENDING_RESPONSES: usize = 64;
+
+/// Delay before accepting again after the first failure to accept a
+/// connection, doubled with every further failure.
+const MIN_ACCEPT_DELAY: Duration = Duration::from_millis(10);
+
+/// Maximum delay before accepting again after a failure to accept a
+/// connection, e.g. once the process ran out of file descriptors.
+const MAX_ACCEPT_DELAY: Duration = Duration::from_secs(1);
+
+/// Logs a failure of the transport as a `tracing` warning with the `tracing`
+/// feature.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn warn(err: &dyn fmt::Debug) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(error = ?err, "websocket error");
+}
+
+/// A request sent by a WebSocket client.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RequestFrame<Request> {
+    /// client chosen identifier, echoed in the corresponding [ResponseFrame]
+    pub id: Uuid,
+    /// the request handled by the router
+    pub request: Request,
+}
+
+/// A response sent back to a WebSocket client.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ResponseFrame<Response> {
+    /// identifier of the [RequestFrame] this frame responds to
+    pub id: Uuid,
+    /// the response, or the display message of the
+    /// [EndpointError](crate::endpoint::EndpointError) on failure
+    pub result: Result<Response, String>,
+}
+
+/// Asynchronous private function serving a single WebSocket connection.
+///
+/// # Arguments
+///
+/// - `router`: The router handling the decoded requests.
+/// - `stream`: The accepted TCP connection.
+/// - `timeout`: An optional [Duration] applied to every request, the
+///   router's default timeout if `None`.
+/// - `codec`: The [Codec] frames are encoded with.
+///
+/// # Behavior
+///
+/// Performs the WebSocket handshake, then reads messages until the client
+/// closes the connection. Every request is handled in its own task and the
+/// encoded responses are funneled through a bounded channel to a single
+/// writer task, no further message is read while [MAX_PENDING_RESPONSES]
+/// requests are pending. Responses to text messages are sent as text,
+/// unless the codec's encoding is not valid UTF-8, and responses to binary
+/// messages as binary. Messages that fail to decode are logged and ignored.
+async fn serve_connection<Request, Response, C: Codec>(
+    router: Router<Request, Response>,
+    stream: TcpStream,
+    timeout: Option<Duration>,
+    codec: C,
+) where
+    Request: Serialize + DeserializeOwned + Send + 'static + Clone,
+    Response: Serialize + DeserializeOwned + Send + 'static + Clone,
+{
+    let (mut sink, mut stream) = match accept_async(stream).await {
+        Ok(websocket) => websocket.split(),
+        Err(err) => {
+            warn(&err);
+            return;
+        }
+    };
+    let (frame_sender, frame_receiver) = bounded::<Message>(MAX_PENDING_RESPONSES);
+    let writer = tokio::spawn(async move {
+        while let Ok(frame) = frame_receiver.recv().await {
+            if let Err(err) = sink.send(frame).await {
+                warn(&err);
+                break;
+            }
+        }
+    });
+    let pending = Arc::new(Semaphore::new(MAX_PENDING_RESPONSES));
+    // the semaphore is never closed
+    while let Ok(permit) = pending.clone().acquire_owned().await {
+        let Some(message) = stream.next().await else {
+            break;
+        };
+        let (bytes, text) = match message {
+            Ok(Message::Text(text)) => (text.into(), true),
+            Ok(Message::Binary(bytes)) => (bytes, false),
+            Ok(Message::Close(_)) => break,
+            Ok(_) => continue,
+            Err(err) => {
+                warn(&err);
+                break;
+            }
+        };
+        let frame: RequestFrame<Request> = match codec.decode(&bytes) {
+            Ok(frame) => frame,
+            Err(err) => {
+                warn(&err);
+                continue;
+            }
+        };
+        let endpoint = router.endpoint(timeout);
+        let frame_sender = frame_sender.clone();
+        let codec = codec.clone();
+        tokio::spawn(async move {
+            // released once the response was handed to the writer
+            let _permit = permit;
+            let result = endpoint
+                .handle_request(frame.request)
+                .await
+                .map_err(|err| err.to_string());
+            let response = ResponseFrame {
+                id: frame.id,
+                result,
+            };
+            match codec.encode(&response) {
+                Ok(encoded) => {
+                    let message = match String::from_utf8(encoded) {
+                        Ok(encoded) if text => Message::text(encoded),
+                        Ok(encoded) => Message::binary(encoded),
+                        Err(err) => Message::binary(err.into_bytes()),
+                    };
+                    let _ = frame_sender.send(message).await;
+                }
+                Err(err) => warn(&err),
+            }
+        });
+    }
+    drop(frame_sender);
+    let _ = writer.await;
+}
+
+impl<Request, Response> Router<Request, Response>
+where
+    Request: Serialize + DeserializeOwned + Send + 'static + Clone,
+    Response: Serialize + DeserializeOwned + Send + 'static + Clone,
+{
+    /// Accepts WebSocket connections on `listener` and serves every
+    /// connection with this router.
+    ///
+    /// # Arguments
+    ///
+    /// - `listener`: A bound [TcpListener].
+    /// - `timeout`: An optional [Duration] applied to every request. If
+    ///   `None`, the router's default timeout is applied.
+    ///
+    /// # Returns
+    ///
+    /// Returns the handle of the spawned accept loop.
+    ///
+    /// # Behavior
+    ///
+    /// After a failure to accept a connection, accepting is retried with an
+    /// exponential backoff of up to a second.
+    pub fn tokio_spawn_websocket(
+        &self,
+        listener: TcpListener,
+        timeout: Option<Duration>,
+    ) -> tokio::task::JoinHandle<()> {
+        self.tokio_spawn_websocket_with_codec(listener, timeout, JsonCodec)
+    }
+
+    /// Accepts WebSocket connections on `listener` like
+    /// [Router::tokio_spawn_websocket], encoding frames with `codec` instead
+    /// of JSON.
+    ///
+    /// # Arguments
+    ///
+    /// - `listener`: A bound [TcpListener].
+    /// - `timeout`: An optional [Duration] applied to every request. If
+    ///   `None`, the router's default timeout is applied.
+    /// - `codec`: The [Codec] the clients encode their frames with.
+    ///
+    /// # Returns
+    ///
+    /// Returns the handle of the spawned accept loop.
+    pub fn tokio_spawn_websocket_with_codec(
+        &self,
+        listener: TcpListener,
+        timeout: Option<Duration>,
+        codec: impl Codec,
+    ) -> tokio::task::JoinHandle<()> {
+        let router = self.clone();
+        tokio::spawn(async move {
+            let mut retry_delay = MIN_ACCEPT_DELAY;
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        retry_delay = MIN_ACCEPT_DELAY;
+                        tokio::spawn(serve_connection(
+                            router.clone(),
+                            stream,
+                            timeout,
+                            codec.clone(),
+                        ));
+                    }
+                    // errors like running out of file descriptors persist
+                    // for a while, accepting right away would spin
+                    Err(err) => {
+                        warn(&err);
+                        tokio::time::sleep(retry_delay).await;
+                        retry_delay = (retry_delay * 2).min(MAX_ACCEPT_DELAY);
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RequestFrame, ResponseFrame, MAX_PENDING_RESPONSES};
+    use crate::channel::{Receiver, Sender};
+    use crate::router::Router;
+    use futures::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{connect_async, tungstenite::Message};
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_websocket_round_trip() {
+        async fn worker(receiver: Receiver<(Uuid, String)>, sender: Sender<(Uuid, String)>) {
+            while let Ok((uuid, request)) = receiver.recv().await {
+                sender.send((uuid, request.to_uppercase())).await.unwrap();
+            }
+        }
+        let router: Router<String, String> = Router::default();
+        router.tokio_spawn();
+        router.tokio_spawn_workers(2, worker);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        router.tokio_spawn_websocket(listener, None);
+
+        let (mut client, _) = connect_async(format!("ws://{address}")).await.unwrap();
+        let request = RequestFrame {
+            id: Uuid::new_v4(),
+            request: "hello".to_string(),
+        };
+        let encoded = serde_json::to_string(&request).unwrap();
+        client.send(Message::text(encoded)).await.unwrap();
+        let message = client.next().await.unwrap().unwrap();
+        let response: ResponseFrame<String> =
+            serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(response.id, request.id);
+        assert_eq!(response.result, Ok("HELLO".to_string()));
+
+        // binary messages are answered in binary
+        let encoded = serde_json::to_vec(&request).unwrap();
+        client.send(Message::binary(encoded)).await.unwrap();
+        let message = client.next().await.unwrap().unwrap();
+        assert!(message.is_binary());
+        let response: ResponseFrame<String> = serde_json::from_slice(&message.into_data()).unwrap();
+        assert_eq!(response.result, Ok("HELLO".to_string()));
+
+        // requests beyond the pending limit are answered once earlier
+        // responses were written
+        let count = 2 * MAX_PENDING_RESPONSES;
+        for _ in 0..count {
+            let encoded = serde_json::to_string(&request).unwrap();
+            client.send(Message::text(encoded)).await.unwrap();
+        }
+        for _ in 0..count {
+            let message = client.next().await.unwrap().unwrap();
+            let response: ResponseFrame<String> =
+                serde_json::from_str(message.to_text().unwrap()).unwrap();
+            assert_eq!(response.result, Ok("HELLO".to_string()));
+        }
+    }
+}
@@ -0,0 +1,622 @@
+//! # Worker Module
+//!
+//! This module provides the [Worker] trait for writing request handlers
+//! without hand-rolling the channel loop.
+//!
+//! ## Overview
+//!
+//! A raw worker function passed to
+//! [Router::tokio_spawn_workers](crate::router::Router::tokio_spawn_workers)
+//! has to receive `(Uuid, Request)` tuples and echo the UUID back with the
+//! response, forgetting to do so silently breaks routing. Implementing
+//! [Worker] instead only requires mapping a request to a response, while
+//! [Router::spawn_worker_instances](crate::router::Router::spawn_worker_instances)
+//! owns the receive/send loop and the UUID plumbing. Managed workers also
+//! isolate panics: a request whose handler panics fails right away with
+//! [EndpointError::WorkerPanicked](crate::endpoint::EndpointError::WorkerPanicked),
+//! and the worker keeps handling the following requests.
+//!
+//! Workers running on another executor, on dedicated threads or in another
+//! crate attach to the router with
+//! [Router::attach_worker](crate::router::Router::attach_worker) instead,
+//! receiving the router's channels in a [WorkerAttachment].
+use std::{
+    fmt,
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::channel::{Receiver, Sender};
+use futures::FutureExt;
+use tokio::{sync::Semaphore, task::JoinSet};
+use uuid::Uuid;
+
+use crate::router::Router;
+
+/// A request handler managed by the [Router](crate::router::Router).
+///
+/// The trait can be implemented with an `async fn`:
+///
+/// ```rust
+/// use s2a4c::worker::Worker;
+///
+/// struct Echo;
+///
+/// impl Worker<String, String> for Echo {
+///     async fn handle(&self, request: String) -> String {
+///         request
+///     }
+/// }
+/// ```
+///
+/// # Type Parameters
+/// - `Request`: the type of the handled requests
+/// - `Response`: the type of the produced responses
+pub trait Worker<Request, Response> {
+    /// Handles a single request and produces its response.
+    fn handle(&self, request: Request) -> impl Future<Output = Response> + Send;
+}
+
+/// Keeps a worker counted in the router's worker count for as long as the
+/// worker task is alive, including when the task panics or is aborted.
+pub(crate) struct WorkerGuard(Arc<AtomicUsize>);
+
+impl WorkerGuard {
+    pub(crate) fn new(workers: Arc<AtomicUsize>) -> Self {
+        workers.fetch_add(1, Ordering::SeqCst);
+        Self(workers)
+    }
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The channel a worker receives requests from and the channel it sends
+/// responses to.
+type Channels<Request, Response> = (Receiver<(Uuid, Request)>, Sender<(Uuid, Response)>);
+
+/// The channels of a worker attached to a [Router] with
+/// [Router::attach_worker], for running the worker on any executor or thread.
+///
+/// The worker receives `(Uuid, Request)` tuples from [WorkerAttachment::receiver]
+/// and sends the responses back along with the UUID of their request to
+/// [WorkerAttachment::sender]. It is counted in the router's worker count
+/// until the attachment is dropped, so the attachment should be kept for as
+/// long as the worker runs.
+pub struct WorkerAttachment<Request, Response> {
+    /// the channel the worker receives requests from
+    receiver: Receiver<(Uuid, Request)>,
+    /// the channel the worker sends responses to
+    sender: Sender<(Uuid, Response)>,
+    /// keeps the worker counted
+    _guard: WorkerGuard,
+}
+
+impl<Request, Response> WorkerAttachment<Request, Response> {
+    /// Creates a new `WorkerAttachment` of the given channels, counted in
+    /// `workers`.
+    pub(crate) fn new(
+        receiver: Receiver<(Uuid, Request)>,
+        sender: Sender<(Uuid, Response)>,
+        workers: Arc<AtomicUsize>,
+    ) -> Self {
+        Self {
+            receiver,
+            sender,
+            _guard: WorkerGuard::new(workers),
+        }
+    }
+    /// Returns the channel the worker receives requests from.
+    pub fn receiver(&self) -> &Receiver<(Uuid, Request)> {
+        &self.receiver
+    }
+    /// Returns the channel the worker sends responses to.
+    pub fn sender(&self) -> &Sender<(Uuid, Response)> {
+        &self.sender
+    }
+    /// Returns clones of both channels, for workers taking them by value.
+    pub fn channels(&self) -> Channels<Request, Response> {
+        (self.receiver.clone(), self.sender.clone())
+    }
+}
+
+impl<Request, Response> fmt::Debug for WorkerAttachment<Request, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkerAttachment")
+            .field("receiver", &self.receiver)
+            .field("sender", &self.sender)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A [Worker] running an asynchronous handler function, see
+/// [Router::spawn_workers_fn](crate::router::Router::spawn_workers_fn).
+pub(crate) struct FnWorker<F>(pub(crate) Arc<F>);
+
+impl<F, Fut, Request, Response> Worker<Request, Response> for FnWorker<F>
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send,
+{
+    fn handle(&self, request: Request) -> impl Future<Output = Response> + Send {
+        (self.0)(request)
+    }
+}
+
+/// A [Worker] running a synchronous, blocking handler on tokio's blocking
+/// thread pool, see
+/// [Router::spawn_blocking_workers](crate::router::Router::spawn_blocking_workers).
+pub(crate) struct BlockingWorker<F>(pub(crate) Arc<F>);
+
+impl<F, Request, Response> Worker<Request, Response> for BlockingWorker<F>
+where
+    F: Fn(Request) -> Response + Send + Sync + 'static,
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    async fn handle(&self, request: Request) -> Response {
+        let handler = self.0.clone();
+        match tokio::task::spawn_blocking(move || handler(request)).await {
+            Ok(response) => response,
+            // the handler panicked, resume the panic in the worker which
+            // fails the request
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+}
+
+/// A [Worker] running a synchronous, CPU-bound handler on a rayon thread
+/// pool, see
+/// [Router::spawn_rayon_workers](crate::router::Router::spawn_rayon_workers).
+#[cfg(feature = "rayon")]
+pub(crate) struct RayonWorker<F> {
+    /// the pool the handler runs on
+    pub(crate) pool: Arc<rayon::ThreadPool>,
+    /// limits the jobs queued on the pool, shared by all workers of the pool
+    pub(crate) jobs: Arc<Semaphore>,
+    /// the handler mapping a request to its response
+    pub(crate) handler: Arc<F>,
+}
+
+#[cfg(feature = "rayon")]
+impl<F, Request, Response> Worker<Request, Response> for RayonWorker<F>
+where
+    F: Fn(Request) -> Response + Send + Sync + 'static,
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    async fn handle(&self, request: Request) -> Response {
+        // the permit is released once the job finished, even if the request
+        // was abandoned while its job was running
+        let permit = self
+            .jobs
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the job semaphore is never closed");
+        let handler = self.handler.clone();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.pool.spawn(move || {
+            // a panicking job would abort the process without a panic
+            // handler, so the panic is resumed in the worker instead
+            let handled = std::panic::catch_unwind(AssertUnwindSafe(|| handler(request)));
+            drop(permit);
+            let _ = sender.send(handled);
+        });
+        match receiver.await {
+            Ok(Ok(response)) => response,
+            Ok(Err(panic)) => std::panic::resume_unwind(panic),
+            Err(_) => panic!("the rayon job was dropped without running"),
+        }
+    }
+}
+
+/// Asynchronous crate-private function that drives a [Worker] instance.
+///
+/// # Arguments
+///
+/// - `worker`: The worker instance handling the requests.
+/// - `router`: The router the requests were submitted to.
+/// - `receiver`: The router's request receiver.
+/// - `sender`: The router's response sender.
+///
+/// # Behavior
+///
+/// The function receives requests until the request channel is closed,
+/// handles each of them with the `worker`, inside the request's
+/// [TraceContext](crate::context::TraceContext), and sends the response back
+/// tagged with the request's UUID. Requests cancelled by their
+/// [Endpoint](crate::endpoint::Endpoint) are abandoned, whether they are
+/// cancelled while queued or while being handled. Requests whose handling
+/// panics fail with
+/// [EndpointError::WorkerPanicked](crate::endpoint::EndpointError::WorkerPanicked).
+/// Health check pings are answered while no request is waiting. It returns
+/// once the request or response channel is closed.
+pub(crate) async fn worker_loop<W, Request, Response>(
+    worker: W,
+    router: Router<Request, Response>,
+    receiver: Receiver<(Uuid, Request)>,
+    sender: Sender<(Uuid, Response)>,
+) where
+    W: Worker<Request, Response>,
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    let health = router.health_probes().clone();
+    let probes = health.probes();
+    loop {
+        // pings are only answered while no request is waiting, so they wait
+        // for the backlog of the worker
+        let (uuid, request) = tokio::select! {
+            biased;
+            received = receiver.recv() => match received {
+                Ok(received) => received,
+                Err(_) => break,
+            },
+            Ok(probe) = probes.recv() => {
+                health.answer(probe).await;
+                continue;
+            }
+        };
+        let Some(cancellation) = router.cancellation_token(&uuid) else {
+            continue;
+        };
+        let context = router.trace_context(&uuid).unwrap_or_default();
+        let handled = AssertUnwindSafe(context.instrument(worker.handle(request))).catch_unwind();
+        let response = tokio::select! {
+            _ = cancellation.cancelled() => {
+                router.deregister(&uuid);
+                continue;
+            }
+            handled = handled => match handled {
+                Ok(response) => response,
+                Err(_) => {
+                    router.fail_panicked(&uuid);
+                    continue;
+                }
+            },
+        };
+        if sender.send((uuid, response)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Asynchronous crate-private function that drives a worker handling up to
+/// `concurrency` requests concurrently, see
+/// [Router::spawn_workers_concurrent](crate::router::Router::spawn_workers_concurrent).
+///
+/// # Arguments
+///
+/// - `handler`: The function mapping a request to its response.
+/// - `concurrency`: The maximum number of requests handled concurrently.
+/// - `router`: The router the requests were submitted to.
+/// - `receiver`: The router's request receiver.
+/// - `sender`: The router's response sender.
+///
+/// # Behavior
+///
+/// Like [worker_loop], except that every request is handled by its own task
+/// guarded by a semaphore, so the worker only receives a request once fewer
+/// than `concurrency` requests are being handled. Once the request channel
+/// is closed, the function waits for the requests being handled. Dropping
+/// the function's future aborts them.
+pub(crate) async fn concurrent_worker_loop<F, Fut, Request, Response>(
+    handler: Arc<F>,
+    concurrency: usize,
+    router: Router<Request, Response>,
+    receiver: Receiver<(Uuid, Request)>,
+    sender: Sender<(Uuid, Response)>,
+) where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+    Request: Send + 'static + Clone,
+    Response: Send + 'static + Clone,
+{
+    let permits = Arc::new(Semaphore::new(concurrency));
+    let mut tasks = JoinSet::new();
+    let health = router.health_probes().clone();
+    let probes = health.probes();
+    loop {
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        while tasks.try_join_next().is_some() {}
+        // pings are only answered while no request is waiting, so they wait
+        // for the backlog of the worker
+        let (uuid, request) = tokio::select! {
+            biased;
+            received = receiver.recv() => match received {
+                Ok(received) => received,
+                Err(_) => break,
+            },
+            Ok(probe) = probes.recv() => {
+                health.answer(probe).await;
+                continue;
+            }
+        };
+        let Some(cancellation) = router.cancellation_token(&uuid) else {
+            continue;
+        };
+        let context = router.trace_context(&uuid).unwrap_or_default();
+        let handler = handler.clone();
+        let router = router.clone();
+        let sender = sender.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            let handled = AssertUnwindSafe(context.instrument(handler(request))).catch_unwind();
+            let response = tokio::select! {
+                _ = cancellation.cancelled() => {
+                    router.deregister(&uuid);
+                    return;
+                }
+                handled = handled => match handled {
+                    Ok(response) => response,
+                    Err(_) => {
+                        router.fail_panicked(&uuid);
+                        return;
+                    }
+                },
+            };
+            let _ = sender.send((uuid, response)).await;
+        });
+    }
+    while tasks.join_next().await.is_some() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Worker;
+    use crate::{endpoint::EndpointError, router::Router};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    struct Counter {
+        handled: Arc<AtomicUsize>,
+    }
+
+    impl Worker<u32, u32> for Counter {
+        async fn handle(&self, request: u32) -> u32 {
+            self.handled.fetch_add(1, Ordering::SeqCst);
+            request * 2
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawn_worker_instances() {
+        let router: Router<u32, u32> = Router::default();
+        let handled = Arc::new(AtomicUsize::new(0));
+        let factory_handled = handled.clone();
+        let handles = router.spawn_worker_instances(3, move || Counter {
+            handled: factory_handled.clone(),
+        });
+        assert_eq!(handles.len(), 3);
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(None);
+        for request in 0..10 {
+            assert_eq!(endpoint.handle_request(request).await, Ok(request * 2));
+        }
+        assert_eq!(handled.load(Ordering::SeqCst), 10);
+    }
+
+    struct Slow {
+        finished: Arc<AtomicUsize>,
+    }
+
+    impl Worker<u32, u32> for Slow {
+        async fn handle(&self, request: u32) -> u32 {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            self.finished.fetch_add(1, Ordering::SeqCst);
+            request
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_request_is_abandoned() {
+        let router: Router<u32, u32> = Router::default();
+        let finished = Arc::new(AtomicUsize::new(0));
+        let factory_finished = finished.clone();
+        router.spawn_worker_instances(1, move || Slow {
+            finished: factory_finished.clone(),
+        });
+        router.tokio_spawn();
+
+        let response = router
+            .endpoint(Some(Duration::from_millis(50)))
+            .handle_request(1)
+            .await;
+        assert!(matches!(response, Err(EndpointError::Timeout(_))));
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+        assert_eq!(router.stats().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_blocking_workers() {
+        let router: Router<u64, u64> = Router::default();
+        router.spawn_blocking_workers(2, |millis| {
+            std::thread::sleep(Duration::from_millis(millis));
+            millis
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.shared_endpoint(Some(Duration::from_millis(500)));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let endpoint = endpoint.clone();
+                tokio::spawn(async move { endpoint.handle_request(100).await })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.await.unwrap(), Ok(100));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[tokio::test]
+    async fn test_spawn_rayon_workers() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let router: Router<u64, u64> = Router::default();
+        assert_eq!(
+            router
+                .spawn_rayon_workers(Arc::new(pool), |n| match n {
+                    0 => panic!("zero"),
+                    n => (1..=n).sum(),
+                })
+                .len(),
+            2
+        );
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(Some(Duration::from_millis(500)));
+        let responses =
+            futures::future::join_all((1..=8).map(|n| endpoint.handle_request(n))).await;
+        assert_eq!(
+            responses,
+            (1..=8).map(|n| Ok(n * (n + 1) / 2)).collect::<Vec<_>>()
+        );
+        assert!(matches!(
+            endpoint.handle_request(0).await,
+            Err(EndpointError::WorkerPanicked)
+        ));
+        assert_eq!(endpoint.handle_request(3).await, Ok(6));
+    }
+
+    #[tokio::test]
+    async fn test_dropped_request_is_abandoned() {
+        let router: Router<u32, u32> = Router::default();
+        let finished = Arc::new(AtomicUsize::new(0));
+        let factory_finished = finished.clone();
+        router.spawn_worker_instances(1, move || Slow {
+            finished: factory_finished.clone(),
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.shared_endpoint(None);
+        let request = tokio::spawn(async move { endpoint.handle_request(1).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(router.stats().in_flight, 1);
+        request.abort();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(router.stats().in_flight, 0);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_workers_concurrent() {
+        let router: Router<u64, u64> = Router::default();
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (handler_running, handler_peak) = (running.clone(), peak.clone());
+        router.spawn_workers_concurrent(2, 3, move |millis| {
+            let (running, peak) = (handler_running.clone(), handler_peak.clone());
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                millis
+            }
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.shared_endpoint(None);
+        let start = tokio::time::Instant::now();
+        let handles: Vec<_> = (0..12)
+            .map(|_| {
+                let endpoint = endpoint.clone();
+                tokio::spawn(async move { endpoint.handle_request(100).await })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.await.unwrap(), Ok(100));
+        }
+        // 2 workers handling 3 requests each at a time take two rounds
+        assert!(start.elapsed() < Duration::from_millis(300));
+        assert_eq!(peak.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_workers_fn() {
+        let router: Router<u32, String> = Router::default();
+        let handles = router.spawn_workers_fn(2, |request| async move { request.to_string() });
+        assert_eq!(handles.len(), 2);
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(None);
+        for request in 0..5 {
+            assert_eq!(
+                endpoint.handle_request(request).await,
+                Ok(request.to_string())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_worker_panic_is_isolated() {
+        let router: Router<u32, u32> = Router::default();
+        router.spawn_workers_fn(1, |request| async move {
+            assert_ne!(request, 0, "cannot handle zero");
+            request
+        });
+        router.tokio_spawn();
+
+        let endpoint = router.endpoint(None);
+        assert_eq!(
+            endpoint.handle_request(0).await,
+            Err(EndpointError::WorkerPanicked)
+        );
+        // the worker survived the panic
+        assert_eq!(endpoint.handle_request(1).await, Ok(1));
+        assert_eq!(router.stats().workers, 1);
+        assert_eq!(router.stats().in_flight, 0);
+    }
+    #[tokio::test]
+    async fn test_attach_worker() {
+        let router: Router<u32, u32> = Router::default();
+        router.tokio_spawn();
+        let attachment = router.attach_worker();
+        assert_eq!(router.stats().workers, 1);
+
+        // the worker runs on a dedicated thread, until it handled a zero
+        let worker = std::thread::spawn(move || {
+            use futures::executor::block_on;
+
+            while let Ok((uuid, request)) = block_on(attachment.receiver().recv()) {
+                block_on(attachment.sender().send((uuid, request * 2))).unwrap();
+                if request == 0 {
+                    break;
+                }
+            }
+        });
+        let endpoint = router.endpoint(None);
+        assert_eq!(endpoint.handle_request(21).await, Ok(42));
+        assert_eq!(endpoint.handle_request(0).await, Ok(0));
+        worker.join().unwrap();
+        // dropping the attachment detached the worker
+        assert_eq!(router.stats().workers, 0);
+        assert_eq!(
+            endpoint.handle_request(1).await,
+            Err(EndpointError::NoWorkers)
+        );
+    }
+}